@@ -0,0 +1,416 @@
+//! Every asset is loaded through here: names are resolved against one asset root, so the game,
+//! the editor and headless tests find the same files, and what was loaded is cached and shared
+//! through `Handle`s.
+//!
+//! `load("weapon.yaml")` returns the cached asset, `reload` replaces it (handles that are already
+//! out keep the old one) and `collect` drops what nothing but the cache holds. The game collects
+//! after hot reloading, so an asset loaded once stays cached until then.
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::BufWriter,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    audio::SoundBank,
+    background::BackgroundBuilder,
+    boss::BossDef,
+    drawable::{PngTexture, SpriteSheet},
+    font::BitmapFont,
+    migrate::{self, Versioned},
+    palette::Palette,
+    particle::ExplosionBuilder,
+    pattern::{BulletPattern, PatternPreview},
+    pickup::PickupBank,
+    rank::RankCurve,
+    settings::DisplaySettings,
+    sfx::SfxParams,
+    tilemap::Tilemap,
+    weapon::WeaponDef,
+    Rc,
+};
+
+/// Something that can be loaded from a file
+pub trait Asset: Any + Sized {
+    fn load(path: &Path) -> Result<Self>;
+}
+
+/// `yaml` types are plain YAML, the others already have a `load(path)`
+macro_rules! impl_asset {
+    (yaml $($id:ty),*) => {
+        $(impl Asset for $id {
+            fn load(path: &Path) -> Result<Self> {
+                load_yaml(path)
+            }
+        })*
+    };
+    ($($id:ty),*) => {
+        $(impl Asset for $id {
+            fn load(path: &Path) -> Result<Self> {
+                <$id>::load(path)
+            }
+        })*
+    };
+}
+
+impl_asset!(
+    SpriteSheet,
+    PngTexture,
+    BitmapFont,
+    Palette,
+    BulletPattern,
+    Tilemap
+);
+impl_asset!(
+    yaml BackgroundBuilder,
+    BossDef,
+    DisplaySettings,
+    ExplosionBuilder,
+    PatternPreview,
+    PickupBank,
+    RankCurve,
+    SfxParams,
+    SoundBank,
+    WeaponDef
+);
+
+/// Upgrades documents saved with an older format, see `migrate`
+pub fn load_yaml<T: Versioned + DeserializeOwned>(path: &Path) -> Result<T> {
+    let data = fs::read(path).with_context(|| format!("Could not open {}", path.display()))?;
+    migrate::from_slice(&data).with_context(|| format!("could not parse {}", path.display()))
+}
+
+pub fn save_yaml<T: Versioned + Serialize>(path: &Path, value: &T) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Could not save {}", path.display()))?;
+    migrate::to_writer(BufWriter::new(file), value)
+}
+
+/// A loaded asset, shared with the cache and every other handle to it.
+/// Sprites are made from a `Handle<SpriteSheet>`, see `rc`.
+pub struct Handle<T> {
+    path: Rc<PathBuf>,
+    asset: Rc<T>,
+}
+
+impl<T> Handle<T> {
+    pub fn new(path: PathBuf, asset: T) -> Self {
+        Self {
+            path: Rc::new(path),
+            asset: Rc::new(asset),
+        }
+    }
+
+    /// Where it was loaded from, including the asset root
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn rc(&self) -> Rc<T> {
+        self.asset.clone()
+    }
+
+    /// How many handles there are, counting the cache's
+    pub fn ref_count(&self) -> usize {
+        Rc::strong_count(&self.asset)
+    }
+
+    /// A copy to change, e.g. to tweak a loaded definition
+    pub fn cloned(&self) -> T
+    where
+        T: Clone,
+    {
+        T::clone(&self.asset)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            asset: self.asset.clone(),
+        }
+    }
+}
+
+impl<T> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.asset
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({})", self.path.display())
+    }
+}
+
+/// A handle without its type
+type Cached = (Rc<PathBuf>, Rc<dyn Any>);
+
+/// The asset root and what was loaded from it
+pub struct Assets {
+    root: PathBuf,
+    cache: HashMap<(TypeId, PathBuf), Cached>,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self::new(".")
+    }
+}
+
+impl fmt::Debug for Assets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Assets")
+            .field("root", &self.root)
+            .field("cached", &self.cache.len())
+            .finish()
+    }
+}
+
+impl Assets {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// `name` within the asset root. Absolute paths are left alone.
+    pub fn path(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.root.join(name)
+    }
+
+    pub fn get<T: Asset>(&self, path: &Path) -> Option<Handle<T>> {
+        let (path, asset) = self.cache.get(&(TypeId::of::<T>(), path.to_path_buf()))?;
+        let asset = asset.clone().downcast::<T>().ok()?;
+        Some(Handle {
+            path: path.clone(),
+            asset,
+        })
+    }
+
+    pub fn insert<T: Asset>(&mut self, handle: &Handle<T>) {
+        self.cache.insert(
+            (TypeId::of::<T>(), handle.path.to_path_buf()),
+            (handle.path.clone(), handle.asset.clone()),
+        );
+    }
+
+    /// Drop whatever was loaded from `path`, whatever it was loaded as
+    pub fn forget(&mut self, path: &Path) {
+        self.cache.retain(|(_, p), _| p != path);
+    }
+
+    /// Drop what only the cache holds, returns how many assets were dropped
+    pub fn collect(&mut self) -> usize {
+        let before = self.cache.len();
+        self.cache
+            .retain(|_, (_, asset)| Rc::strong_count(asset) > 1);
+        before - self.cache.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+}
+
+thread_local! {
+    static ASSETS: RefCell<Assets> = RefCell::new(Assets::default());
+}
+
+/// Resolve names against `root` from now on, and forget everything loaded from the old one
+pub fn set_root(root: impl Into<PathBuf>) {
+    ASSETS.with(|a| *a.borrow_mut() = Assets::new(root));
+}
+
+pub fn root() -> PathBuf {
+    ASSETS.with(|a| a.borrow().root().to_path_buf())
+}
+
+/// `name` within the asset root
+pub fn path(name: impl AsRef<Path>) -> PathBuf {
+    ASSETS.with(|a| a.borrow().path(name))
+}
+
+pub fn exists(name: impl AsRef<Path>) -> bool {
+    path(name).exists()
+}
+
+/// The cached `name`, loaded first if it is not cached
+pub fn load<T: Asset>(name: impl AsRef<Path>) -> Result<Handle<T>> {
+    let path = path(name);
+    if let Some(handle) = ASSETS.with(|a| a.borrow().get::<T>(&path)) {
+        return Ok(handle);
+    }
+    insert_loaded(path)
+}
+
+/// Load `name` again, even when it is cached
+pub fn reload<T: Asset>(name: impl AsRef<Path>) -> Result<Handle<T>> {
+    insert_loaded(path(name))
+}
+
+/// Not borrowed while loading, assets may load other assets
+fn insert_loaded<T: Asset>(path: PathBuf) -> Result<Handle<T>> {
+    let asset = T::load(&path)?;
+    let handle = Handle::new(path, asset);
+    ASSETS.with(|a| a.borrow_mut().insert(&handle));
+    Ok(handle)
+}
+
+/// Load `name` next time it is asked for
+pub fn forget(name: impl AsRef<Path>) {
+    let path = path(name);
+    ASSETS.with(|a| a.borrow_mut().forget(&path));
+}
+
+/// See `Assets::collect`
+pub fn collect() -> usize {
+    ASSETS.with(|a| a.borrow_mut().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        text: String,
+    }
+
+    impl Versioned for Note {}
+    impl_asset!(yaml Note);
+
+    /// A fresh asset root for this test, also made the thread's root
+    fn root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cowshmup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("notes")).unwrap();
+        set_root(&dir);
+        dir
+    }
+
+    fn write(name: &str, text: &str) {
+        save_yaml(&path(name), &Note { text: text.into() }).unwrap();
+    }
+
+    #[test]
+    fn names_are_resolved_against_the_root() {
+        let dir = root("assets-paths");
+        assert_eq!(super::root(), dir);
+        assert_eq!(path("notes/a.yaml"), dir.join("notes/a.yaml"));
+        assert!(!exists("notes/a.yaml"));
+        write("notes/a.yaml", "a");
+        assert!(exists("notes/a.yaml"));
+
+        let handle = load::<Note>("notes/a.yaml").unwrap();
+        assert_eq!(handle.path(), dir.join("notes/a.yaml"));
+        assert_eq!(handle.text, "a");
+        // Absolute paths are left alone, and share the cache with the relative name
+        let absolute = load::<Note>(dir.join("notes/a.yaml")).unwrap();
+        assert!(Rc::ptr_eq(&handle.rc(), &absolute.rc()));
+
+        let err = load::<Note>("notes/missing.yaml").unwrap_err();
+        assert!(format!("{:#}", err).contains("missing.yaml"), "{:#}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loads_are_cached() {
+        let dir = root("assets-cache");
+        write("a.yaml", "a");
+        let first = load::<Note>("a.yaml").unwrap();
+        // Changing the file does nothing until it is reloaded
+        write("a.yaml", "b");
+        let second = load::<Note>("a.yaml").unwrap();
+        assert!(Rc::ptr_eq(&first.rc(), &second.rc()));
+        assert_eq!(second.text, "a");
+        // The cache's handle and these two
+        assert_eq!(first.ref_count(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reloading_leaves_old_handles_alone() {
+        let dir = root("assets-reload");
+        write("a.yaml", "a");
+        let old = load::<Note>("a.yaml").unwrap();
+        write("a.yaml", "b");
+        let new = reload::<Note>("a.yaml").unwrap();
+        assert_eq!(old.text, "a");
+        assert_eq!(new.text, "b");
+        assert_eq!(load::<Note>("a.yaml").unwrap().text, "b");
+        // The cache let go of the old one
+        assert_eq!(old.ref_count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn forgotten_assets_are_loaded_again() {
+        let dir = root("assets-forget");
+        write("a.yaml", "a");
+        let old = load::<Note>("a.yaml").unwrap();
+        write("a.yaml", "b");
+        forget("a.yaml");
+        assert_eq!(old.ref_count(), 1);
+        assert_eq!(load::<Note>("a.yaml").unwrap().text, "b");
+        assert_eq!(old.text, "a");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn collect_drops_what_only_the_cache_holds() {
+        let dir = root("assets-collect");
+        write("a.yaml", "a");
+        write("b.yaml", "b");
+        let kept = load::<Note>("a.yaml").unwrap();
+        load::<Note>("b.yaml").unwrap();
+        assert_eq!(ASSETS.with(|a| a.borrow().len()), 2);
+
+        assert_eq!(collect(), 1);
+        assert_eq!(ASSETS.with(|a| a.borrow().len()), 1);
+        assert_eq!(kept.ref_count(), 2);
+        assert!(Rc::ptr_eq(
+            &kept.rc(),
+            &load::<Note>("a.yaml").unwrap().rc()
+        ));
+
+        drop(kept);
+        assert_eq!(collect(), 1);
+        assert!(ASSETS.with(|a| a.borrow().is_empty()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn the_same_file_is_cached_per_type() {
+        let mut assets = Assets::new("root");
+        let note = Handle::new(assets.path("a.yaml"), Note { text: "a".into() });
+        assets.insert(&note);
+        assert!(assets.get::<Note>(Path::new("root/a.yaml")).is_some());
+        assert!(assets.get::<RankCurve>(Path::new("root/a.yaml")).is_none());
+        assert!(assets.get::<Note>(Path::new("a.yaml")).is_none());
+        assets.forget(Path::new("root/a.yaml"));
+        assert!(assets.is_empty());
+    }
+}
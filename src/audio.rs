@@ -0,0 +1,519 @@
+//! Sound effects triggered by name from gameplay, see `World::play_sound`.
+//!
+//! `Audio` decides which sounds actually play: every sound has a cooldown, a voice limit and a
+//! priority, and there are only so many voices overall. What is left is handed to an
+//! `AudioBackend`, `MacroquadBackend` in the game and `NullBackend` when there is no audio device.
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+};
+
+use anyhow::Result;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    migrate::Versioned,
+    sfx::{evict, play_cached, sound_key, stop_key, SfxParams, SoundKey, SAMPLE_RATE},
+    CenterPt,
+};
+
+pub const DEFAULT_SOUNDS_FILE: &str = "sounds.yaml";
+
+/// Ask for a sound by its name in the `SoundBank`. With a position, the sound pans to where it
+/// is on screen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoundEvent {
+    pub name: String,
+    pub position: Option<CenterPt>,
+}
+
+impl SoundEvent {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            position: None,
+        }
+    }
+
+    pub fn at(name: &str, position: CenterPt) -> Self {
+        Self {
+            name: String::from(name),
+            position: Some(position),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoundDef {
+    pub sfx: SfxParams,
+    /// Multiplied with the volume of the sfx
+    pub volume: f32,
+    /// A voice can only be stolen by a sound with at least the same priority
+    pub priority: u8,
+    /// Seconds after playing during which the sound is ignored
+    pub cooldown: f32,
+    /// Copies of this sound that may play at once, the oldest is stopped to make room
+    pub max_voices: usize,
+}
+
+impl Default for SoundDef {
+    fn default() -> Self {
+        Self {
+            sfx: SfxParams::default(),
+            volume: 1.,
+            priority: 0,
+            cooldown: 0.05,
+            max_voices: 2,
+        }
+    }
+}
+
+/// Every sound gameplay can ask for, by name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoundBank {
+    pub sounds: BTreeMap<String, SoundDef>,
+}
+
+impl Versioned for SoundBank {}
+
+pub type VoiceId = u64;
+
+/// Whatever makes the noise. Samples are mono at `SAMPLE_RATE`, `pan` goes from -1 (left) to 1
+/// (right).
+pub trait AudioBackend {
+    fn play(&mut self, voice: VoiceId, name: &str, samples: &[f32], volume: f32, pan: f32);
+    fn stop(&mut self, voice: VoiceId);
+    /// False once the voice was stopped, also when stopping another voice stopped it
+    fn is_playing(&self, _voice: VoiceId) -> bool {
+        true
+    }
+    /// The sound named `name` changed or is gone, forget what was loaded for it
+    fn unload(&mut self, _name: &str) {}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendCall {
+    Play {
+        voice: VoiceId,
+        name: String,
+        volume: f32,
+        pan: f32,
+    },
+    Stop(VoiceId),
+    Unload(String),
+}
+
+/// Plays nothing, for headless runs. Remembers what it was asked to do, so it can be checked.
+#[derive(Debug, Clone, Default)]
+pub struct NullBackend {
+    pub calls: Vec<BackendCall>,
+}
+
+impl AudioBackend for NullBackend {
+    fn play(&mut self, voice: VoiceId, name: &str, _samples: &[f32], volume: f32, pan: f32) {
+        self.calls.push(BackendCall::Play {
+            voice,
+            name: String::from(name),
+            volume,
+            pan,
+        });
+    }
+
+    fn stop(&mut self, voice: VoiceId) {
+        self.calls.push(BackendCall::Stop(voice));
+    }
+
+    fn unload(&mut self, name: &str) {
+        self.calls.push(BackendCall::Unload(String::from(name)));
+    }
+}
+
+/// Pan is rounded to this many steps either side, every step is another sound to load
+const PAN_STEPS: f32 = 4.;
+
+/// Macroquad has no panning, so sounds are loaded as stereo WAVs with the pan baked in.
+///
+/// Macroquad can only stop all copies of a sound at once, so stopping a voice stops every voice
+/// of the same sound at the same pan. They are forgotten too, see `AudioBackend::is_playing`.
+#[derive(Debug, Default)]
+pub struct MacroquadBackend {
+    voices: HashMap<VoiceId, SoundKey>,
+}
+
+impl MacroquadBackend {
+    fn key(name: &str, step: i8) -> SoundKey {
+        sound_key(&("sound bank", name, step))
+    }
+}
+
+impl AudioBackend for MacroquadBackend {
+    fn play(&mut self, voice: VoiceId, name: &str, samples: &[f32], volume: f32, pan: f32) {
+        let step = (pan.clamp(-1., 1.) * PAN_STEPS).round() as i8;
+        let key = Self::key(name, step);
+        play_cached(key, volume, || {
+            stereo_wav_bytes(samples, step as f32 / PAN_STEPS)
+        });
+        self.voices.insert(voice, key);
+    }
+
+    fn stop(&mut self, voice: VoiceId) {
+        if let Some(key) = self.voices.remove(&voice) {
+            stop_key(key);
+            self.voices.retain(|_, k| *k != key);
+        }
+    }
+
+    fn is_playing(&self, voice: VoiceId) -> bool {
+        self.voices.contains_key(&voice)
+    }
+
+    fn unload(&mut self, name: &str) {
+        let steps = PAN_STEPS as i8;
+        for step in -steps..=steps {
+            evict(Self::key(name, step));
+        }
+    }
+}
+
+/// 16 bit stereo WAV, panned with constant power
+pub fn stereo_wav_bytes(samples: &[f32], pan: f32) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let angle = (pan.clamp(-1., 1.) + 1.) * std::f32::consts::FRAC_PI_4;
+    let (left, right) = (angle.cos(), angle.sin());
+    let mut bytes = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut bytes, spec)?;
+    for s in samples {
+        for gain in [left, right] {
+            writer.write_sample(((s * gain).clamp(-1., 1.) * i16::MAX as f32) as i16)?;
+        }
+    }
+    writer.finalize()?;
+    Ok(bytes.into_inner())
+}
+
+#[derive(Debug, Clone)]
+struct Sound {
+    def: SoundDef,
+    samples: Vec<f32>,
+    duration: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Voice {
+    id: VoiceId,
+    name: String,
+    priority: u8,
+    remaining: f32,
+}
+
+/// Decides which sound events play, see the module docs
+pub struct Audio<B: AudioBackend> {
+    backend: B,
+    sounds: HashMap<String, Sound>,
+    /// Playing voices, oldest first
+    voices: Vec<Voice>,
+    cooldowns: HashMap<String, f32>,
+    next_id: VoiceId,
+    /// Voices that can play at once, across all sounds
+    pub max_voices: usize,
+    pub volume: f32,
+    /// The part of the world on screen, positions are panned across it
+    pub listener: Rect,
+}
+
+impl<B: AudioBackend> Audio<B> {
+    /// Renders every sound in the bank up front
+    pub fn new(bank: &SoundBank, backend: B) -> Self {
+        let mut audio = Self {
+            backend,
+            sounds: HashMap::new(),
+            voices: Vec::new(),
+            cooldowns: HashMap::new(),
+            next_id: 0,
+            max_voices: 8,
+            volume: 1.,
+            listener: Rect::new(0., 0., crate::world::GAME_WIDTH, crate::world::GAME_HEIGHT),
+        };
+        audio.set_bank(bank);
+        audio
+    }
+
+    /// Stops what is playing and renders the sounds of `bank`. Sounds that changed are unloaded
+    /// from the backend.
+    pub fn set_bank(&mut self, bank: &SoundBank) {
+        self.stop_all();
+        self.cooldowns.clear();
+        for (name, sound) in &self.sounds {
+            if bank.sounds.get(name) != Some(&sound.def) {
+                self.backend.unload(name);
+            }
+        }
+        self.sounds = bank
+            .sounds
+            .iter()
+            .map(|(name, def)| {
+                let samples = def.sfx.render();
+                let sound = Sound {
+                    def: def.clone(),
+                    duration: samples.len() as f32 / SAMPLE_RATE as f32,
+                    samples,
+                };
+                (name.clone(), sound)
+            })
+            .collect();
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn playing(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// -1 at the left edge of the listener, 1 at the right
+    pub fn pan(&self, position: CenterPt) -> f32 {
+        let half = (self.listener.w / 2.).max(1.);
+        let (x, _) = position.get();
+        ((x - self.listener.center().x) / half).clamp(-1., 1.)
+    }
+
+    /// Play the sound unless it is cooling down or every voice is busy with more important
+    /// sounds
+    pub fn trigger(&mut self, event: &SoundEvent) -> Option<VoiceId> {
+        let Some(sound) = self.sounds.get(&event.name) else {
+            warn!("No sound named {}", event.name);
+            return None;
+        };
+        if self.cooldowns.get(&event.name).is_some_and(|c| *c > 0.) {
+            return None;
+        }
+        let def = &sound.def;
+        let same = self.voices.iter().filter(|v| v.name == event.name).count();
+        let steal = if same >= def.max_voices.max(1) {
+            self.voices.iter().position(|v| v.name == event.name)
+        } else if self.voices.len() >= self.max_voices {
+            // The least important voice, the oldest of those
+            let lowest = self.voices.iter().map(|v| v.priority).min()?;
+            if lowest > def.priority {
+                return None;
+            }
+            self.voices.iter().position(|v| v.priority == lowest)
+        } else {
+            None
+        };
+        if let Some(index) = steal {
+            let voice = self.voices.remove(index);
+            self.backend.stop(voice.id);
+            let backend = &self.backend;
+            self.voices.retain(|v| backend.is_playing(v.id));
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let pan = event.position.map_or(0., |p| self.pan(p));
+        self.backend.play(
+            id,
+            &event.name,
+            &sound.samples,
+            def.volume * self.volume,
+            pan,
+        );
+        self.cooldowns.insert(event.name.clone(), def.cooldown);
+        self.voices.push(Voice {
+            id,
+            name: event.name.clone(),
+            priority: def.priority,
+            remaining: sound.duration,
+        });
+        Some(id)
+    }
+
+    pub fn stop_all(&mut self) {
+        for voice in self.voices.drain(..) {
+            self.backend.stop(voice.id);
+        }
+    }
+
+    /// Count down cooldowns and forget voices that finished or were stopped
+    pub fn update(&mut self, delta_time: f32) {
+        for cooldown in self.cooldowns.values_mut() {
+            *cooldown -= delta_time;
+        }
+        self.cooldowns.retain(|_, c| *c > 0.);
+        for voice in &mut self.voices {
+            voice.remaining -= delta_time;
+        }
+        let backend = &self.backend;
+        self.voices
+            .retain(|v| v.remaining > 0. && backend.is_playing(v.id));
+    }
+}
+
+impl<B: AudioBackend> std::fmt::Debug for Audio<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Audio")
+            .field("sounds", &self.sounds.len())
+            .field("voices", &self.voices)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tenth of a second long
+    fn sound(priority: u8, cooldown: f32, max_voices: usize) -> SoundDef {
+        SoundDef {
+            sfx: SfxParams {
+                sustain: 0.05,
+                decay: 0.05,
+                ..Default::default()
+            },
+            priority,
+            cooldown,
+            max_voices,
+            ..Default::default()
+        }
+    }
+
+    fn audio() -> Audio<NullBackend> {
+        let bank = SoundBank {
+            sounds: BTreeMap::from([
+                (String::from("shot"), sound(0, 0., 2)),
+                (String::from("blip"), sound(0, 0., 4)),
+                (String::from("boom"), sound(5, 0., 4)),
+                (String::from("coin"), sound(0, 0.05, 4)),
+            ]),
+        };
+        Audio::new(&bank, NullBackend::default())
+    }
+
+    fn stops(audio: &Audio<NullBackend>) -> Vec<VoiceId> {
+        let calls = &audio.backend().calls;
+        calls
+            .iter()
+            .filter_map(|c| match c {
+                BackendCall::Stop(id) => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unknown_sounds_do_not_play() {
+        let mut audio = audio();
+        assert_eq!(audio.trigger(&SoundEvent::new("moo")), None);
+        assert!(audio.backend().calls.is_empty());
+    }
+
+    #[test]
+    fn oldest_voice_of_a_sound_is_stolen() {
+        let mut audio = audio();
+        let shot = SoundEvent::new("shot");
+        assert_eq!(audio.trigger(&shot), Some(0));
+        assert_eq!(audio.trigger(&shot), Some(1));
+        assert_eq!(audio.trigger(&shot), Some(2));
+        assert_eq!(stops(&audio), vec![0]);
+        assert_eq!(audio.playing(), 2);
+    }
+
+    #[test]
+    fn voice_limit_steals_by_priority() {
+        let mut audio = audio();
+        audio.max_voices = 2;
+        let blip = SoundEvent::new("blip");
+        let boom = SoundEvent::new("boom");
+        audio.trigger(&blip);
+        audio.trigger(&blip);
+        // Louder sounds take the oldest of the least important voices
+        assert_eq!(audio.trigger(&boom), Some(2));
+        assert_eq!(audio.trigger(&boom), Some(3));
+        assert_eq!(stops(&audio), vec![0, 1]);
+        // Every voice is more important
+        assert_eq!(audio.trigger(&blip), None);
+        // Same priority steals
+        assert_eq!(audio.trigger(&boom), Some(4));
+        assert_eq!(stops(&audio), vec![0, 1, 2]);
+        assert_eq!(audio.playing(), 2);
+    }
+
+    #[test]
+    fn cooldowns() {
+        let mut audio = audio();
+        let coin = SoundEvent::new("coin");
+        assert!(audio.trigger(&coin).is_some());
+        assert_eq!(audio.trigger(&coin), None);
+        audio.update(0.04);
+        assert_eq!(audio.trigger(&coin), None);
+        audio.update(0.02);
+        assert!(audio.trigger(&coin).is_some());
+        assert_eq!(audio.playing(), 2);
+    }
+
+    #[test]
+    fn finished_voices_are_forgotten() {
+        let mut audio = audio();
+        audio.trigger(&SoundEvent::new("shot"));
+        audio.update(0.05);
+        assert_eq!(audio.playing(), 1);
+        audio.update(0.06);
+        assert_eq!(audio.playing(), 0);
+        assert!(stops(&audio).is_empty());
+    }
+
+    #[test]
+    fn pan_follows_the_listener() {
+        let mut audio = audio();
+        audio.listener = Rect::new(100., 0., 200., 300.);
+        let pans: Vec<f32> = [50., 100., 150., 200., 300., 400.]
+            .into_iter()
+            .map(|x| {
+                audio.trigger(&SoundEvent::at("blip", CenterPt::new(x, 10.)));
+                match audio.backend().calls.last() {
+                    Some(BackendCall::Play { pan, .. }) => *pan,
+                    call => panic!("{:?}", call),
+                }
+            })
+            .collect();
+        assert_eq!(pans, [-1., -1., -0.5, 0., 1., 1.]);
+        audio.trigger(&SoundEvent::new("boom"));
+        assert!(matches!(
+            audio.backend().calls.last(),
+            Some(BackendCall::Play { pan, .. }) if *pan == 0.
+        ));
+    }
+
+    #[test]
+    fn changed_sounds_are_unloaded() {
+        let mut audio = audio();
+        let mut bank = SoundBank::default();
+        bank.sounds.insert(String::from("shot"), sound(0, 0., 2));
+        bank.sounds.insert(String::from("blip"), sound(1, 0., 4));
+        audio.trigger(&SoundEvent::new("shot"));
+        audio.set_bank(&bank);
+        let calls = &audio.backend().calls;
+        assert_eq!(calls[1], BackendCall::Stop(0));
+        let mut unloaded: Vec<_> = calls
+            .iter()
+            .filter_map(|c| match c {
+                BackendCall::Unload(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        unloaded.sort();
+        assert_eq!(unloaded, ["blip", "boom", "coin"]);
+    }
+}
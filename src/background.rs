@@ -0,0 +1,436 @@
+use ::rand::{rngs::StdRng, Rng, SeedableRng};
+use egui_macroquad::egui::{self, DragValue, Grid, Ui};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::{self, Handle},
+    buildable::Buildable,
+    drawable::{Drawable, PngTexture},
+    migrate::Versioned,
+    palette,
+    updateable::Updateable,
+    utils::GameColor,
+    widgets::color_picker::color_edit_palette_button,
+    CenterPt, Velocity,
+};
+
+/// A color from the active palette, that follows palette remaps
+fn pal(index: u8) -> GameColor {
+    let fallback = palette::with_active(|p| p.get(index)).unwrap_or(WHITE);
+    GameColor::indexed(index, fallback)
+}
+
+/// Stars scrolling past at one speed. Several of these, slower and darker ones in the back, make
+/// a parallax starfield.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StarLayer {
+    pub count: u16,
+    /// Pixels per second
+    pub velocity: Velocity,
+    /// Each star picks one of these, usually palette colors like `pal(6)`
+    pub colors: Vec<GameColor>,
+    /// Width and height of a star in pixels
+    pub size: f32,
+}
+
+impl Default for StarLayer {
+    fn default() -> Self {
+        Self {
+            count: 20,
+            velocity: Velocity::new(0., 20.),
+            colors: vec![pal(5), pal(6)],
+            size: 1.,
+        }
+    }
+}
+
+/// An image repeated over the screen, scrolling at its own rate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageLayer {
+    pub image: String,
+    /// Pixels per second
+    pub velocity: Velocity,
+    pub tint: GameColor,
+    pub repeat_x: bool,
+    pub repeat_y: bool,
+}
+
+impl Default for ImageLayer {
+    fn default() -> Self {
+        Self {
+            image: String::from("background.png"),
+            velocity: Velocity::new(0., 10.),
+            tint: crate::utils::WHITE,
+            repeat_x: true,
+            repeat_y: true,
+        }
+    }
+}
+
+/// Drawn in order, the first one is furthest back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum BackgroundLayer {
+    Stars(StarLayer),
+    Image(ImageLayer),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackgroundBuilder {
+    layers: Vec<BackgroundLayer>,
+    /// Same seed, same stars
+    seed: u64,
+    /// How long the editor preview runs before it starts over
+    preview_time: f32,
+}
+
+impl Default for BackgroundBuilder {
+    fn default() -> Self {
+        Self {
+            layers: vec![
+                BackgroundLayer::Stars(StarLayer {
+                    count: 40,
+                    velocity: Velocity::new(0., 8.),
+                    colors: vec![pal(1), pal(5)],
+                    size: 1.,
+                }),
+                BackgroundLayer::Stars(StarLayer::default()),
+                BackgroundLayer::Stars(StarLayer {
+                    count: 8,
+                    velocity: Velocity::new(0., 60.),
+                    colors: vec![pal(7)],
+                    size: 1.,
+                }),
+            ],
+            seed: 1,
+            preview_time: 10.,
+        }
+    }
+}
+
+impl Versioned for BackgroundBuilder {}
+
+impl BackgroundBuilder {
+    pub fn with_layer(mut self, layer: BackgroundLayer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Names of the images the image layers use
+    pub fn images(&self) -> impl Iterator<Item = &str> {
+        self.layers.iter().filter_map(|layer| match layer {
+            BackgroundLayer::Image(desc) => Some(desc.image.as_str()),
+            BackgroundLayer::Stars(_) => None,
+        })
+    }
+
+    /// Build a background covering `width` x `height` pixels. Images are shared through
+    /// `assets`, so building again does not load them again. Image layers whose image can not be
+    /// loaded are left out.
+    pub fn build_sized(&self, width: f32, height: f32) -> Background {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let layers = self
+            .layers
+            .iter()
+            .filter_map(|layer| match layer {
+                BackgroundLayer::Stars(desc) => {
+                    let stars = (0..desc.count)
+                        .map(|_| Star {
+                            pos: Vec2::new(rng.gen_range(0. ..width), rng.gen_range(0. ..height)),
+                            color: if desc.colors.is_empty() {
+                                crate::utils::WHITE
+                            } else {
+                                desc.colors[rng.gen_range(0..desc.colors.len())]
+                            },
+                        })
+                        .collect();
+                    Some(Scrolling::Stars {
+                        desc: desc.clone(),
+                        stars,
+                    })
+                }
+                BackgroundLayer::Image(desc) => match assets::load::<PngTexture>(&desc.image) {
+                    Err(err) => {
+                        warn!("Unable to load background image: {:#?}", err);
+                        None
+                    }
+                    Ok(texture) => Some(Scrolling::Image {
+                        desc: desc.clone(),
+                        texture,
+                        offset: Vec2::ZERO,
+                    }),
+                },
+            })
+            .collect();
+        Background {
+            size: Vec2::new(width, height),
+            layers,
+        }
+    }
+
+    fn star_layer_ui(ui: &mut Ui, desc: &mut StarLayer, id: usize) {
+        Grid::new(format!("bg_stars_{}", id)).show(ui, |ui| {
+            ui.label("Count");
+            ui.add(DragValue::new(&mut desc.count).clamp_range(0..=1000));
+            ui.end_row();
+
+            ui.label("Velocity");
+            velocity_ui(ui, &mut desc.velocity);
+            ui.end_row();
+
+            ui.label("Size");
+            ui.add(DragValue::new(&mut desc.size).clamp_range(1..=8));
+            ui.end_row();
+
+            ui.label("Colors");
+            ui.horizontal(|ui| {
+                let mut to_remove = None;
+                for (i, color) in desc.colors.iter_mut().enumerate() {
+                    color_edit_palette_button(ui, color)
+                        .context_menu(|ui| {
+                            if ui.button("Remove").clicked() {
+                                to_remove = Some(i);
+                                ui.close_menu();
+                            }
+                        })
+                        .on_hover_text("Right click to remove");
+                }
+                if let Some(i) = to_remove {
+                    desc.colors.remove(i);
+                }
+                if ui.small_button("+").clicked() {
+                    let last = desc.colors.last().copied().unwrap_or_default();
+                    desc.colors.push(last);
+                }
+            });
+            ui.end_row();
+        });
+    }
+
+    fn image_layer_ui(ui: &mut Ui, desc: &mut ImageLayer, id: usize) {
+        Grid::new(format!("bg_image_{}", id)).show(ui, |ui| {
+            ui.label("Image");
+            ui.text_edit_singleline(&mut desc.image);
+            ui.end_row();
+
+            ui.label("Velocity");
+            velocity_ui(ui, &mut desc.velocity);
+            ui.end_row();
+
+            ui.label("Repeat");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut desc.repeat_x, "x");
+                ui.checkbox(&mut desc.repeat_y, "y");
+            });
+            ui.end_row();
+
+            ui.label("Tint");
+            color_edit_palette_button(ui, &mut desc.tint);
+            ui.end_row();
+        });
+    }
+}
+
+fn velocity_ui(ui: &mut Ui, velocity: &mut Velocity) {
+    let (mut x, mut y) = (*velocity).into();
+    ui.horizontal(|ui| {
+        ui.add(DragValue::new(&mut x).speed(0.5));
+        ui.add(DragValue::new(&mut y).speed(0.5));
+    });
+    *velocity = Velocity::new(x, y);
+}
+
+impl Buildable for BackgroundBuilder {
+    type Byproduct = Background;
+
+    /// The background covers twice the distance to `center`, so the whole screen when that is
+    /// the center of the screen
+    fn build(self, center: CenterPt) -> Option<Self::Byproduct> {
+        let (cx, cy) = center.into();
+        Some(self.build_sized(cx * 2., cy * 2.))
+    }
+
+    fn max_loop_time(&self) -> f32 {
+        self.preview_time
+    }
+
+    fn draw_gizmos_at(&self, _center: CenterPt) {}
+
+    fn editor_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Background");
+            if ui.small_button("Add Stars").clicked() {
+                self.layers
+                    .push(BackgroundLayer::Stars(StarLayer::default()));
+            }
+            if ui.small_button("Add Image").clicked() {
+                self.layers
+                    .push(BackgroundLayer::Image(ImageLayer::default()));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Seed");
+            ui.add(DragValue::new(&mut self.seed));
+            ui.label("Preview");
+            ui.add(
+                DragValue::new(&mut self.preview_time)
+                    .clamp_range(1..=120)
+                    .suffix("s"),
+            );
+        });
+
+        let mut to_remove = Vec::new();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (i, layer) in self.layers.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    let name = match layer {
+                        BackgroundLayer::Stars(_) => "Stars",
+                        BackgroundLayer::Image(_) => "Image",
+                    };
+                    ui.heading(format!("{} layer #{}", name, i + 1));
+                    if ui.small_button("").clicked() {
+                        to_remove.push(i);
+                    }
+                });
+                ui.group(|ui| match layer {
+                    BackgroundLayer::Stars(desc) => Self::star_layer_ui(ui, desc, i),
+                    BackgroundLayer::Image(desc) => Self::image_layer_ui(ui, desc, i),
+                });
+            }
+        });
+
+        to_remove.into_iter().for_each(|i| {
+            self.layers.remove(i);
+        });
+    }
+
+    fn get_base_id() -> &'static str {
+        "bg"
+    }
+
+    fn get_default_file_name() -> &'static str {
+        "background.yaml"
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Star {
+    pos: Vec2,
+    color: GameColor,
+}
+
+#[derive(Debug)]
+enum Scrolling {
+    Stars {
+        desc: StarLayer,
+        stars: Vec<Star>,
+    },
+    Image {
+        desc: ImageLayer,
+        texture: Handle<PngTexture>,
+        offset: Vec2,
+    },
+}
+
+/// A built background, drawn from (0, 0) to its size. Put it on a fixed layer, like
+/// `World::set_background` does, so it stays on the screen.
+#[derive(Debug)]
+pub struct Background {
+    size: Vec2,
+    layers: Vec<Scrolling>,
+}
+
+impl Background {
+    pub fn size(&self) -> Vec2 {
+        self.size
+    }
+}
+
+impl Updateable for Background {
+    fn update(&mut self, delta_time: f32) {
+        let size = self.size;
+        for layer in self.layers.iter_mut() {
+            match layer {
+                Scrolling::Stars { desc, stars } => {
+                    let (vx, vy) = desc.velocity.into();
+                    let v = Vec2::new(vx, vy) * delta_time;
+                    for star in stars.iter_mut() {
+                        let pos = star.pos + v;
+                        star.pos = Vec2::new(pos.x.rem_euclid(size.x), pos.y.rem_euclid(size.y));
+                    }
+                }
+                Scrolling::Image {
+                    desc,
+                    texture,
+                    offset,
+                } => {
+                    let (vx, vy) = desc.velocity.into();
+                    let texture = texture.texture();
+                    let tile = Vec2::new(texture.width(), texture.height());
+                    *offset += Vec2::new(vx, vy) * delta_time;
+                    if desc.repeat_x {
+                        offset.x = offset.x.rem_euclid(tile.x);
+                    }
+                    if desc.repeat_y {
+                        offset.y = offset.y.rem_euclid(tile.y);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drawable for Background {
+    fn draw(&self) {
+        for layer in self.layers.iter() {
+            match layer {
+                Scrolling::Stars { desc, stars } => {
+                    for star in stars.iter() {
+                        let (x, y) = (star.pos.x.floor(), star.pos.y.floor());
+                        draw_rectangle(x, y, desc.size, desc.size, star.color.into());
+                    }
+                }
+                Scrolling::Image {
+                    desc,
+                    texture,
+                    offset,
+                } => {
+                    let texture = texture.texture();
+                    let (w, h) = (texture.width(), texture.height());
+                    let offset = offset.round();
+                    // Start one tile before the screen when repeating, the offset is never
+                    // more than a tile
+                    let (x0, x1) = if desc.repeat_x {
+                        (offset.x - w, self.size.x)
+                    } else {
+                        (offset.x, offset.x)
+                    };
+                    let (y0, y1) = if desc.repeat_y {
+                        (offset.y - h, self.size.y)
+                    } else {
+                        (offset.y, offset.y)
+                    };
+                    let mut y = y0;
+                    while y <= y1 {
+                        let mut x = x0;
+                        while x <= x1 {
+                            draw_texture(texture, x, y, desc.tint.into());
+                            x += w;
+                        }
+                        y += h;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,694 @@
+//! Bosses: a core with destructible parts, going through phases that each move and shoot
+//! differently.
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bullet::Bullet,
+    drawable::Drawable,
+    migrate::Versioned,
+    particle::ExplosionBuilder,
+    rank::Rank,
+    utils::{self, GameColor},
+    CenterPt, Velocity,
+};
+
+/// When a phase starts, checked once the phase before it is running
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PhaseTrigger {
+    /// Health (core and parts together) drops below this fraction of the full health
+    HpBelow(f32),
+    /// Seconds after the phase before started
+    After(f32),
+}
+
+impl Default for PhaseTrigger {
+    fn default() -> Self {
+        PhaseTrigger::HpBelow(0.5)
+    }
+}
+
+/// Relative to where the boss was spawned
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Movement {
+    #[default]
+    Still,
+    /// Figure of eight, `period` seconds per loop
+    Hover { amplitude: (f32, f32), period: f32 },
+    /// Follow the player left and right, in pixels per second
+    Chase { speed: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AttackPattern {
+    /// Evenly spread all around
+    Ring { count: u32 },
+    /// Fanned out towards the player over `spread` degrees
+    Aimed { count: u32, spread: f32 },
+    /// A ring that turns `turn` degrees every volley
+    Spiral { count: u32, turn: f32 },
+}
+
+impl Default for AttackPattern {
+    fn default() -> Self {
+        AttackPattern::Ring { count: 8 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttackDef {
+    pub pattern: AttackPattern,
+    /// Seconds between volleys
+    pub interval: f32,
+    /// Pixels per second
+    pub speed: f32,
+    pub radius: f32,
+    pub color: GameColor,
+    /// Fired from this part, and stops when it is destroyed. The core fires when `None`.
+    pub part: Option<String>,
+    /// Name of a `BulletPattern` started every volley instead of firing `pattern`, so it
+    /// should end on its own
+    pub bullet_pattern: Option<String>,
+}
+
+impl Default for AttackDef {
+    fn default() -> Self {
+        Self {
+            pattern: AttackPattern::default(),
+            interval: 1.,
+            speed: 40.,
+            radius: 1.5,
+            color: utils::PINK,
+            part: None,
+            bullet_pattern: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhaseDef {
+    /// Ignored for the first phase
+    pub trigger: PhaseTrigger,
+    pub movement: Movement,
+    pub attacks: Vec<AttackDef>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PartDef {
+    pub name: String,
+    /// From the center of the boss
+    pub offset: (f32, f32),
+    /// Hit box
+    pub radius: f32,
+    pub hp: f32,
+    pub score: u32,
+    /// Drop table in the `PickupBank` rolled when the part is destroyed
+    pub drops: Option<String>,
+    /// The core can not be hurt while this part stands
+    pub shield: bool,
+    pub color: GameColor,
+}
+
+impl Default for PartDef {
+    fn default() -> Self {
+        Self {
+            name: String::from("part"),
+            offset: (0., 0.),
+            radius: 4.,
+            hp: 20.,
+            score: 500,
+            drops: None,
+            shield: false,
+            color: utils::GRAY,
+        }
+    }
+}
+
+/// One explosion of the death sequence
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeathBlast {
+    /// Seconds after the boss died
+    pub delay: f32,
+    pub offset: (f32, f32),
+    pub explosion: ExplosionBuilder,
+    /// Camera shake trauma
+    pub shake: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BossDef {
+    pub name: String,
+    pub hp: f32,
+    /// Hit box of the core
+    pub radius: f32,
+    pub color: GameColor,
+    pub score: u32,
+    /// Drop table in the `PickupBank` rolled when the core is destroyed
+    pub drops: Option<String>,
+    pub parts: Vec<PartDef>,
+    pub phases: Vec<PhaseDef>,
+    /// Plays in order once the core is destroyed, the boss is gone after the last one
+    pub death: Vec<DeathBlast>,
+}
+
+impl Default for BossDef {
+    fn default() -> Self {
+        let blast = |delay: f32, offset: (f32, f32), size: f32, shake: f32| DeathBlast {
+            delay,
+            offset,
+            explosion: ExplosionBuilder::burst(size),
+            shake,
+        };
+        let wing = |name: &str, x: f32| PartDef {
+            name: String::from(name),
+            offset: (x, 2.),
+            shield: true,
+            ..Default::default()
+        };
+        Self {
+            name: String::from("boss"),
+            hp: 100.,
+            radius: 8.,
+            color: utils::MAROON,
+            score: 10000,
+            drops: None,
+            parts: vec![wing("left wing", -14.), wing("right wing", 14.)],
+            phases: vec![
+                PhaseDef {
+                    movement: Movement::Hover {
+                        amplitude: (20., 4.),
+                        period: 4.,
+                    },
+                    attacks: vec![
+                        AttackDef {
+                            pattern: AttackPattern::Aimed {
+                                count: 3,
+                                spread: 30.,
+                            },
+                            part: Some(String::from("left wing")),
+                            ..Default::default()
+                        },
+                        AttackDef {
+                            pattern: AttackPattern::Aimed {
+                                count: 3,
+                                spread: 30.,
+                            },
+                            part: Some(String::from("right wing")),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                },
+                PhaseDef {
+                    trigger: PhaseTrigger::HpBelow(0.5),
+                    movement: Movement::Chase { speed: 20. },
+                    attacks: vec![AttackDef {
+                        pattern: AttackPattern::Spiral {
+                            count: 6,
+                            turn: 12.,
+                        },
+                        interval: 0.2,
+                        ..Default::default()
+                    }],
+                },
+            ],
+            death: vec![
+                blast(0., (-6., -4.), 8., 0.2),
+                blast(0.3, (8., 2.), 8., 0.2),
+                blast(0.6, (-2., 6.), 10., 0.3),
+                blast(1., (0., 0.), 20., 0.8),
+            ],
+        }
+    }
+}
+
+impl Versioned for BossDef {}
+
+impl BossDef {
+    /// Core and parts together
+    pub fn max_hp(&self) -> f32 {
+        self.hp + self.parts.iter().map(|p| p.hp).sum::<f32>()
+    }
+}
+
+/// What happened to the boss during an update, for the world to act on
+#[derive(Debug, Clone)]
+pub enum BossEvent {
+    Fire(Bullet),
+    /// Start the bullet pattern named `name` at `center`
+    Pattern {
+        name: String,
+        center: CenterPt,
+    },
+    Explode {
+        center: CenterPt,
+        explosion: ExplosionBuilder,
+        shake: f32,
+    },
+    PhaseChanged(usize),
+    PartDestroyed {
+        name: String,
+        center: CenterPt,
+        score: u32,
+        drops: Option<String>,
+    },
+    /// The core was destroyed, the death sequence starts
+    Defeated {
+        center: CenterPt,
+        score: u32,
+        drops: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Part {
+    hp: f32,
+    flash: f32,
+}
+
+/// A `BossDef` fighting
+#[derive(Debug, Clone)]
+pub struct Boss {
+    pub def: BossDef,
+    anchor: CenterPt,
+    center: CenterPt,
+    hp: f32,
+    parts: Vec<Part>,
+    phase: usize,
+    phase_time: f32,
+    /// Time to the next volley of each attack of the phase
+    attack_timers: Vec<f32>,
+    volleys: Vec<u32>,
+    flash: f32,
+    /// Seconds since the core was destroyed
+    dying: Option<f32>,
+    events: Vec<BossEvent>,
+}
+
+/// How long something flashes after it was hit
+const HIT_FLASH: f32 = 0.05;
+
+impl Boss {
+    pub fn new(def: BossDef, center: CenterPt) -> Self {
+        let parts = def
+            .parts
+            .iter()
+            .map(|p| Part {
+                hp: p.hp,
+                flash: 0.,
+            })
+            .collect();
+        let mut boss = Self {
+            hp: def.hp,
+            def,
+            anchor: center,
+            center,
+            parts,
+            phase: 0,
+            phase_time: 0.,
+            attack_timers: Vec::new(),
+            volleys: Vec::new(),
+            flash: 0.,
+            dying: None,
+            events: Vec::new(),
+        };
+        boss.start_phase(0);
+        boss
+    }
+
+    pub fn phase(&self) -> usize {
+        self.phase
+    }
+
+    /// Core and parts together
+    pub fn hp(&self) -> f32 {
+        self.hp + self.parts.iter().map(|p| p.hp).sum::<f32>()
+    }
+
+    /// From 0 to 1
+    pub fn hp_fraction(&self) -> f32 {
+        (self.hp() / self.def.max_hp().max(1.)).clamp(0., 1.)
+    }
+
+    pub fn is_dying(&self) -> bool {
+        self.dying.is_some()
+    }
+
+    /// The death sequence is over, the boss can be removed
+    pub fn is_gone(&self) -> bool {
+        let last = self.def.death.iter().map(|b| b.delay).fold(0., f32::max);
+        self.dying.is_some_and(|t| t > last)
+    }
+
+    fn start_phase(&mut self, phase: usize) {
+        self.phase = phase;
+        self.phase_time = 0.;
+        let attacks = self.def.phases.get(phase).map_or(&[][..], |p| &p.attacks);
+        self.attack_timers = attacks.iter().map(|a| a.interval).collect();
+        self.volleys = vec![0; attacks.len()];
+    }
+
+    fn part_index(&self, name: &str) -> Option<usize> {
+        self.def.parts.iter().position(|p| p.name == name)
+    }
+
+    fn part_center(&self, index: usize) -> CenterPt {
+        let (x, y) = self.center.get();
+        let (ox, oy) = self.def.parts[index].offset;
+        CenterPt::new(x + ox, y + oy)
+    }
+
+    fn is_shielded(&self) -> bool {
+        self.def
+            .parts
+            .iter()
+            .zip(&self.parts)
+            .any(|(def, part)| def.shield && part.hp > 0.)
+    }
+
+    /// Something at `center` overlaps the core or a part that still stands
+    pub fn touches(&self, center: CenterPt, radius: f32) -> bool {
+        if self.dying.is_some() {
+            return false;
+        }
+        let (x, y) = center.get();
+        let touches = |at: CenterPt, r: f32| {
+            let (px, py) = at.get();
+            vec2(px - x, py - y).length() < r + radius
+        };
+        touches(self.center, self.def.radius)
+            || (0..self.parts.len()).any(|i| {
+                self.parts[i].hp > 0. && touches(self.part_center(i), self.def.parts[i].radius)
+            })
+    }
+
+    /// Check a bullet at `center` against the parts, then the core. Returns true when it hit
+    /// something, even a shielded core.
+    pub fn hit(&mut self, center: CenterPt, radius: f32, damage: f32) -> bool {
+        if self.dying.is_some() {
+            return false;
+        }
+        let (x, y) = center.get();
+        let touches = |at: CenterPt, r: f32| {
+            let (px, py) = at.get();
+            vec2(px - x, py - y).length() < r + radius
+        };
+        for i in 0..self.parts.len() {
+            if self.parts[i].hp <= 0. || !touches(self.part_center(i), self.def.parts[i].radius) {
+                continue;
+            }
+            let part = &mut self.parts[i];
+            part.hp -= damage;
+            part.flash = HIT_FLASH;
+            if part.hp <= 0. {
+                part.hp = 0.;
+                let def = &self.def.parts[i];
+                self.events.push(BossEvent::PartDestroyed {
+                    name: def.name.clone(),
+                    center: self.part_center(i),
+                    score: def.score,
+                    drops: def.drops.clone(),
+                });
+                // Parts go off like the first blast of the death sequence
+                let explosion = self.def.death.first().map(|b| b.explosion.clone());
+                self.events.push(BossEvent::Explode {
+                    center: self.part_center(i),
+                    explosion: explosion.unwrap_or_default(),
+                    shake: 0.3,
+                });
+            }
+            return true;
+        }
+        if !touches(self.center, self.def.radius) {
+            return false;
+        }
+        if !self.is_shielded() {
+            self.hp -= damage;
+            self.flash = HIT_FLASH;
+            if self.hp <= 0. {
+                self.hp = 0.;
+                self.dying = Some(0.);
+                self.events.push(BossEvent::Defeated {
+                    center: self.center,
+                    score: self.def.score,
+                    drops: self.def.drops.clone(),
+                });
+            }
+        }
+        true
+    }
+
+    /// Move, shoot and maybe change phase. `target` is the player, when there is one. Bullets
+    /// get faster and more numerous with `rank`.
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        target: Option<CenterPt>,
+        rank: &Rank,
+    ) -> Vec<BossEvent> {
+        self.flash = (self.flash - delta_time).max(0.);
+        for part in &mut self.parts {
+            part.flash = (part.flash - delta_time).max(0.);
+        }
+        if let Some(time) = self.dying {
+            self.update_death(time, delta_time);
+        } else {
+            self.phase_time += delta_time;
+            self.update_phase();
+            self.update_movement(delta_time, target);
+            self.update_attacks(delta_time, target, rank);
+        }
+        std::mem::take(&mut self.events)
+    }
+
+    fn update_phase(&mut self) {
+        let Some(next) = self.def.phases.get(self.phase + 1) else {
+            return;
+        };
+        let start = match next.trigger {
+            PhaseTrigger::HpBelow(fraction) => self.hp_fraction() < fraction,
+            PhaseTrigger::After(seconds) => self.phase_time >= seconds,
+        };
+        if start {
+            self.start_phase(self.phase + 1);
+            self.events.push(BossEvent::PhaseChanged(self.phase));
+        }
+    }
+
+    fn update_movement(&mut self, delta_time: f32, target: Option<CenterPt>) {
+        let Some(phase) = self.def.phases.get(self.phase) else {
+            return;
+        };
+        let (ax, ay) = self.anchor.get();
+        let (x, y) = self.center.get();
+        self.center = match phase.movement {
+            Movement::Still => self.center,
+            Movement::Hover { amplitude, period } => {
+                let t = self.phase_time / period.max(0.1) * std::f32::consts::TAU;
+                CenterPt::new(
+                    ax + t.sin() * amplitude.0,
+                    ay + (t * 2.).sin() * amplitude.1,
+                )
+            }
+            Movement::Chase { speed } => {
+                let tx = target.map_or(ax, |t| t.get().0);
+                let step = (tx - x).clamp(-speed * delta_time, speed * delta_time);
+                CenterPt::new(x + step, y + (ay - y) * delta_time.min(1.))
+            }
+        };
+    }
+
+    fn update_attacks(&mut self, delta_time: f32, target: Option<CenterPt>, rank: &Rank) {
+        let Some(phase) = self.def.phases.get(self.phase) else {
+            return;
+        };
+        for (i, attack) in phase.attacks.iter().enumerate() {
+            let from = match &attack.part {
+                None => self.center,
+                Some(name) => match self.part_index(name) {
+                    Some(p) if self.parts[p].hp > 0. => self.part_center(p),
+                    _ => continue,
+                },
+            };
+            self.attack_timers[i] -= delta_time;
+            if self.attack_timers[i] > 0. {
+                continue;
+            }
+            self.attack_timers[i] += attack.interval.max(0.05);
+            let volley = self.volleys[i];
+            self.volleys[i] += 1;
+            if let Some(name) = &attack.bullet_pattern {
+                self.events.push(BossEvent::Pattern {
+                    name: name.clone(),
+                    center: from,
+                });
+                continue;
+            }
+            let speed = attack.speed * rank.bullet_speed();
+            for angle in attack_angles(attack.pattern, from, target, volley, rank) {
+                let angle = angle.to_radians();
+                let velocity = Velocity::new(angle.sin(), -angle.cos()) * speed;
+                self.events.push(BossEvent::Fire(Bullet::new(
+                    from,
+                    velocity,
+                    attack.radius,
+                    attack.color,
+                )));
+            }
+        }
+    }
+
+    fn update_death(&mut self, time: f32, delta_time: f32) {
+        let now = time + delta_time;
+        let (x, y) = self.center.get();
+        for blast in &self.def.death {
+            let delay = blast.delay.max(0.);
+            if delay >= time && delay < now {
+                self.events.push(BossEvent::Explode {
+                    center: CenterPt::new(x + blast.offset.0, y + blast.offset.1),
+                    explosion: blast.explosion.clone(),
+                    shake: blast.shake,
+                });
+            }
+        }
+        self.dying = Some(now);
+    }
+}
+
+/// Degrees clockwise from straight up, like `Shot::angle`
+fn attack_angles(
+    pattern: AttackPattern,
+    from: CenterPt,
+    target: Option<CenterPt>,
+    volley: u32,
+    rank: &Rank,
+) -> Vec<f32> {
+    let ring = |count: u32, start: f32| {
+        let step = 360. / count.max(1) as f32;
+        (0..count).map(|i| start + i as f32 * step).collect()
+    };
+    match pattern {
+        AttackPattern::Ring { count } => ring(rank.bullet_count(count), 0.),
+        AttackPattern::Spiral { count, turn } => {
+            ring(rank.bullet_count(count), volley as f32 * turn)
+        }
+        AttackPattern::Aimed { count, spread } => {
+            let count = rank.bullet_count(count);
+            let aim = target.map_or(180., |t| {
+                let (fx, fy) = from.get();
+                let (tx, ty) = t.get();
+                (tx - fx).atan2(fy - ty).to_degrees()
+            });
+            if count <= 1 {
+                return vec![aim];
+            }
+            let step = spread / (count - 1) as f32;
+            (0..count)
+                .map(|i| aim - spread / 2. + i as f32 * step)
+                .collect()
+        }
+    }
+}
+
+crate::impl_pts!(center Boss);
+
+impl Drawable for Boss {
+    fn draw(&self) {
+        if self.is_dying() {
+            return;
+        }
+        let (x, y) = self.center.get();
+        for (i, (def, part)) in self.def.parts.iter().zip(&self.parts).enumerate() {
+            if part.hp <= 0. {
+                continue;
+            }
+            let color = if part.flash > 0. {
+                WHITE
+            } else {
+                def.color.into()
+            };
+            let (px, py) = self.part_center(i).get();
+            draw_circle(px, py, def.radius, color);
+        }
+        let color = if self.flash > 0. {
+            WHITE
+        } else {
+            self.def.color.into()
+        };
+        draw_circle(x, y, self.def.radius, color);
+    }
+
+    fn draw_gizmos(&self) {
+        let (x, y) = self.center.get();
+        let core = if self.is_shielded() { BLUE } else { RED };
+        draw_circle_lines(x, y, self.def.radius, 0.5, core);
+        for (i, (def, part)) in self.def.parts.iter().zip(&self.parts).enumerate() {
+            if part.hp > 0. {
+                let (px, py) = self.part_center(i).get();
+                draw_circle_lines(px, py, def.radius, 0.5, RED);
+            }
+        }
+        let (ax, ay) = self.anchor.get();
+        draw_line(ax - 2., ay, ax + 3., ay, 1., GREEN);
+        draw_line(ax, ay - 2., ax, ay + 3., 1., GREEN);
+    }
+}
+
+/// Health across the top of the screen, drawn on the HUD with the phase marks of `HpBelow`
+/// triggers. `width` is the width of the screen.
+pub fn draw_health_bar(boss: &Boss, width: f32) {
+    let (x, y, w, h) = (4., 3., width - 8., 3.);
+    draw_rectangle(x - 1., y - 1., w + 2., h + 2., utils::DARKGRAY.into());
+    draw_rectangle(x, y, w * boss.hp_fraction(), h, utils::RED.into());
+    for phase in boss.def.phases.iter().skip(1) {
+        if let PhaseTrigger::HpBelow(fraction) = phase.trigger {
+            let mark = x + (w * fraction).round();
+            draw_line(mark, y, mark, y + h, 1., utils::WHITE.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destroyed_parts_and_cores_drop() {
+        let def = BossDef {
+            hp: 1.,
+            drops: Some(String::from("boss")),
+            parts: vec![PartDef {
+                hp: 1.,
+                offset: (20., 0.),
+                drops: Some(String::from("wing")),
+                ..Default::default()
+            }],
+            phases: vec![PhaseDef::default()],
+            ..Default::default()
+        };
+        let mut boss = Boss::new(def, CenterPt::new(50., 50.));
+        assert!(boss.hit(CenterPt::new(70., 50.), 1., 1.));
+        assert!(boss.hit(CenterPt::new(50., 50.), 1., 1.));
+        let drops: Vec<_> = boss
+            .update(0., None, &Rank::default())
+            .into_iter()
+            .filter_map(|e| match e {
+                BossEvent::PartDestroyed { center, drops, .. }
+                | BossEvent::Defeated { center, drops, .. } => Some((drops, center)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            drops,
+            [
+                (Some(String::from("wing")), CenterPt::new(70., 50.)),
+                (Some(String::from("boss")), CenterPt::new(50., 50.)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use macroquad::prelude::*;
+
+use crate::{
+    alive::IsAlive, drawable::Drawable, timers::AliveTimer, updateable::Updateable,
+    utils::GameColor, CenterPt, Velocity,
+};
+
+/// A round bullet flying in a straight line until its time is up
+#[derive(Debug, Clone)]
+pub struct Bullet {
+    center: CenterPt,
+    velocity: Velocity,
+    pub radius: f32,
+    pub color: GameColor,
+    ttl: AliveTimer,
+    grazed: bool,
+}
+
+impl Bullet {
+    pub fn new(center: CenterPt, velocity: Velocity, radius: f32, color: GameColor) -> Self {
+        Self {
+            center,
+            velocity,
+            radius,
+            color,
+            ttl: AliveTimer::new(3.),
+            grazed: false,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: f32) -> Self {
+        self.ttl = AliveTimer::new(ttl);
+        self
+    }
+
+    /// Remove the bullet on the next cleanup, e.g. after it hit something
+    pub fn kill(&mut self) {
+        self.ttl = AliveTimer::new(0.);
+    }
+
+    /// Returns true the first time it is called for this bullet, so each bullet is only grazed
+    /// once
+    pub fn graze(&mut self) -> bool {
+        !std::mem::replace(&mut self.grazed, true)
+    }
+
+    pub fn overlaps(&self, center: CenterPt, radius: f32) -> bool {
+        let (x, y) = self.center.get();
+        let (ox, oy) = center.get();
+        vec2(x - ox, y - oy).length() < self.radius + radius
+    }
+}
+
+crate::impl_pts!(center Bullet);
+crate::impl_pts!(velocity Bullet);
+
+impl IsAlive for Bullet {
+    fn is_alive(&self) -> bool {
+        self.ttl.is_alive()
+    }
+}
+
+impl Updateable for Bullet {
+    fn update(&mut self, delta_time: f32) {
+        self.ttl.update(delta_time);
+        self.center = self.center + self.velocity * delta_time;
+    }
+}
+
+impl Drawable for Bullet {
+    fn draw(&self) {
+        let (x, y) = self.center.get();
+        draw_circle(x, y, self.radius, self.color.into());
+    }
+
+    fn draw_gizmos(&self) {
+        let (x, y) = self.center.get();
+        draw_circle_lines(x, y, self.radius, 0.5, RED);
+    }
+}
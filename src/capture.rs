@@ -0,0 +1,325 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use image::{codecs::gif::GifEncoder, Delay, Frame, RgbaImage};
+use macroquad::texture::{Image, Texture2D};
+use serde::{Deserialize, Serialize};
+
+use crate::updateable::Updateable;
+
+pub const DEFAULT_CAPTURE_DIR: &str = "captures";
+
+/// Copy a texture (e.g. `RetroCamera::texture`) back from the GPU, scaled up by a whole number so
+/// pixels stay square and sharp
+pub fn grab(texture: Texture2D, scale: u32) -> Image {
+    upscale(&texture.get_texture_data(), scale)
+}
+
+/// Nearest neighbour upscale, every pixel becomes a `scale` x `scale` block
+pub fn upscale(image: &Image, scale: u32) -> Image {
+    let scale = scale.max(1) as usize;
+    if scale == 1 {
+        return image.clone();
+    }
+    let (w, h) = (image.width as usize, image.height as usize);
+    let mut bytes = Vec::with_capacity(w * h * scale * scale * 4);
+    for y in 0..h {
+        let row = &image.bytes[y * w * 4..(y + 1) * w * 4];
+        let mut scaled_row = Vec::with_capacity(w * scale * 4);
+        for px in row.chunks_exact(4) {
+            for _ in 0..scale {
+                scaled_row.extend_from_slice(px);
+            }
+        }
+        for _ in 0..scale {
+            bytes.extend_from_slice(&scaled_row);
+        }
+    }
+    Image {
+        bytes,
+        width: (w * scale) as u16,
+        height: (h * scale) as u16,
+    }
+}
+
+fn to_rgba_image(image: Image) -> Result<RgbaImage> {
+    RgbaImage::from_raw(image.width.into(), image.height.into(), image.bytes)
+        .context("image data does not match its size")
+}
+
+pub fn save_png(image: &Image, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    to_rgba_image(image.clone())?
+        .save_with_format(path, image::ImageFormat::Png)
+        .with_context(|| format!("Could not save {}", path.display()))
+}
+
+/// Save a screenshot of the texture in `dir`, named after the current time
+pub fn screenshot(texture: Texture2D, scale: u32, dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir).with_context(|| format!("Could not create {}", dir.display()))?;
+    let path = dir.join(format!("screenshot-{}.png", timestamp()));
+    save_png(&grab(texture, scale), &path)?;
+    Ok(path)
+}
+
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordFormat {
+    /// Numbered PNGs, written as they are captured
+    #[default]
+    Frames,
+    /// One animated GIF, encoded as it is captured
+    Gif,
+}
+
+/// What the game asked to capture this frame, handled after the frame was rendered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptureRequest {
+    Screenshot,
+    /// Record this many seconds
+    Record(f32, RecordFormat),
+}
+
+/// Writes what the recorder grabbed, on its own thread so the game does not stutter. Frames
+/// come in at their original size and are only upscaled here.
+fn write_recording(
+    format: RecordFormat,
+    target: PathBuf,
+    scale: u32,
+    fps: f32,
+    frames: Receiver<Image>,
+) -> Result<()> {
+    match format {
+        RecordFormat::Frames => {
+            for (i, image) in frames.into_iter().enumerate() {
+                let name = format!("frame-{:05}.png", i);
+                save_png(&upscale(&image, scale), target.join(name))?;
+            }
+        }
+        RecordFormat::Gif => {
+            let file = File::create(&target)
+                .with_context(|| format!("Could not create {}", target.display()))?;
+            let mut encoder = GifEncoder::new(BufWriter::new(file));
+            encoder.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+            let delay = Delay::from_numer_denom_ms(1000, fps.round().max(1.) as u32);
+            for image in frames {
+                let image = to_rgba_image(upscale(&image, scale))?;
+                encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A recording being written
+#[derive(Debug)]
+struct Writer {
+    frames: Sender<Image>,
+    thread: JoinHandle<Result<()>>,
+}
+
+/// Records the game screen for a number of seconds. Call `capture` once per frame, after the
+/// frame was rendered.
+#[derive(Debug)]
+pub struct Recorder {
+    pub dir: PathBuf,
+    pub scale: u32,
+    /// Frames per second to capture. GIF delays are in hundredths of a second, so 25 or 50 play
+    /// back at the right speed.
+    pub fps: f32,
+    remaining: f32,
+    since_last: f32,
+    /// Where the current recording goes, a folder for frames or the GIF file
+    target: PathBuf,
+    writer: Option<Writer>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from(DEFAULT_CAPTURE_DIR),
+            scale: 1,
+            fps: 25.,
+            remaining: 0.,
+            since_last: 0.,
+            target: PathBuf::new(),
+            writer: None,
+        }
+    }
+}
+
+impl Recorder {
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Time left in the current recording
+    pub fn remaining(&self) -> f32 {
+        self.remaining.max(0.)
+    }
+
+    pub fn start(&mut self, seconds: f32, format: RecordFormat) -> Result<()> {
+        if self.is_recording() {
+            self.finish()?;
+        }
+        let name = format!("recording-{}", timestamp());
+        self.target = match format {
+            RecordFormat::Frames => self.dir.join(name),
+            RecordFormat::Gif => self.dir.join(name + ".gif"),
+        };
+        let dir = if format == RecordFormat::Frames {
+            &self.target
+        } else {
+            &self.dir
+        };
+        fs::create_dir_all(dir).with_context(|| format!("Could not create {}", dir.display()))?;
+        let (frames, received) = channel();
+        let (target, scale, fps) = (self.target.clone(), self.scale, self.fps);
+        let thread = thread::Builder::new()
+            .name(String::from("recording"))
+            .spawn(move || write_recording(format, target, scale, fps, received))
+            .context("could not start writing the recording")?;
+        self.writer = Some(Writer { frames, thread });
+        self.remaining = seconds;
+        // Capture the very first frame
+        self.since_last = 1. / self.fps;
+        Ok(())
+    }
+
+    /// Grab the texture if a frame is due, and finish the recording when its time is up.
+    /// Returns the recording's path once it is written.
+    pub fn capture(&mut self, texture: Texture2D) -> Result<Option<PathBuf>> {
+        self.add_frame(|| texture.get_texture_data())
+    }
+
+    /// `capture` without a GPU. A slow frame that spans several capture intervals is repeated,
+    /// so the recording plays back at the speed the game ran.
+    fn add_frame(&mut self, grab: impl FnOnce() -> Image) -> Result<Option<PathBuf>> {
+        let Some(writer) = &self.writer else {
+            return Ok(None);
+        };
+        let frame_time = 1. / self.fps;
+        let due = (self.since_last / frame_time).floor();
+        if due >= 1. {
+            self.since_last -= due * frame_time;
+            let image = grab();
+            for _ in 1..due as usize {
+                if writer.frames.send(image.clone()).is_err() {
+                    break;
+                }
+            }
+            // The writer only hangs up when it failed
+            if writer.frames.send(image).is_err() {
+                return self.finish().map(Some);
+            }
+        }
+        if self.remaining <= 0. {
+            return self.finish().map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Stop recording, and wait for what was recorded to be written
+    pub fn finish(&mut self) -> Result<PathBuf> {
+        self.remaining = 0.;
+        if let Some(Writer { frames, thread }) = self.writer.take() {
+            drop(frames);
+            thread
+                .join()
+                .map_err(|_| anyhow!("writing the recording panicked"))?
+                .with_context(|| format!("Could not save {}", self.target.display()))?;
+        }
+        Ok(self.target.clone())
+    }
+}
+
+impl Updateable for Recorder {
+    fn update(&mut self, delta_time: f32) {
+        if self.is_recording() {
+            self.since_last += delta_time;
+            self.remaining -= delta_time;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::gif::GifDecoder, AnimationDecoder};
+
+    use super::*;
+
+    fn pixel() -> Image {
+        Image {
+            bytes: vec![255, 0, 0, 255],
+            width: 1,
+            height: 1,
+        }
+    }
+
+    /// 4 frames, the one after a quarter of a second twice
+    fn record(name: &str, format: RecordFormat) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cowshmup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut recorder = Recorder {
+            dir: dir.clone(),
+            scale: 2,
+            fps: 10.,
+            ..Default::default()
+        };
+        recorder.start(0.3, format).unwrap();
+        let mut saved = None;
+        for delta_time in [0., 0.25, 0.1] {
+            recorder.update(delta_time);
+            assert!(saved.is_none());
+            saved = recorder.add_frame(pixel).unwrap();
+        }
+        assert!(!recorder.is_recording());
+        saved.unwrap()
+    }
+
+    #[test]
+    fn slow_frames_are_repeated() {
+        let path = record("frames", RecordFormat::Frames);
+        let mut names: Vec<_> = fs::read_dir(&path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "frame-00000.png",
+                "frame-00001.png",
+                "frame-00002.png",
+                "frame-00003.png"
+            ]
+        );
+        let frame = image::open(path.join("frame-00003.png")).unwrap();
+        assert_eq!((frame.width(), frame.height()), (2, 2));
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn gifs_are_written_while_recording() {
+        let path = record("gif", RecordFormat::Gif);
+        let decoder = GifDecoder::new(File::open(&path).unwrap()).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].buffer().dimensions(), (2, 2));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,7 +1,11 @@
-use crate::{Accel, CenterPt, Size, TopLeftPt, Velocity};
-use macroquad::{
-    prelude::Color,
-    shapes::{draw_circle, draw_line},
+mod aseprite;
+mod sprite;
+use crate::{updateable::Updateable, utils::GameColor, Accel, CenterPt, Size, TopLeftPt, Velocity};
+use macroquad::shapes::{draw_circle, draw_line};
+pub(crate) use sprite::load_png;
+pub use sprite::{
+    Animation, AnimationDesc, AnimationFrame, AnimationMode, FrameRect, PngTexture, Sprite,
+    SpriteFrame, SpriteSheet, SpriteSheetDesc,
 };
 use std::fmt::Debug;
 
@@ -50,47 +54,47 @@ pub struct Line {
     y1: f32,
     x2: f32,
     y2: f32,
-    color: Color,
+    color: GameColor,
 }
 
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct Circle {
     center: CenterPt,
     radius: f32,
-    color: Color,
+    color: GameColor,
 }
 
 impl Line {
-    pub fn new(x1: f32, y1: f32, x2: f32, y2: f32, color: Color) -> Self {
+    pub fn new(x1: f32, y1: f32, x2: f32, y2: f32, color: impl Into<GameColor>) -> Self {
         Self {
             x1,
             y1,
             x2,
             y2,
-            color,
+            color: color.into(),
         }
     }
 }
 
 impl Drawable for Line {
     fn draw(&self) {
-        draw_line(self.x1, self.y1, self.x2, self.y2, 1.0, self.color);
+        draw_line(self.x1, self.y1, self.x2, self.y2, 1.0, self.color.into());
     }
 }
 
 impl Circle {
-    pub fn new(center: CenterPt, r: f32, color: Color) -> Self {
+    pub fn new(center: CenterPt, r: f32, color: impl Into<GameColor>) -> Self {
         Self {
             center,
             radius: r,
-            color,
+            color: color.into(),
         }
     }
 }
 
 impl Drawable for Circle {
     fn draw(&self) {
-        draw_circle(self.center.0, self.center.1, self.radius, self.color)
+        draw_circle(self.center.0, self.center.1, self.radius, self.color.into())
     }
 }
 
@@ -98,6 +102,7 @@ impl Drawable for Circle {
 pub enum Graphic {
     Line(Line),
     Circle(Circle),
+    Sprite(Sprite),
 }
 
 impl Drawable for Graphic {
@@ -105,16 +110,29 @@ impl Drawable for Graphic {
         match self {
             Graphic::Line(l) => l.draw(),
             Graphic::Circle(c) => c.draw(),
+            Graphic::Sprite(s) => s.draw(),
+        }
+    }
+}
+
+impl Updateable for Graphic {
+    fn update(&mut self, delta_time: f32) {
+        if let Graphic::Sprite(s) = self {
+            s.update(delta_time)
         }
     }
 }
 
 impl Graphic {
-    pub fn line(x1: f32, y1: f32, x2: f32, y2: f32, color: Color) -> Self {
+    pub fn line(x1: f32, y1: f32, x2: f32, y2: f32, color: impl Into<GameColor>) -> Self {
         Graphic::Line(Line::new(x1, y1, x2, y2, color))
     }
 
-    pub fn circle(center: CenterPt, radius: f32, color: Color) -> Self {
+    pub fn circle(center: CenterPt, radius: f32, color: impl Into<GameColor>) -> Self {
         Graphic::Circle(Circle::new(center, radius, color))
     }
+
+    pub fn sprite(sprite: Sprite) -> Self {
+        Graphic::Sprite(sprite)
+    }
 }
@@ -0,0 +1,457 @@
+//! Import sprite sheets exported from Aseprite (File > Export Sprite Sheet, JSON data). Both the
+//! "Hash" and "Array" layouts are understood.
+//!
+//! Frame tags become animations. Slices become per frame hitboxes, or attachment points when the
+//! slice has a pivot set.
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{bail, Result};
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+use super::sprite::{
+    AnimationDesc, AnimationFrame, AnimationMode, FrameRect, SpriteFrame, SpriteSheetDesc,
+};
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+struct AseRect {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+struct AseSize {
+    w: f32,
+    h: f32,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+struct AsePoint {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AseFrame {
+    #[serde(default)]
+    filename: String,
+    frame: AseRect,
+    #[serde(default)]
+    rotated: bool,
+    sprite_source_size: AseRect,
+    source_size: AseSize,
+    /// milliseconds
+    duration: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AseDirection {
+    #[default]
+    Forward,
+    Reverse,
+    Pingpong,
+    #[serde(rename = "pingpong_reverse")]
+    PingpongReverse,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AseTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: AseDirection,
+    /// Aseprite writes this as a string, and only when the tag does not repeat forever
+    #[serde(default)]
+    repeat: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AseSliceKey {
+    frame: usize,
+    bounds: AseRect,
+    #[serde(default)]
+    pivot: Option<AsePoint>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AseSlice {
+    name: String,
+    keys: Vec<AseSliceKey>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AseMeta {
+    image: String,
+    #[serde(default)]
+    frame_tags: Vec<AseTag>,
+    #[serde(default)]
+    slices: Vec<AseSlice>,
+}
+
+/// The "Hash" layout is an object keyed by file name. The key order is the frame order, so it has
+/// to be kept rather than collected into a map.
+#[derive(Debug, Clone)]
+struct AseFrames(Vec<AseFrame>);
+
+impl<'de> Deserialize<'de> for AseFrames {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FramesVisitor;
+
+        impl<'de> Visitor<'de> for FramesVisitor {
+            type Value = AseFrames;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an array or object of frames")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut frames = Vec::new();
+                while let Some(frame) = seq.next_element::<AseFrame>()? {
+                    frames.push(frame);
+                }
+                Ok(AseFrames(frames))
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut frames = Vec::new();
+                while let Some((filename, frame)) = map.next_entry::<String, AseFrame>()? {
+                    frames.push(AseFrame { filename, ..frame });
+                }
+                Ok(AseFrames(frames))
+            }
+        }
+
+        deserializer.deserialize_any(FramesVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AseSheet {
+    frames: AseFrames,
+    meta: AseMeta,
+}
+
+/// Does this JSON look like it came out of Aseprite, rather than being a `SpriteSheetDesc`?
+pub fn is_aseprite_json(value: &serde_json::Value) -> bool {
+    value.get("meta").is_some() && value.get("frames").is_some()
+}
+
+impl SpriteSheetDesc {
+    pub fn from_aseprite(value: serde_json::Value) -> Result<Self> {
+        let sheet: AseSheet = serde_json::from_value(value)?;
+        let frames = sheet.frames.0;
+        if let Some(rotated) = frames.iter().find(|f| f.rotated) {
+            bail!(
+                "frame {} is rotated, export without \"rotated\" sprites",
+                rotated.filename
+            );
+        }
+
+        let names = frames
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                if f.filename.is_empty() {
+                    i.to_string()
+                } else {
+                    f.filename.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut desc = SpriteSheetDesc {
+            image: sheet.meta.image,
+            ..Default::default()
+        };
+
+        for (i, f) in frames.iter().enumerate() {
+            // Trimmed frames are smaller than the source, so everything that is relative to the
+            // source (pivot, slices) has to be moved by the trimmed amount
+            let trim = f.sprite_source_size;
+            let mut sprite_frame = SpriteFrame {
+                rect: FrameRect {
+                    x: f.frame.x,
+                    y: f.frame.y,
+                    w: f.frame.w,
+                    h: f.frame.h,
+                },
+                pivot: Some((f.source_size.w / 2. - trim.x, f.source_size.h / 2. - trim.y)),
+                ..Default::default()
+            };
+            for slice in sheet.meta.slices.iter() {
+                // A key applies from its frame until the next key
+                let Some(key) = slice.keys.iter().rev().find(|k| k.frame <= i) else {
+                    continue;
+                };
+                let b = key.bounds;
+                if let Some(pivot) = key.pivot {
+                    sprite_frame.points.insert(
+                        slice.name.clone(),
+                        (b.x + pivot.x - trim.x, b.y + pivot.y - trim.y),
+                    );
+                } else {
+                    sprite_frame.hitboxes.insert(
+                        slice.name.clone(),
+                        FrameRect {
+                            x: b.x - trim.x,
+                            y: b.y - trim.y,
+                            w: b.w,
+                            h: b.h,
+                        },
+                    );
+                }
+            }
+            desc.frames.insert(names[i].clone(), sprite_frame);
+        }
+
+        let anim_frame = |i: usize| AnimationFrame {
+            frame: names[i].clone(),
+            duration: frames[i].duration / 1000.,
+        };
+        let mut animations = BTreeMap::new();
+        for tag in sheet.meta.frame_tags.iter() {
+            if tag.from > tag.to || tag.to >= frames.len() {
+                bail!("tag {} is out of range", tag.name);
+            }
+            let forward = (tag.from..=tag.to).map(anim_frame).collect::<Vec<_>>();
+            let reverse = forward.iter().rev().cloned().collect::<Vec<_>>();
+            let (mut sequence, mode) = match tag.direction {
+                AseDirection::Forward => (forward, AnimationMode::Loop),
+                AseDirection::Reverse => (reverse, AnimationMode::Loop),
+                AseDirection::Pingpong => (forward, AnimationMode::PingPong),
+                AseDirection::PingpongReverse => (reverse, AnimationMode::PingPong),
+            };
+            let repeat = tag
+                .repeat
+                .as_deref()
+                .and_then(|r| r.parse::<usize>().ok())
+                .filter(|r| *r > 0);
+            let mode = if let Some(repeat) = repeat {
+                // A tag that only plays a few times is unrolled into a single play through. Like
+                // in Aseprite, every pass of a ping-pong counts as a repeat, and the frame it
+                // turns on is only shown once.
+                let passes = [sequence.clone(), sequence.iter().rev().cloned().collect()];
+                for pass in 1..repeat {
+                    if mode == AnimationMode::PingPong {
+                        sequence.extend(passes[pass % 2].iter().skip(1).cloned());
+                    } else {
+                        sequence.extend(passes[0].iter().cloned());
+                    }
+                }
+                AnimationMode::Once
+            } else {
+                mode
+            };
+            animations.insert(
+                tag.name.clone(),
+                AnimationDesc {
+                    frames: sequence,
+                    mode,
+                    ..Default::default()
+                },
+            );
+        }
+        if animations.is_empty() && !frames.is_empty() {
+            animations.insert(
+                String::from("default"),
+                AnimationDesc {
+                    frames: (0..frames.len()).map(anim_frame).collect(),
+                    ..Default::default()
+                },
+            );
+        }
+        desc.animations = animations;
+        Ok(desc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn frame(x: f32, duration: f32) -> serde_json::Value {
+        json!({
+            "frame": {"x": x, "y": 0, "w": 16, "h": 16},
+            "rotated": false,
+            "trimmed": false,
+            "spriteSourceSize": {"x": 0, "y": 0, "w": 16, "h": 16},
+            "sourceSize": {"w": 16, "h": 16},
+            "duration": duration
+        })
+    }
+
+    /// Four frames in the "Array" layout
+    fn sheet(tags: serde_json::Value, slices: serde_json::Value) -> SpriteSheetDesc {
+        let frames = (0..4)
+            .map(|i| frame(i as f32 * 16., 100. * (i + 1) as f32))
+            .collect::<Vec<_>>();
+        SpriteSheetDesc::from_aseprite(json!({
+            "frames": frames,
+            "meta": {"image": "ship.png", "frameTags": tags, "slices": slices}
+        }))
+        .unwrap()
+    }
+
+    fn tag(direction: &str, repeat: Option<&str>) -> serde_json::Value {
+        let mut tag = json!({"name": "tag", "from": 0, "to": 3, "direction": direction});
+        if let Some(repeat) = repeat {
+            tag["repeat"] = json!(repeat);
+        }
+        json!([tag])
+    }
+
+    /// The frames of the only animation, and its mode
+    fn played(direction: &str, repeat: Option<&str>) -> (Vec<String>, AnimationMode) {
+        let desc = sheet(tag(direction, repeat), json!([]));
+        let animation = &desc.animations["tag"];
+        let frames = animation.frames.iter().map(|f| f.frame.clone()).collect();
+        (frames, animation.mode)
+    }
+
+    fn names(frames: &[usize]) -> Vec<String> {
+        frames.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn hash_layout_keeps_frame_order_and_trims() {
+        let mut trimmed = frame(16., 50.);
+        trimmed["spriteSourceSize"] = json!({"x": 2, "y": 3, "w": 12, "h": 10});
+        let desc = SpriteSheetDesc::from_aseprite(json!({
+            "frames": {"ship 1.aseprite": frame(0., 100.), "ship 0.aseprite": trimmed},
+            "meta": {
+                "image": "ship.png",
+                "slices": [{"name": "gun", "keys": [
+                    {"frame": 0, "bounds": {"x": 4, "y": 5, "w": 1, "h": 1}, "pivot": {"x": 0, "y": 1}}
+                ]}]
+            }
+        }))
+        .unwrap();
+        assert_eq!(desc.image, "ship.png");
+        let frames = &desc.animations["default"].frames;
+        assert_eq!(
+            frames.iter().map(|f| f.frame.as_str()).collect::<Vec<_>>(),
+            ["ship 1.aseprite", "ship 0.aseprite"]
+        );
+        assert_eq!(frames[0].duration, 0.1);
+        assert_eq!(frames[1].duration, 0.05);
+        let frame = &desc.frames["ship 0.aseprite"];
+        assert_eq!(frame.rect.x, 16.);
+        // Pivot and slices move with the trim
+        assert_eq!(frame.pivot, Some((6., 5.)));
+        assert_eq!(frame.points["gun"], (2., 3.));
+        assert_eq!(desc.frames["ship 1.aseprite"].points["gun"], (4., 6.));
+    }
+
+    #[test]
+    fn slices_become_hitboxes_and_points() {
+        let slices = json!([
+            {"name": "body", "keys": [
+                {"frame": 0, "bounds": {"x": 2, "y": 2, "w": 12, "h": 12}},
+                {"frame": 2, "bounds": {"x": 4, "y": 4, "w": 8, "h": 8}}
+            ]},
+            {"name": "late", "keys": [{"frame": 3, "bounds": {"x": 0, "y": 0, "w": 1, "h": 1}}]}
+        ]);
+        let desc = sheet(json!([]), slices);
+        let body = |i: usize| desc.frames[&i.to_string()].hitboxes["body"].w;
+        assert_eq!([body(0), body(1), body(2), body(3)], [12., 12., 8., 8.]);
+        assert!(!desc.frames["2"].hitboxes.contains_key("late"));
+        assert!(desc.frames["3"].hitboxes.contains_key("late"));
+        assert!(desc.frames["0"].points.is_empty());
+    }
+
+    #[test]
+    fn tags_become_animations() {
+        assert_eq!(
+            played("forward", None),
+            (names(&[0, 1, 2, 3]), AnimationMode::Loop)
+        );
+        assert_eq!(
+            played("reverse", None),
+            (names(&[3, 2, 1, 0]), AnimationMode::Loop)
+        );
+        assert_eq!(
+            played("pingpong", None),
+            (names(&[0, 1, 2, 3]), AnimationMode::PingPong)
+        );
+        assert_eq!(
+            played("pingpong_reverse", None),
+            (names(&[3, 2, 1, 0]), AnimationMode::PingPong)
+        );
+        let desc = sheet(tag("forward", None), json!([]));
+        let durations = desc.animations["tag"]
+            .frames
+            .iter()
+            .map(|f| f.duration)
+            .collect::<Vec<_>>();
+        assert_eq!(durations, [0.1, 0.2, 0.3, 0.4]);
+    }
+
+    #[test]
+    fn repeats_are_unrolled() {
+        assert_eq!(
+            played("forward", Some("2")),
+            (names(&[0, 1, 2, 3, 0, 1, 2, 3]), AnimationMode::Once)
+        );
+        assert_eq!(
+            played("reverse", Some("1")),
+            (names(&[3, 2, 1, 0]), AnimationMode::Once)
+        );
+        // Each way through a ping-pong is one repeat
+        assert_eq!(
+            played("pingpong", Some("1")),
+            (names(&[0, 1, 2, 3]), AnimationMode::Once)
+        );
+        assert_eq!(
+            played("pingpong", Some("2")),
+            (names(&[0, 1, 2, 3, 2, 1, 0]), AnimationMode::Once)
+        );
+        assert_eq!(
+            played("pingpong", Some("3")),
+            (names(&[0, 1, 2, 3, 2, 1, 0, 1, 2, 3]), AnimationMode::Once)
+        );
+        assert_eq!(
+            played("pingpong_reverse", Some("2")),
+            (names(&[3, 2, 1, 0, 1, 2, 3]), AnimationMode::Once)
+        );
+        // Aseprite leaves out the repeat when it is forever
+        assert_eq!(played("pingpong", Some("0")).1, AnimationMode::PingPong);
+    }
+
+    #[test]
+    fn bad_sheets_are_rejected() {
+        let mut rotated = frame(0., 100.);
+        rotated["rotated"] = json!(true);
+        let err = SpriteSheetDesc::from_aseprite(json!({
+            "frames": [rotated],
+            "meta": {"image": "ship.png"}
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("rotated"));
+
+        let err = SpriteSheetDesc::from_aseprite(json!({
+            "frames": [frame(0., 100.)],
+            "meta": {"image": "ship.png", "frameTags": [{"name": "walk", "from": 0, "to": 4}]}
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("tag walk is out of range"));
+    }
+}
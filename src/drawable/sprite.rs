@@ -0,0 +1,512 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use macroquad::{
+    prelude::{Color, Rect, Vec2, WHITE},
+    texture::{draw_texture_ex, DrawTextureParams, FilterMode, Texture2D},
+};
+use serde::{Deserialize, Serialize};
+
+use super::aseprite::is_aseprite_json;
+use crate::{
+    drawable::Drawable,
+    migrate::{self, Versioned},
+    updateable::Updateable,
+    CenterPt, Rc,
+};
+
+/// A rectangle, in pixels, within a sprite sheet
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameRect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl From<FrameRect> for Rect {
+    fn from(value: FrameRect) -> Self {
+        Rect::new(value.x, value.y, value.w, value.h)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpriteFrame {
+    pub rect: FrameRect,
+    /// Where the frame is anchored, relative to its top left corner. Defaults to the center of
+    /// the frame
+    pub pivot: Option<(f32, f32)>,
+    /// Named areas, relative to the top left corner of the frame
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub hitboxes: BTreeMap<String, FrameRect>,
+    /// Named points (gun muzzles, engine flames...), relative to the top left corner of the frame
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub points: BTreeMap<String, (f32, f32)>,
+}
+
+impl SpriteFrame {
+    pub fn pivot(&self) -> (f32, f32) {
+        self.pivot.unwrap_or((self.rect.w / 2., self.rect.h / 2.))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnimationMode {
+    #[default]
+    Loop,
+    /// Play forward, then backward, then forward again...
+    PingPong,
+    /// Play once and hold the last frame
+    Once,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationFrame {
+    /// Name of the frame in the sprite sheet
+    pub frame: String,
+    /// How long the frame is shown, in seconds
+    pub duration: f32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnimationDesc {
+    pub frames: Vec<AnimationFrame>,
+    pub mode: AnimationMode,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+/// The part of a sprite sheet that lives in a YAML or JSON file next to the image
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpriteSheetDesc {
+    /// Image file, relative to the description file
+    pub image: String,
+    pub frames: BTreeMap<String, SpriteFrame>,
+    pub animations: BTreeMap<String, AnimationDesc>,
+}
+
+impl Versioned for SpriteSheetDesc {}
+
+impl SpriteSheetDesc {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path).with_context(|| format!("Could not open {}", path.display()))?;
+        let desc = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => {
+                let value = serde_json::from_slice::<serde_json::Value>(&data)
+                    .with_context(|| format!("could not parse {}", path.display()))?;
+                if is_aseprite_json(&value) {
+                    Self::from_aseprite(value)
+                } else {
+                    serde_json::from_value::<Self>(value).map_err(|e| e.into())
+                }
+                .with_context(|| format!("could not parse {}", path.display()))?
+            }
+            _ => migrate::from_slice::<Self>(&data)
+                .with_context(|| format!("could not parse {}", path.display()))?,
+        };
+        desc.validate()
+            .with_context(|| format!("could not parse {}", path.display()))?;
+        Ok(desc)
+    }
+
+    /// Every animation frame has to last for some time
+    fn validate(&self) -> Result<()> {
+        for (name, animation) in &self.animations {
+            for (i, frame) in animation.frames.iter().enumerate() {
+                if frame.duration <= 0. {
+                    bail!(
+                        "frame {} of animation {} has duration {}, it has to be more than 0",
+                        i,
+                        name,
+                        frame.duration
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A texture atlas and the named frames and animations within it
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+    texture: Texture2D,
+    desc: SpriteSheetDesc,
+}
+
+impl SpriteSheet {
+    pub fn new(texture: Texture2D, desc: SpriteSheetDesc) -> Self {
+        texture.set_filter(FilterMode::Nearest);
+        Self { texture, desc }
+    }
+
+    /// Load a sprite sheet description (`.yaml`, `.yml` or `.json`) and the PNG it refers to
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let desc = SpriteSheetDesc::load(path)?;
+        let image_path = path.with_file_name(&desc.image);
+        let texture = load_png(&image_path)?;
+        Ok(Self::new(texture, desc))
+    }
+
+    pub fn texture(&self) -> Texture2D {
+        self.texture
+    }
+
+    pub fn frame(&self, name: &str) -> Option<&SpriteFrame> {
+        self.desc.frames.get(name)
+    }
+
+    pub fn animation(&self, name: &str) -> Option<Animation> {
+        self.desc.animations.get(name).cloned().map(Animation::new)
+    }
+
+    pub fn desc(&self) -> &SpriteSheetDesc {
+        &self.desc
+    }
+}
+
+pub(crate) fn load_png(path: &Path) -> Result<Texture2D> {
+    let bytes = fs::read(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let img = image::load_from_memory_with_format(&bytes, image::ImageFormat::Png)
+        .with_context(|| format!("could not decode {}", path.display()))?
+        .to_rgba8();
+    let texture = Texture2D::from_rgba8(img.width() as u16, img.height() as u16, img.as_raw());
+    texture.set_filter(FilterMode::Nearest);
+    Ok(texture)
+}
+
+/// A texture loaded from a PNG on its own, like a background image. Load it through `assets` to
+/// share it, the texture is deleted with the last handle.
+#[derive(Debug)]
+pub struct PngTexture(Texture2D);
+
+impl PngTexture {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        load_png(path.as_ref()).map(Self)
+    }
+
+    pub fn texture(&self) -> Texture2D {
+        self.0
+    }
+}
+
+impl Drop for PngTexture {
+    fn drop(&mut self) {
+        self.0.delete();
+    }
+}
+
+/// Plays an `AnimationDesc`, keeping track of the current frame
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Animation {
+    desc: AnimationDesc,
+    index: usize,
+    time: f32,
+    reverse: bool,
+    finished: bool,
+}
+
+impl Animation {
+    pub fn new(desc: AnimationDesc) -> Self {
+        Self {
+            desc,
+            ..Default::default()
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.index = 0;
+        self.time = 0.;
+        self.reverse = false;
+        self.finished = false;
+    }
+
+    /// Name of the frame that should currently be shown
+    pub fn current_frame(&self) -> Option<&str> {
+        self.desc.frames.get(self.index).map(|f| f.frame.as_str())
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.index
+    }
+
+    /// Only `AnimationMode::Once` animations ever finish
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn flip_x(&self) -> bool {
+        self.desc.flip_x
+    }
+
+    pub fn flip_y(&self) -> bool {
+        self.desc.flip_y
+    }
+
+    fn advance(&mut self) {
+        let last = self.desc.frames.len() - 1;
+        match self.desc.mode {
+            AnimationMode::Loop => {
+                self.index = if self.index >= last {
+                    0
+                } else {
+                    self.index + 1
+                }
+            }
+            AnimationMode::Once => {
+                if self.index >= last {
+                    self.finished = true;
+                } else {
+                    self.index += 1;
+                }
+            }
+            AnimationMode::PingPong => {
+                if last == 0 {
+                    return;
+                }
+                if self.reverse && self.index == 0 {
+                    self.reverse = false;
+                } else if !self.reverse && self.index >= last {
+                    self.reverse = true;
+                }
+                if self.reverse {
+                    self.index -= 1;
+                } else {
+                    self.index += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Updateable for Animation {
+    fn update(&mut self, delta_time: f32) {
+        if self.desc.frames.is_empty() || self.finished {
+            return;
+        }
+        self.time += delta_time;
+        // Zero length frames are skipped, counted so all zero frames can not loop forever
+        let mut skipped = 0;
+        while !self.finished {
+            let duration = self.desc.frames[self.index].duration;
+            if duration <= 0. {
+                if skipped >= self.desc.frames.len() {
+                    break;
+                }
+                skipped += 1;
+            } else if self.time < duration {
+                break;
+            } else {
+                self.time -= duration;
+                skipped = 0;
+            }
+            self.advance();
+        }
+    }
+}
+
+/// A (possibly animated) frame from a sprite sheet, drawn snapped to whole pixels
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    sheet: Rc<SpriteSheet>,
+    frame: String,
+    center: CenterPt,
+    color: Color,
+    flip_x: bool,
+    flip_y: bool,
+    animation: Option<Animation>,
+}
+
+impl Sprite {
+    pub fn new(sheet: Rc<SpriteSheet>, frame: &str, center: CenterPt) -> Self {
+        Self {
+            sheet,
+            frame: String::from(frame),
+            center,
+            color: WHITE,
+            flip_x: false,
+            flip_y: false,
+            animation: None,
+        }
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_flip(mut self, flip_x: bool, flip_y: bool) -> Self {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+        self
+    }
+
+    pub fn with_animation(mut self, animation: Animation) -> Self {
+        self.animation = Some(animation);
+        self
+    }
+
+    /// Start playing the named animation from the sprite sheet, if there is one
+    pub fn play(&mut self, name: &str) {
+        self.animation = self.sheet.animation(name);
+    }
+
+    pub fn animation(&self) -> Option<&Animation> {
+        self.animation.as_ref()
+    }
+
+    pub fn set_flip(&mut self, flip_x: bool, flip_y: bool) {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+    }
+
+    pub fn current_frame(&self) -> &str {
+        self.animation
+            .as_ref()
+            .and_then(|a| a.current_frame())
+            .unwrap_or(&self.frame)
+    }
+
+    fn flips(&self) -> (bool, bool) {
+        let (anim_flip_x, anim_flip_y) = self
+            .animation
+            .as_ref()
+            .map_or((false, false), |a| (a.flip_x(), a.flip_y()));
+        (self.flip_x != anim_flip_x, self.flip_y != anim_flip_y)
+    }
+
+    /// Top left corner of the current frame, in world space
+    fn origin(&self, frame: &SpriteFrame) -> (f32, f32) {
+        let (flip_x, flip_y) = self.flips();
+        let (px, py) = frame.pivot();
+        // Mirror the pivot along with the image so flipped sprites stay anchored
+        let px = if flip_x { frame.rect.w - px } else { px };
+        let py = if flip_y { frame.rect.h - py } else { py };
+        let (cx, cy) = self.center.into();
+        ((cx - px).round(), (cy - py).round())
+    }
+
+    /// A named hitbox of the current frame, in world space
+    pub fn hitbox(&self, name: &str) -> Option<Rect> {
+        let frame = self.sheet.frame(self.current_frame())?;
+        let hb = frame.hitboxes.get(name)?;
+        let (flip_x, flip_y) = self.flips();
+        let (ox, oy) = self.origin(frame);
+        let x = if flip_x {
+            frame.rect.w - hb.x - hb.w
+        } else {
+            hb.x
+        };
+        let y = if flip_y {
+            frame.rect.h - hb.y - hb.h
+        } else {
+            hb.y
+        };
+        Some(Rect::new(ox + x, oy + y, hb.w, hb.h))
+    }
+
+    /// A named attachment point of the current frame, in world space
+    pub fn attachment(&self, name: &str) -> Option<CenterPt> {
+        let frame = self.sheet.frame(self.current_frame())?;
+        let (x, y) = *frame.points.get(name)?;
+        let (flip_x, flip_y) = self.flips();
+        let (ox, oy) = self.origin(frame);
+        let x = if flip_x { frame.rect.w - x } else { x };
+        let y = if flip_y { frame.rect.h - y } else { y };
+        Some(CenterPt::new(ox + x, oy + y))
+    }
+}
+
+crate::impl_pts!(center Sprite);
+
+impl Drawable for Sprite {
+    fn draw(&self) {
+        let Some(frame) = self.sheet.frame(self.current_frame()) else {
+            return;
+        };
+        let (flip_x, flip_y) = self.flips();
+        let (x, y) = self.origin(frame);
+        draw_texture_ex(
+            self.sheet.texture(),
+            x,
+            y,
+            self.color,
+            DrawTextureParams {
+                dest_size: Some(Vec2::new(frame.rect.w, frame.rect.h)),
+                source: Some(frame.rect.into()),
+                flip_x,
+                flip_y,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+impl Updateable for Sprite {
+    fn update(&mut self, delta_time: f32) {
+        if let Some(animation) = &mut self.animation {
+            animation.update(delta_time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(durations: &[f32], mode: AnimationMode) -> Animation {
+        Animation::new(AnimationDesc {
+            frames: durations
+                .iter()
+                .enumerate()
+                .map(|(i, duration)| AnimationFrame {
+                    frame: i.to_string(),
+                    duration: *duration,
+                })
+                .collect(),
+            mode,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn zero_length_frames_are_skipped() {
+        let mut anim = animation(&[0.1, 0., 0.1], AnimationMode::Loop);
+        anim.update(0.15);
+        assert_eq!(anim.current_frame(), Some("2"));
+        anim.update(0.1);
+        assert_eq!(anim.current_frame(), Some("0"));
+
+        let mut anim = animation(&[0.1, 0.], AnimationMode::Once);
+        anim.update(0.15);
+        assert!(anim.is_finished());
+    }
+
+    #[test]
+    fn all_zero_frames_do_not_hang() {
+        for mode in [AnimationMode::Loop, AnimationMode::PingPong] {
+            let mut anim = animation(&[0., 0., -1.], mode);
+            anim.update(1.);
+            anim.update(1.);
+        }
+        let mut anim = animation(&[0.], AnimationMode::PingPong);
+        anim.update(1.);
+        assert_eq!(anim.current_frame(), Some("0"));
+    }
+
+    #[test]
+    fn zero_length_frames_are_rejected() {
+        let mut desc = SpriteSheetDesc::default();
+        desc.animations.insert(
+            "walk".to_string(),
+            animation(&[0.1, 0.], AnimationMode::Loop).desc,
+        );
+        let err = desc.validate().unwrap_err();
+        assert!(err.to_string().contains("frame 1 of animation walk"));
+    }
+}
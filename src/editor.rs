@@ -3,7 +3,22 @@ use std::collections::HashMap;
 use crate::preview::{Preview, PreviewBuildableData};
 use crate::State;
 use crate::{game_data::GameData, prelude::*};
-use cowshmup::particle::ExplosionBuilder;
+use cowshmup::{
+    assets,
+    audio::SoundEvent,
+    background::BackgroundBuilder,
+    capture::{CaptureRequest, RecordFormat},
+    layer::LayerId,
+    migrate::Versioned,
+    palette::{self, PaletteRemap},
+    particle::ExplosionBuilder,
+    pattern::PatternPreview,
+    retro_camera::CameraEffect,
+    settings::{AspectMode, Filter, DEFAULT_SETTINGS_FILE},
+    sfx::SfxParams,
+    utils,
+    widgets::color_picker::color_edit_palette_button,
+};
 use macroquad::rand;
 
 /// Editor represents an editor for various ascpects of the game. An editor can be serialized so
@@ -17,6 +32,15 @@ pub struct Editor {
     pub re_add_objects_to_game: bool,
     pub show_debug: bool,
     pub show_properties: bool,
+    /// Fade to black amount, to check how things look with the palette remapped
+    #[serde(skip)]
+    fade: f32,
+    record_seconds: f32,
+    record_format: RecordFormat,
+    /// Sound the debug panel plays, from the sound bank
+    sound_name: String,
+    /// Drop table the debug panel rolls
+    drop_table: String,
 
     pub previews: HashMap<EditorPreview, PreviewMeta>,
 }
@@ -31,17 +55,26 @@ pub struct PreviewMeta {
 #[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord, Clone, Copy)]
 pub enum EditorPreview {
     Explosion,
+    Background,
+    Sfx,
+    Pattern,
 }
 
 impl EditorPreview {
     fn create_preview(&self) -> Box<dyn Preview> {
         match self {
             EditorPreview::Explosion => Box::<PreviewBuildableData<ExplosionBuilder>>::default(),
+            EditorPreview::Background => Box::<PreviewBuildableData<BackgroundBuilder>>::default(),
+            EditorPreview::Sfx => Box::<PreviewBuildableData<SfxParams>>::default(),
+            EditorPreview::Pattern => Box::<PreviewBuildableData<PatternPreview>>::default(),
         }
     }
     fn get_name(&self) -> &str {
         match self {
             EditorPreview::Explosion => "Explosion Preview",
+            EditorPreview::Background => "Background Preview",
+            EditorPreview::Sfx => "Sound Effect Preview",
+            EditorPreview::Pattern => "Bullet Pattern Preview",
         }
     }
 }
@@ -57,15 +90,21 @@ impl std::fmt::Debug for Editor {
     }
 }
 
+impl Versioned for Editor {}
+
 impl Editor {
     pub fn init(&mut self) {
         self.re_add_objects_to_game = true;
-        self.previews
-            .entry(EditorPreview::Explosion)
-            .or_insert_with(PreviewMeta::default);
+        self.previews.entry(EditorPreview::Explosion).or_default();
+        self.previews.entry(EditorPreview::Background).or_default();
+        self.previews.entry(EditorPreview::Sfx).or_default();
+        self.previews.entry(EditorPreview::Pattern).or_default();
         if self.seed.is_none() {
             self.seed = Some(69420);
         }
+        if self.record_seconds <= 0. {
+            self.record_seconds = 5.;
+        }
     }
 
     pub fn update_egui(&mut self, egui_ctx: &egui::Context, game: &mut GameData) {
@@ -100,6 +139,243 @@ impl Editor {
         ui.label(format!("FPS {}", game.fps));
         ui.label(format!("TIME {}", game.time));
         ui.label(format!("FT {}", game.frame_time));
+        let view = game.world.camera().view();
+        ui.label(format!("VIEW {} {}", view.x, view.y));
+        if let Some(p) = game.mouse_world {
+            ui.label(format!("MOUSE {} {}", p.x, p.y));
+        }
+        ui.separator();
+        ui.label(format!(
+            "Palette {}",
+            palette::with_active(|p| p.name.clone())
+        ));
+        let fade = egui::Slider::new(&mut self.fade, 0.0..=1.0).text("Fade");
+        if ui.add(fade).changed() {
+            let remap = palette::with_active(|p| PaletteRemap::fade(p, self.fade));
+            palette::set_remap(remap);
+        }
+        ui.separator();
+        ui.label("Camera Effects");
+        ui.horizontal(|ui| {
+            if ui.button("Shake").clicked() {
+                game.world.camera_effect(CameraEffect::Shake(0.5));
+            }
+            if ui.button("Hit-stop").clicked() {
+                game.world.camera_effect(CameraEffect::HitStop(0.2));
+            }
+            if ui.button("Flash").clicked() {
+                game.world.camera_effect(CameraEffect::Flash {
+                    color: utils::WHITE,
+                    duration: 0.25,
+                });
+            }
+        });
+        ui.separator();
+        ui.label("Sound");
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.sound_name);
+            if ui.button("Play").clicked() {
+                // Where the mouse is, so the pan can be heard
+                let event = match game.mouse_world {
+                    Some(p) => SoundEvent::at(&self.sound_name, (p.x, p.y).into()),
+                    None => SoundEvent::new(&self.sound_name),
+                };
+                game.world.play_sound(event);
+            }
+        });
+        ui.separator();
+        ui.label("Pickups");
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.drop_table);
+            if ui.button("Drop").clicked() {
+                let at = match game.mouse_world {
+                    Some(p) => (p.x, p.y).into(),
+                    None => game.world.camera().center(),
+                };
+                game.world.drop_pickups(&self.drop_table, at);
+            }
+        });
+        if let Some(player) = game.world.player() {
+            ui.label(format!(
+                "POWER {} LEVEL {}",
+                player.weapon.power(),
+                player.weapon.level()
+            ));
+            ui.label(format!(
+                "SCORE {} GRAZE {} x{:.2}",
+                player.score,
+                player.grazes,
+                player.multiplier()
+            ));
+        }
+        ui.separator();
+        self.rank_ui(ui, game);
+        ui.separator();
+        ui.label("Boss");
+        ui.horizontal(|ui| {
+            if ui.button("Spawn").clicked() {
+                let view = game.world.camera().view();
+                game.spawn_boss("boss", vec2(view.center().x, view.y + 32.));
+            }
+            if let Some(boss) = game.world.boss() {
+                ui.label(format!("PHASE {} HP {:.0}", boss.phase(), boss.hp()));
+            }
+        });
+        ui.separator();
+        self.layers_ui(ui, game);
+        ui.separator();
+        self.capture_ui(ui, game);
+        ui.separator();
+        self.display_settings_ui(ui, game);
+    }
+
+    fn rank_ui(&mut self, ui: &mut egui::Ui, game: &mut GameData) {
+        let rank = game.world.rank_mut();
+        ui.label("Rank");
+        let range = rank.curve.range;
+        let mut value = rank.value();
+        if ui
+            .add(egui::Slider::new(&mut value, range.min..=range.max).max_decimals(1))
+            .changed()
+        {
+            rank.set_value(value);
+        }
+        ui.label(format!(
+            "SPEED x{:.2} DENSITY x{:.2} EXTRA {}",
+            rank.bullet_speed(),
+            rank.bullet_density(),
+            rank.extra_enemies()
+        ));
+        egui::CollapsingHeader::new("Curve").show(ui, |ui| {
+            let curve = &mut rank.curve;
+            egui::Grid::new("rank_curve").show(ui, |ui| {
+                let row = |ui: &mut egui::Ui, label: &str, v: &mut f32| {
+                    ui.label(label);
+                    ui.add(egui::DragValue::new(v).speed(0.01));
+                    ui.end_row();
+                };
+                row(ui, "Per second", &mut curve.per_second);
+                row(ui, "Per 1000 points", &mut curve.per_thousand_points);
+                row(ui, "Per power level", &mut curve.per_power_level);
+                row(ui, "On death", &mut curve.on_death);
+            });
+            ui.label("Bullet speed");
+            curve.bullet_speed.editor_ui(ui, 0.1..=4.);
+            ui.label("Bullet density");
+            curve.bullet_density.editor_ui(ui, 0.1..=4.);
+            ui.label("Extra enemies");
+            curve.extra_enemies.editor_ui(ui, 0. ..=8.);
+        });
+    }
+
+    fn layers_ui(&mut self, ui: &mut egui::Ui, game: &mut GameData) {
+        ui.label("Layers");
+        egui::Grid::new("layers").show(ui, |ui| {
+            for (id, layer) in game.world.layers_mut().iter_mut() {
+                ui.checkbox(&mut layer.settings.visible, format!("{:?}", id))
+                    .on_hover_text(format!("{} items", layer.len()));
+                ui.checkbox(&mut layer.settings.own_target, "Target")
+                    .on_hover_text("Render to its own target, needed for the tint");
+                ui.add_enabled_ui(layer.settings.own_target, |ui| {
+                    color_edit_palette_button(ui, &mut layer.settings.tint);
+                });
+                ui.end_row();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Clear");
+            color_edit_palette_button(ui, &mut game.world.layers_mut().clear_color);
+        });
+        if ui.small_button("Show all").clicked() {
+            for id in LayerId::ALL {
+                game.world.layers_mut().get_mut(id).settings.visible = true;
+            }
+        }
+    }
+
+    fn capture_ui(&mut self, ui: &mut egui::Ui, game: &mut GameData) {
+        ui.label("Capture");
+        ui.add(
+            egui::Slider::new(&mut game.capture_scale, 1..=8)
+                .text("Scale")
+                .integer(),
+        );
+        ui.horizontal(|ui| {
+            if ui.button("Screenshot").clicked() {
+                game.capture = Some(CaptureRequest::Screenshot);
+            }
+            ui.label("F12");
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut self.record_seconds)
+                    .clamp_range(0.5..=60.)
+                    .speed(0.1)
+                    .suffix("s"),
+            );
+            ui.radio_value(&mut self.record_format, RecordFormat::Frames, "Frames");
+            ui.radio_value(&mut self.record_format, RecordFormat::Gif, "GIF");
+        });
+        ui.add_enabled_ui(!game.recording, |ui| {
+            if ui.button("Record").clicked() {
+                game.capture = Some(CaptureRequest::Record(
+                    self.record_seconds,
+                    self.record_format,
+                ));
+            }
+        });
+        if game.recording {
+            ui.label("Recording...");
+        }
+    }
+
+    fn display_settings_ui(&mut self, ui: &mut egui::Ui, game: &mut GameData) {
+        ui.label("Display");
+        let settings = &mut game.settings;
+        egui::Grid::new("display_settings").show(ui, |ui| {
+            ui.label("Resolution");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut settings.width).clamp_range(16..=1024));
+                ui.add(egui::DragValue::new(&mut settings.height).clamp_range(16..=1024));
+            });
+            ui.end_row();
+
+            ui.label("Aspect");
+            egui::ComboBox::from_id_source("display_aspect")
+                .selected_text(format!("{:?}", settings.aspect))
+                .show_ui(ui, |ui| {
+                    for mode in [
+                        AspectMode::Fit,
+                        AspectMode::Letterbox,
+                        AspectMode::Pillarbox,
+                        AspectMode::Stretch,
+                    ] {
+                        ui.selectable_value(&mut settings.aspect, mode, format!("{:?}", mode));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Filter");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut settings.filter, Filter::Nearest, "Nearest");
+                ui.radio_value(&mut settings.filter, Filter::Linear, "Linear");
+            });
+            ui.end_row();
+
+            ui.label("Scaling");
+            ui.checkbox(&mut settings.allow_non_int_scaling, "Allow non-integer");
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Apply").clicked() {
+                game.apply_settings = true;
+            }
+            if ui.button("Save").clicked() {
+                if let Err(err) = game.settings.save(assets::path(DEFAULT_SETTINGS_FILE)) {
+                    error!("Can't save: {:?}", err);
+                }
+            }
+        });
     }
 
     fn message_ui(&mut self, ui: &mut egui::Ui, game: &mut GameData) {
@@ -119,6 +395,22 @@ impl Editor {
                     if let State::Paused = game.state {
                         ui.label("Press S to Step");
                     }
+                    if let Some(err) = game.errors.last() {
+                        if ui.small_button("Clear").clicked() {
+                            game.errors.clear();
+                            return;
+                        }
+                        let count = game.errors.len();
+                        let label = ui.colored_label(
+                            egui::Color32::LIGHT_RED,
+                            format!("Errors: {} - {}", count, err),
+                        );
+                        label.on_hover_ui(|ui| {
+                            for err in &game.errors {
+                                ui.label(err);
+                            }
+                        });
+                    }
                 });
             });
     }
@@ -153,6 +445,16 @@ impl Editor {
         });
     }
 
+    /// Reload open previews editing `file_name`
+    pub fn reload_previews(&mut self, file_name: &str) -> Vec<anyhow::Error> {
+        self.previews
+            .values_mut()
+            .filter_map(|meta| meta.preview.as_mut())
+            .filter(|preview| preview.file_name() == file_name)
+            .filter_map(|preview| preview.load().err())
+            .collect()
+    }
+
     fn previews(&mut self, ctx: &egui::Context, game: &GameData) {
         self.previews.iter_mut().for_each(|(key, meta)| {
             if meta.opened {
@@ -178,3 +480,24 @@ impl Editor {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cowshmup::migrate;
+
+    use super::*;
+
+    #[test]
+    fn version_1_fixture() {
+        let mut editor =
+            migrate::from_slice::<Editor>(include_bytes!("../fixtures/v1/editor.yaml")).unwrap();
+        editor.init();
+        assert_eq!(editor.seed, Some(69420));
+        assert!(editor.show_debug);
+        assert!(editor.previews[&EditorPreview::Explosion].opened);
+        // Added since, they start out as their defaults
+        assert_eq!(editor.drop_table, Editor::default().drop_table);
+        let saved = migrate::to_value(&editor).unwrap();
+        assert!(migrate::from_value::<Editor>(saved).is_ok());
+    }
+}
@@ -0,0 +1,109 @@
+use macroquad::prelude::*;
+
+use crate::{
+    drawable::Drawable,
+    script::{Coroutine, Snapshot},
+    updateable::Updateable,
+    utils::{self, GameColor},
+    CenterPt, Velocity,
+};
+
+/// An enemy whose behavior is a script, see `script`
+#[derive(Debug)]
+pub struct Enemy {
+    center: CenterPt,
+    velocity: Velocity,
+    pub radius: f32,
+    pub hp: f32,
+    pub score: u32,
+    /// Drop table in the `PickupBank` rolled when the enemy is killed
+    pub drops: Option<String>,
+    pub color: GameColor,
+    pub script: Coroutine,
+    /// Seconds left showing a hit
+    flash: f32,
+    vanished: bool,
+}
+
+impl Enemy {
+    pub fn new(script: Coroutine, center: CenterPt) -> Self {
+        Self {
+            center,
+            velocity: Velocity::default(),
+            radius: 4.,
+            hp: 3.,
+            score: 100,
+            drops: None,
+            color: utils::ORANGE,
+            script,
+            flash: 0.,
+            vanished: false,
+        }
+    }
+
+    pub fn snapshot(&self, player: Option<CenterPt>, rank: f32) -> Snapshot {
+        Snapshot {
+            center: self.center,
+            player,
+            rank,
+            hp: self.hp,
+            ..Default::default()
+        }
+    }
+
+    /// Remove without exploding
+    pub fn vanish(&mut self) {
+        self.vanished = true;
+    }
+
+    pub fn is_vanished(&self) -> bool {
+        self.vanished
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.hp <= 0.
+    }
+
+    pub fn touches(&self, center: CenterPt, radius: f32) -> bool {
+        let (x, y) = self.center.get();
+        let (ox, oy) = center.get();
+        vec2(x - ox, y - oy).length() < self.radius + radius
+    }
+
+    /// Returns true when it took the hit
+    pub fn hit(&mut self, center: CenterPt, radius: f32, damage: f32) -> bool {
+        if self.is_dead() || self.vanished || !self.touches(center, radius) {
+            return false;
+        }
+        self.hp -= damage;
+        self.flash = 0.05;
+        true
+    }
+}
+
+crate::impl_pts!(center Enemy);
+crate::impl_pts!(velocity Enemy);
+
+impl Updateable for Enemy {
+    fn update(&mut self, delta_time: f32) {
+        self.flash = (self.flash - delta_time).max(0.);
+        self.center = self.center + self.velocity * delta_time;
+    }
+}
+
+impl Drawable for Enemy {
+    fn draw(&self) {
+        let (x, y) = self.center.get();
+        let color = if self.flash > 0. {
+            WHITE
+        } else {
+            self.color.into()
+        };
+        draw_circle(x, y, self.radius, color);
+    }
+
+    fn draw_gizmos(&self) {
+        let (x, y) = self.center.get();
+        draw_circle_lines(x, y, self.radius, 0.5, RED);
+    }
+}
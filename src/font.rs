@@ -0,0 +1,372 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use macroquad::{
+    prelude::{Color, Rect, Vec2, BLACK, WHITE},
+    texture::{draw_texture_ex, DrawTextureParams, Texture2D},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    drawable::load_png,
+    migrate::{self, Versioned},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Glyph {
+    /// Where the glyph is in the atlas
+    pub rect: Rect,
+    pub x_offset: f32,
+    pub y_offset: f32,
+    /// How far to move the pen after drawing the glyph
+    pub x_advance: f32,
+}
+
+/// Metrics for a font where every glyph has the same size and the glyphs are laid out in a grid,
+/// left to right and top to bottom, in the order given by `chars`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GridFontDesc {
+    /// Image file, relative to the description file
+    pub image: String,
+    pub glyph_width: u32,
+    pub glyph_height: u32,
+    /// Glyphs per row in the atlas. Zero means as many as fit in the image
+    pub columns: u32,
+    pub chars: String,
+    /// Defaults to `glyph_width + 1`
+    pub advance: Option<f32>,
+    /// Defaults to `glyph_height + 1`
+    pub line_height: Option<f32>,
+}
+
+impl Versioned for GridFontDesc {}
+
+impl Default for GridFontDesc {
+    fn default() -> Self {
+        Self {
+            image: String::from("font.png"),
+            glyph_width: 4,
+            glyph_height: 6,
+            columns: 0,
+            chars: (' '..='~').collect(),
+            advance: None,
+            line_height: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextStyle {
+    pub color: Color,
+    /// Drawn one pixel around every glyph
+    pub outline: Option<Color>,
+    /// Drawn one pixel down and to the right of every glyph
+    pub shadow: Option<Color>,
+    /// `x` is the left edge, center or right edge of each line
+    pub align: TextAlign,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            color: WHITE,
+            outline: None,
+            shadow: None,
+            align: TextAlign::Left,
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn new(color: Color) -> Self {
+        Self {
+            color,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_outline(mut self, color: Color) -> Self {
+        self.outline = Some(color);
+        self
+    }
+
+    pub fn with_shadow(mut self, color: Color) -> Self {
+        self.shadow = Some(color);
+        self
+    }
+
+    pub fn with_align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// White text with a black outline, readable on top of anything
+    pub fn banner() -> Self {
+        Self::new(WHITE)
+            .with_outline(BLACK)
+            .with_align(TextAlign::Center)
+    }
+}
+
+/// A font drawn from a glyph atlas texture, at exactly one texel per pixel
+#[derive(Debug, Clone)]
+pub struct BitmapFont {
+    texture: Texture2D,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+    line_height: f32,
+}
+
+impl BitmapFont {
+    /// Load a BMFont text file (`.fnt`) or a `GridFontDesc` (`.yaml`, `.yml`)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Could not open {}", path.display()))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("fnt") => {
+                let fnt = BmFont::parse(&data)
+                    .with_context(|| format!("could not parse {}", path.display()))?;
+                let texture = load_png(&path.with_file_name(&fnt.page))?;
+                Ok(fnt.into_font(texture))
+            }
+            _ => {
+                let desc = migrate::from_str::<GridFontDesc>(&data)
+                    .with_context(|| format!("could not parse {}", path.display()))?;
+                let texture = load_png(&path.with_file_name(&desc.image))?;
+                Ok(Self::from_grid(texture, &desc))
+            }
+        }
+    }
+
+    pub fn from_grid(texture: Texture2D, desc: &GridFontDesc) -> Self {
+        let (gw, gh) = (desc.glyph_width as f32, desc.glyph_height as f32);
+        let columns = if desc.columns == 0 {
+            ((texture.width() / gw) as u32).max(1)
+        } else {
+            desc.columns
+        };
+        let glyphs = desc
+            .chars
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let (col, row) = (i as u32 % columns, i as u32 / columns);
+                let glyph = Glyph {
+                    rect: Rect::new(col as f32 * gw, row as f32 * gh, gw, gh),
+                    x_offset: 0.,
+                    y_offset: 0.,
+                    x_advance: desc.advance.unwrap_or(gw + 1.),
+                };
+                (c, glyph)
+            })
+            .collect();
+        Self {
+            texture,
+            glyphs,
+            kerning: HashMap::new(),
+            line_height: desc.line_height.unwrap_or(gh + 1.),
+        }
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.line_height
+    }
+
+    fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs
+            .get(&c)
+            .or_else(|| self.glyphs.get(&c.to_ascii_uppercase()))
+            .or_else(|| self.glyphs.get(&c.to_ascii_lowercase()))
+    }
+
+    fn line_width(&self, line: &str) -> f32 {
+        let mut width = 0.;
+        let mut prev = None;
+        for c in line.chars() {
+            if let Some(g) = self.glyph(c) {
+                width += g.x_advance + self.kern(prev, c);
+            }
+            prev = Some(c);
+        }
+        width
+    }
+
+    fn kern(&self, prev: Option<char>, c: char) -> f32 {
+        prev.and_then(|p| self.kerning.get(&(p, c)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Width and height of the text, in pixels
+    pub fn measure(&self, text: &str) -> Vec2 {
+        let lines = text.lines().collect::<Vec<_>>();
+        let width = lines
+            .iter()
+            .map(|l| self.line_width(l))
+            .fold(0_f32, f32::max);
+        Vec2::new(width, lines.len() as f32 * self.line_height)
+    }
+
+    fn draw_pass(&self, text: &str, x: f32, y: f32, align: TextAlign, color: Color) {
+        for (row, line) in text.lines().enumerate() {
+            let mut pen_x = match align {
+                TextAlign::Left => x,
+                TextAlign::Center => x - (self.line_width(line) / 2.).floor(),
+                TextAlign::Right => x - self.line_width(line),
+            }
+            .round();
+            let pen_y = (y + row as f32 * self.line_height).round();
+            let mut prev = None;
+            for c in line.chars() {
+                let Some(g) = self.glyph(c) else {
+                    prev = Some(c);
+                    continue;
+                };
+                pen_x += self.kern(prev, c);
+                draw_texture_ex(
+                    self.texture,
+                    pen_x + g.x_offset,
+                    pen_y + g.y_offset,
+                    color,
+                    DrawTextureParams {
+                        dest_size: Some(Vec2::new(g.rect.w, g.rect.h)),
+                        source: Some(g.rect),
+                        ..Default::default()
+                    },
+                );
+                pen_x += g.x_advance;
+                prev = Some(c);
+            }
+        }
+    }
+
+    /// Draw text with its top left (or top center/right, see `TextAlign`) at x, y.
+    ///
+    /// The atlas should be white on transparent so that the style colors apply.
+    pub fn draw_text(&self, text: &str, x: f32, y: f32, style: &TextStyle) {
+        if let Some(shadow) = style.shadow {
+            self.draw_pass(text, x + 1., y + 1., style.align, shadow);
+        }
+        if let Some(outline) = style.outline {
+            for (ox, oy) in [
+                (-1., -1.),
+                (0., -1.),
+                (1., -1.),
+                (-1., 0.),
+                (1., 0.),
+                (-1., 1.),
+                (0., 1.),
+                (1., 1.),
+            ] {
+                self.draw_pass(text, x + ox, y + oy, style.align, outline);
+            }
+        }
+        self.draw_pass(text, x, y, style.align, style.color);
+    }
+}
+
+/// The parts of an AngelCode BMFont text file that we use
+#[derive(Debug, Default)]
+struct BmFont {
+    page: String,
+    line_height: f32,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+}
+
+impl BmFont {
+    fn parse(data: &str) -> Result<Self> {
+        let mut fnt = BmFont::default();
+        for line in data.lines() {
+            let mut parts = line.split_whitespace();
+            let Some(tag) = parts.next() else {
+                continue;
+            };
+            let attrs = parse_attrs(line);
+            let num = |key: &str| -> Result<f32> {
+                attrs
+                    .get(key)
+                    .ok_or_else(|| anyhow!("{} is missing {}", tag, key))?
+                    .parse::<f32>()
+                    .with_context(|| format!("{} has a bad {}", tag, key))
+            };
+            let ch = |key: &str| -> Result<char> {
+                let id = num(key)? as u32;
+                char::from_u32(id).ok_or_else(|| anyhow!("{} is not a character", id))
+            };
+            match tag {
+                "common" => {
+                    fnt.line_height = num("lineHeight")?;
+                    if num("pages").unwrap_or(1.) > 1. {
+                        bail!("only single page fonts are supported");
+                    }
+                }
+                "page" => {
+                    fnt.page = attrs
+                        .get("file")
+                        .ok_or_else(|| anyhow!("page is missing file"))?
+                        .clone();
+                }
+                "char" => {
+                    let glyph = Glyph {
+                        rect: Rect::new(num("x")?, num("y")?, num("width")?, num("height")?),
+                        x_offset: num("xoffset")?,
+                        y_offset: num("yoffset")?,
+                        x_advance: num("xadvance")?,
+                    };
+                    fnt.glyphs.insert(ch("id")?, glyph);
+                }
+                "kerning" => {
+                    fnt.kerning
+                        .insert((ch("first")?, ch("second")?), num("amount")?);
+                }
+                _ => {}
+            }
+        }
+        if fnt.page.is_empty() {
+            bail!("no page file");
+        }
+        Ok(fnt)
+    }
+
+    fn into_font(self, texture: Texture2D) -> BitmapFont {
+        BitmapFont {
+            texture,
+            glyphs: self.glyphs,
+            kerning: self.kerning,
+            line_height: self.line_height,
+        }
+    }
+}
+
+/// Split `key=value key="quoted value"` pairs
+fn parse_attrs(line: &str) -> HashMap<&str, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = line;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].rsplit(' ').next().unwrap_or_default();
+        rest = &rest[eq + 1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end + 1..).unwrap_or_default();
+            &quoted[..end]
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        attrs.insert(key, String::from(value));
+    }
+    attrs
+}
@@ -1,3 +1,14 @@
+use cowshmup::{
+    alive::IsAlive,
+    assets,
+    boss::{Boss, BossDef},
+    capture::{CaptureRequest, RecordFormat},
+    font::{BitmapFont, TextStyle},
+    player::PlayerInput,
+    settings::DisplaySettings,
+    timers::AliveTimer,
+    Rc,
+};
 use macroquad::input;
 
 // Game_data.rs
@@ -6,22 +17,150 @@ use crate::{prelude::*, state::State};
 #[derive(Default, Debug)]
 pub struct GameData {
     pub world: World,
+    /// Without a font, banners fall back to macroquad's (blurry at this size) text
+    pub font: Option<Rc<BitmapFont>>,
     pub fps: i32,
     pub frame_time: f32,
     pub time: f32,
     pub state: State,
     pub show_gizmos: bool,
     pub show_editor: bool,
+    /// Set by the camera while a hit-stop is freezing the world
+    pub hit_stop: bool,
+    /// Set when the last update moved the game on, camera shakes and hit-stops only run out then
+    pub stepped: bool,
+    /// Where the mouse is in the world, when it is over the game
+    pub mouse_world: Option<Vec2>,
+    pub settings: DisplaySettings,
+    /// Set when `settings` changed and the camera should pick them up
+    pub apply_settings: bool,
+    /// Screenshot or recording to take once this frame is rendered
+    pub capture: Option<CaptureRequest>,
+    /// Set while a recording is running
+    pub recording: bool,
+    /// Screenshots and recordings are upscaled by this
+    pub capture_scale: u32,
+    /// Times the player can start over after a game over
+    pub continues: u32,
+    /// Counts down during `State::GameOver`
+    pub continue_timer: AliveTimer,
+    /// Script and asset errors, latest last, for the editor to show
+    pub errors: Vec<String>,
+}
+
+/// Seconds to decide to continue
+const CONTINUE_TIME: f32 = 10.;
+/// Errors kept around
+const MAX_ERRORS: usize = 20;
+
+/// Arrow keys move, X fires, Z bombs
+fn player_input() -> PlayerInput {
+    let axis =
+        |neg, pos| input::is_key_down(pos) as i32 as f32 - input::is_key_down(neg) as i32 as f32;
+    PlayerInput {
+        direction: vec2(
+            axis(KeyCode::Left, KeyCode::Right),
+            axis(KeyCode::Up, KeyCode::Down),
+        ),
+        fire: input::is_key_down(KeyCode::X),
+        bomb: input::is_key_pressed(KeyCode::Z),
+    }
 }
 
 impl GameData {
     fn update_game(&mut self, delta_time: f32) {
         self.time += delta_time;
         self.handle_common_input(delta_time);
-        self.world.update(delta_time);
+        self.world.set_player_input(player_input());
+        if !self.hit_stop {
+            self.world.update(delta_time);
+        }
+        self.update_scripts();
+        self.spawn_map_objects();
+        if self.world.player().is_some_and(|p| p.is_game_over()) {
+            self.state = State::GameOver;
+            self.continue_timer = AliveTimer::new(CONTINUE_TIME);
+        }
         self.fps = get_fps();
     }
 
+    /// What scrolled on screen. Tiled objects typed `enemy`, `boss` or `trigger` spawn
+    /// something, the `script` property picks the script, otherwise it is the object's name.
+    fn spawn_map_objects(&mut self) {
+        for object in self.world.take_map_objects() {
+            match object.kind.as_str() {
+                "boss" => self.spawn_boss(&object.name, object.rect.center()),
+                "enemy" => {
+                    // Extra enemies line up to the right of the first
+                    let count = 1 + self.world.rank().extra_enemies();
+                    let script = object.property("script").unwrap_or(&object.name);
+                    let drops = object.property("drops");
+                    let at = object.rect.center();
+                    for i in 0..count {
+                        let center = (at.x + i as f32 * 12., at.y).into();
+                        if let Some(enemy) = self.world.spawn_enemy(script, center) {
+                            enemy.drops = drops.map(String::from);
+                        }
+                    }
+                }
+                // Stage timeline events, the script runs like one started by `run_script`
+                "trigger" => {
+                    let script = object.property("script").unwrap_or(&object.name);
+                    self.world.run_script(script);
+                }
+                _ => info!("{} {:?} came on screen", object.kind, object.name),
+            }
+        }
+    }
+
+    /// Logged, and shown by the editor
+    pub fn report_error(&mut self, message: String) {
+        warn!("{}", message);
+        self.errors.push(message);
+        let excess = self.errors.len().saturating_sub(MAX_ERRORS);
+        self.errors.drain(..excess);
+    }
+
+    fn update_scripts(&mut self) {
+        for err in self.world.take_script_errors() {
+            self.report_error(err.to_string());
+        }
+    }
+
+    /// The world keeps going, a continue puts the player back in
+    fn update_game_over(&mut self, delta_time: f32) {
+        self.time += delta_time;
+        self.handle_common_input(delta_time);
+        if !self.hit_stop {
+            self.world.update(delta_time);
+        }
+        self.update_scripts();
+        self.spawn_map_objects();
+        self.continue_timer.update(delta_time);
+        if self.continues > 0 && self.continue_timer.is_alive() && input::is_key_pressed(KeyCode::X)
+        {
+            self.continues -= 1;
+            if let Some(player) = self.world.player_mut() {
+                player.continue_game();
+            }
+            self.world.rank_mut().reset_score();
+            self.state = State::Playing;
+        }
+        self.fps = get_fps();
+    }
+
+    /// Load `<name>.yaml`, the default boss if that fails
+    pub fn spawn_boss(&mut self, name: &str, at: Vec2) {
+        let def = match assets::load::<BossDef>(format!("{}.yaml", name)) {
+            Err(err) => {
+                warn!("Unable to load boss: {:#?}", err);
+                BossDef::default()
+            }
+            Ok(v) => v.cloned(),
+        };
+        self.world.spawn_boss(Boss::new(def, (at.x, at.y).into()));
+    }
+
     pub fn is_editor(&self) -> bool {
         self.show_editor
     }
@@ -38,6 +177,12 @@ impl GameData {
         if input::is_key_pressed(KeyCode::E) || input::is_key_pressed(KeyCode::F11) {
             self.show_editor = !self.show_editor;
         }
+        if input::is_key_pressed(KeyCode::F12) {
+            self.capture = Some(CaptureRequest::Screenshot);
+        }
+        if input::is_key_pressed(KeyCode::F9) && !self.recording {
+            self.capture = Some(CaptureRequest::Record(5., RecordFormat::Gif));
+        }
         if input::is_key_pressed(KeyCode::Escape) {
             self.press_escape();
         }
@@ -62,17 +207,40 @@ impl GameData {
     }
 
     fn draw_game(&self) {
-        clear_background(RED);
-
         self.world.draw();
     }
 
+    /// Draw a line of text centered horizontally on the game screen
+    fn draw_banner(&self, text: &str, y: f32) {
+        // The world may have scrolled, banners stay put on the screen
+        let origin = self.world.camera().screen_to_world(Vec2::ZERO);
+        let width = self.world.camera().view().w;
+        let y = origin.y + y;
+        if let Some(font) = &self.font {
+            font.draw_text(text, origin.x + width / 2., y, &TextStyle::banner());
+        } else {
+            let size = measure_text(text, None, 10, 1.0);
+            let x = origin.x + ((width - size.width) / 2.).round();
+            // macroquad draws from the baseline, bitmap fonts from the top
+            draw_text(text, x, (y + size.offset_y).round(), 10.0, WHITE);
+        }
+    }
+
     fn draw_paused(&self) {
-        draw_text("Paused", 34.0, 60.0, 10.0, WHITE);
+        self.draw_banner("Paused", 60.0);
     }
 
     fn draw_step(&self) {
-        draw_text("Press s to Step", 20.0, 60.0, 10.0, WHITE);
+        self.draw_banner("Press s to Step", 60.0);
+    }
+
+    fn draw_game_over(&self) {
+        self.draw_banner("Game Over", 60.0);
+        if self.continues > 0 && self.continue_timer.is_alive() {
+            let seconds = self.continue_timer.ttl().ceil();
+            self.draw_banner(&format!("Continue? {}", seconds), 72.0);
+            self.draw_banner(&format!("Press X ({} left)", self.continues), 84.0);
+        }
     }
 
     fn press_escape(&mut self) {
@@ -80,11 +248,15 @@ impl GameData {
             State::Playing => self.state = State::Exit,
             State::Paused => self.state = State::Playing,
             State::Step => self.state = State::Exit,
+            State::GameOver => self.state = State::Exit,
             _ => {}
         }
     }
 
     fn press_space(&mut self) {
+        if self.state == State::GameOver {
+            return;
+        }
         if self.state.is_playing() {
             self.state = State::Paused;
         } else {
@@ -95,6 +267,7 @@ impl GameData {
 
 impl Updateable for GameData {
     fn update(&mut self, delta_time: f32) {
+        self.stepped = matches!(self.state, State::Playing | State::Step | State::GameOver);
         match self.state {
             State::Init => self.state = State::Paused,
             State::Playing => self.update_game(delta_time),
@@ -102,6 +275,7 @@ impl Updateable for GameData {
             State::Step => {
                 self.update_game(delta_time);
             }
+            State::GameOver => self.update_game_over(delta_time),
             State::Exit => {}
         }
     }
@@ -120,6 +294,10 @@ impl Drawable for GameData {
                 self.draw_game();
                 self.draw_step();
             }
+            State::GameOver => {
+                self.draw_game();
+                self.draw_game_over();
+            }
             State::Exit => self.draw_game(),
         }
     }
@@ -127,6 +305,10 @@ impl Drawable for GameData {
     fn draw_gizmos(&self) {
         if self.show_gizmos {
             self.world.draw_gizmos();
+            if let Some(p) = self.mouse_world {
+                draw_line(p.x - 2., p.y, p.x + 3., p.y, 1., YELLOW);
+                draw_line(p.x, p.y - 2., p.x, p.y + 3., 1., YELLOW);
+            }
         }
     }
 }
@@ -0,0 +1,369 @@
+use std::cell::Cell;
+
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    background::Background,
+    drawable::{Drawable, Graphic},
+    particle::Particle,
+    tilemap::Tilemap,
+    updateable::Updateable,
+    utils::{self, GameColor},
+};
+
+/// Layers are drawn in this order, back to front
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum LayerId {
+    /// Stays put on the screen, see `World::set_background`
+    Background,
+    Parallax,
+    #[default]
+    Enemies,
+    Player,
+    Bullets,
+    Particles,
+    /// Stays put on the screen while the world scrolls
+    Hud,
+    Debug,
+}
+
+impl LayerId {
+    pub const ALL: [LayerId; 8] = [
+        LayerId::Background,
+        LayerId::Parallax,
+        LayerId::Enemies,
+        LayerId::Player,
+        LayerId::Bullets,
+        LayerId::Particles,
+        LayerId::Hud,
+        LayerId::Debug,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayerSettings {
+    pub visible: bool,
+    /// Draw the layer to its own render target first, then composite it onto the screen
+    pub own_target: bool,
+    /// Multiplies the whole layer, only used with `own_target`
+    pub tint: GameColor,
+    /// Screen coordinates instead of world coordinates, for HUDs
+    pub fixed: bool,
+}
+
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            own_target: false,
+            tint: utils::WHITE,
+            fixed: false,
+        }
+    }
+}
+
+/// Anything that can be put on a layer
+pub enum LayerItem {
+    Graphic(Graphic),
+    Particle(Box<dyn Particle>),
+    Background(Background),
+    Tilemap(Tilemap),
+}
+
+impl LayerItem {
+    /// Like `draw`, but tilemaps only draw what is inside `view`
+    fn draw_view(&self, view: Rect) {
+        match self {
+            LayerItem::Tilemap(t) => t.draw_view(view),
+            other => other.draw(),
+        }
+    }
+}
+
+impl Drawable for LayerItem {
+    fn draw(&self) {
+        match self {
+            LayerItem::Graphic(g) => g.draw(),
+            LayerItem::Particle(p) => p.draw(),
+            LayerItem::Background(b) => b.draw(),
+            LayerItem::Tilemap(t) => t.draw(),
+        }
+    }
+
+    fn draw_gizmos(&self) {
+        match self {
+            LayerItem::Graphic(g) => g.draw_gizmos(),
+            LayerItem::Particle(p) => p.draw_gizmos(),
+            LayerItem::Background(b) => b.draw_gizmos(),
+            LayerItem::Tilemap(t) => t.draw_gizmos(),
+        }
+    }
+}
+
+impl Updateable for LayerItem {
+    fn update(&mut self, delta_time: f32) {
+        match self {
+            LayerItem::Graphic(g) => g.update(delta_time),
+            LayerItem::Particle(p) => p.update(delta_time),
+            LayerItem::Background(b) => b.update(delta_time),
+            LayerItem::Tilemap(t) => t.update(delta_time),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Layer {
+    pub settings: LayerSettings,
+    /// Sorted by z, lower z is drawn first. Items with the same z keep the order they were added.
+    items: Vec<(i32, LayerItem)>,
+    /// Created when first needed, and again when the screen size changes
+    target: Cell<Option<RenderTarget>>,
+}
+
+impl Layer {
+    pub fn add(&mut self, z: i32, item: LayerItem) {
+        let at = self.items.partition_point(|(other, _)| *other <= z);
+        self.items.insert(at, (z, item));
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = &LayerItem> {
+        self.items.iter().map(|(_, item)| item)
+    }
+
+    pub fn items_mut(&mut self) -> impl Iterator<Item = &mut LayerItem> {
+        self.items.iter_mut().map(|(_, item)| item)
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    fn target(&self, width: u32, height: u32) -> RenderTarget {
+        match self.target.get() {
+            Some(target)
+                if target.texture.width() as u32 == width
+                    && target.texture.height() as u32 == height =>
+            {
+                target
+            }
+            old => {
+                if let Some(old) = old {
+                    old.delete();
+                }
+                let target = render_target(width, height);
+                target.texture.set_filter(FilterMode::Nearest);
+                self.target.set(Some(target));
+                target
+            }
+        }
+    }
+
+    fn free_target(&self) {
+        if let Some(target) = self.target.take() {
+            target.delete();
+        }
+    }
+
+    fn draw_items(&self, view: Rect, gizmos: bool, extra: &dyn Fn()) {
+        let gl = unsafe { get_internal_gl() }.quad_gl;
+        let mut item_view = view;
+        if self.settings.fixed {
+            gl.push_model_matrix(Mat4::from_translation(vec3(view.x, view.y, 0.)));
+            item_view.move_to(Vec2::ZERO);
+        }
+        for (_, item) in self.items.iter() {
+            if gizmos {
+                item.draw_gizmos();
+            } else {
+                item.draw_view(item_view);
+            }
+        }
+        extra();
+        if self.settings.fixed {
+            gl.pop_model_matrix();
+        }
+    }
+
+    /// Draw the layer. `view` is the part of the world that is on screen, in whole pixels.
+    pub fn draw(&self, view: Rect) {
+        self.draw_with(view, &|| {})
+    }
+
+    /// Like `draw`, with `extra` drawn on top of the items, as if it were part of the layer
+    pub fn draw_with(&self, view: Rect, extra: &dyn Fn()) {
+        if !self.settings.visible {
+            return;
+        }
+        if !self.settings.own_target {
+            self.free_target();
+            self.draw_items(view, false, extra);
+            return;
+        }
+        let target = self.target(view.w as u32, view.h as u32);
+        let mut camera = Camera2D::from_display_rect(view);
+        camera.render_target = Some(target);
+        camera.zoom.y *= -1.;
+        push_camera_state();
+        set_camera(&camera);
+        clear_background(BLANK);
+        self.draw_items(view, false, extra);
+        pop_camera_state();
+        draw_texture_ex(
+            target.texture,
+            view.x,
+            view.y,
+            self.settings.tint.into(),
+            DrawTextureParams {
+                dest_size: Some(view.size()),
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn draw_gizmos(&self, view: Rect) {
+        self.draw_gizmos_with(view, &|| {})
+    }
+
+    pub fn draw_gizmos_with(&self, view: Rect, extra: &dyn Fn()) {
+        if self.settings.visible {
+            self.draw_items(view, true, extra);
+        }
+    }
+}
+
+impl Updateable for Layer {
+    /// Particles that died are dropped
+    fn update(&mut self, delta_time: f32) {
+        self.items
+            .iter_mut()
+            .for_each(|(_, item)| item.update(delta_time));
+        self.items
+            .retain(|(_, item)| !matches!(item, LayerItem::Particle(p) if !p.is_alive()));
+    }
+}
+
+/// One `Layer` for every `LayerId`
+pub struct Layers {
+    layers: Vec<Layer>,
+    /// Fills the screen before the first layer is drawn
+    pub clear_color: GameColor,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        let layers = LayerId::ALL
+            .iter()
+            .map(|id| Layer {
+                settings: LayerSettings {
+                    fixed: matches!(id, LayerId::Background | LayerId::Hud),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .collect();
+        Self {
+            layers,
+            clear_color: utils::BLACK,
+        }
+    }
+}
+
+impl Layers {
+    pub fn get(&self, id: LayerId) -> &Layer {
+        &self.layers[id as usize]
+    }
+
+    pub fn get_mut(&mut self, id: LayerId) -> &mut Layer {
+        &mut self.layers[id as usize]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LayerId, &Layer)> {
+        LayerId::ALL.into_iter().zip(self.layers.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (LayerId, &mut Layer)> {
+        LayerId::ALL.into_iter().zip(self.layers.iter_mut())
+    }
+
+    pub fn add(&mut self, id: LayerId, z: i32, item: LayerItem) {
+        self.get_mut(id).add(z, item);
+    }
+
+    pub fn tilemaps(&self) -> impl Iterator<Item = &Tilemap> {
+        self.layers
+            .iter()
+            .flat_map(|l| l.items())
+            .filter_map(|i| match i {
+                LayerItem::Tilemap(t) => Some(t),
+                _ => None,
+            })
+    }
+
+    pub fn tilemaps_mut(&mut self) -> impl Iterator<Item = &mut Tilemap> {
+        self.layers
+            .iter_mut()
+            .flat_map(|l| l.items_mut())
+            .filter_map(|i| match i {
+                LayerItem::Tilemap(t) => Some(t),
+                _ => None,
+            })
+    }
+
+    pub fn draw(&self, view: Rect) {
+        self.draw_with(view, |_| {})
+    }
+
+    /// Like `draw`, `extra` draws things that are not layer items (like the player) on their layer
+    pub fn draw_with(&self, view: Rect, extra: impl Fn(LayerId)) {
+        clear_background(self.clear_color.into());
+        for (id, layer) in self.iter() {
+            layer.draw_with(view, &|| extra(id));
+        }
+    }
+
+    pub fn draw_gizmos(&self, view: Rect) {
+        self.draw_gizmos_with(view, |_| {})
+    }
+
+    pub fn draw_gizmos_with(&self, view: Rect, extra: impl Fn(LayerId)) {
+        for (id, layer) in self.iter() {
+            layer.draw_gizmos_with(view, &|| extra(id));
+        }
+    }
+}
+
+impl Updateable for Layers {
+    fn update(&mut self, delta_time: f32) {
+        self.layers.iter_mut().for_each(|l| l.update(delta_time));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{particle::Explosion, CenterPt};
+
+    #[test]
+    fn dead_particles_leave_the_layer() {
+        let mut layers = Layers::default();
+        let sparks =
+            Explosion::sparks(CenterPt::new(10., 10.), CenterPt::new(0., 0.), utils::WHITE);
+        layers.add(LayerId::Particles, 0, LayerItem::Particle(Box::new(sparks)));
+        layers.update(0.05);
+        assert_eq!(layers.get(LayerId::Particles).len(), 1);
+        layers.update(1.);
+        assert!(layers.get(LayerId::Particles).is_empty());
+    }
+}
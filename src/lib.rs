@@ -3,14 +3,37 @@ use std::ops::{Add, Mul};
 use serde::{Deserialize, Serialize};
 
 pub mod alive;
+pub mod assets;
+pub mod audio;
+pub mod background;
+pub mod boss;
 pub mod buildable;
+pub mod bullet;
+pub mod capture;
 pub mod drawable;
+pub mod enemy;
+pub mod font;
+pub mod layer;
+pub mod migrate;
 pub mod minmax;
+pub mod music;
+pub mod palette;
 pub mod particle;
+pub mod pattern;
+pub mod pickup;
+pub mod player;
+pub mod rank;
 pub mod retro_camera;
+pub mod script;
+pub mod scroll_camera;
+pub mod settings;
+pub mod sfx;
+pub mod tilemap;
 pub mod timers;
 pub mod updateable;
 pub mod utils;
+pub mod watch;
+pub mod weapon;
 pub mod widgets;
 pub mod world;
 
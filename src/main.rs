@@ -5,26 +5,139 @@ mod game_data;
 mod prelude;
 mod preview;
 mod state;
-use cowshmup::retro_camera::RetroCamera;
+use cowshmup::{
+    assets,
+    audio::{Audio, MacroquadBackend, SoundBank, DEFAULT_SOUNDS_FILE},
+    background::BackgroundBuilder,
+    buildable::Buildable,
+    capture::{self, CaptureRequest, Recorder},
+    font::BitmapFont,
+    layer::LayerId,
+    palette::{self, Palette},
+    pickup::{PickupBank, DEFAULT_PICKUPS_FILE},
+    player::Player,
+    rank::{RankCurve, DEFAULT_RANK_FILE},
+    retro_camera::RetroCamera,
+    script::{ScriptBank, DEFAULT_STAGE_SCRIPT},
+    settings::{DisplaySettings, DEFAULT_SETTINGS_FILE},
+    tilemap::Tilemap,
+    watch::AssetWatcher,
+    weapon::{Weapon, WeaponDef},
+};
 use editor::Editor;
 use prelude::*;
 use state::State;
-use std::{
-    fs::File,
-    io::{BufReader, BufWriter},
-};
+use std::{env, path::Path};
 
 use crate::game_data::GameData;
 
 fn load_editor() -> anyhow::Result<Editor> {
-    let rdr =
-        BufReader::new(File::open("editor.yaml").with_context(|| "Could not open editor.yaml")?);
-    serde_yaml::from_reader::<_, Editor>(rdr).with_context(|| "could not parse editor.yaml")
+    assets::load_yaml(&assets::path("editor.yaml"))
+}
+
+/// `path`, relative to the asset root, changed on disk. Reload it and rebuild what was built from it
+fn reload_asset(
+    path: &Path,
+    game: &mut GameData,
+    editor: &mut Editor,
+    background: &mut BackgroundBuilder,
+    audio: &mut Audio<MacroquadBackend>,
+) {
+    let Some(name) = path.to_str() else {
+        return;
+    };
+    let result = match name {
+        "editor.yaml" => load_editor().map(|mut v| {
+            v.init();
+            *editor = v;
+        }),
+        DEFAULT_SETTINGS_FILE => assets::reload::<DisplaySettings>(name).map(|v| {
+            game.settings = v.cloned();
+            game.apply_settings = true;
+        }),
+        "palette.hex" => assets::reload::<Palette>(name).map(|v| palette::set_active(v.cloned())),
+        DEFAULT_PICKUPS_FILE => {
+            assets::reload::<PickupBank>(name).map(|v| game.world.set_pickup_bank(v.cloned()))
+        }
+        // Keep the rank the player earned
+        DEFAULT_RANK_FILE => {
+            assets::reload::<RankCurve>(name).map(|v| game.world.rank_mut().curve = v.cloned())
+        }
+        // Keep the power the player collected
+        "weapon.yaml" => assets::reload::<WeaponDef>(name).map(|v| {
+            if let Some(player) = game.world.player_mut() {
+                player.weapon.def = v.cloned();
+            }
+        }),
+        DEFAULT_SOUNDS_FILE => assets::reload::<SoundBank>(name).map(|v| audio.set_bank(&v)),
+        _ if name == BackgroundBuilder::get_default_file_name() => {
+            assets::reload::<BackgroundBuilder>(name).map(|v| {
+                let (width, height) = game.settings.size();
+                game.world.set_background(v.build_sized(width, height));
+                *background = v.cloned();
+            })
+        }
+        _ if background.images().any(|image| image == name) => {
+            assets::forget(name);
+            let (width, height) = game.settings.size();
+            game.world
+                .set_background(background.build_sized(width, height));
+            Ok(())
+        }
+        _ if name.ends_with(".rhai") => {
+            game.world.reload_scripts();
+            Ok(())
+        }
+        _ => {
+            assets::forget(name);
+            // Bullet patterns are also cached by name
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                game.world.forget_pattern(stem);
+            }
+            Ok(())
+        }
+    };
+    let errors = result.err().into_iter().chain(editor.reload_previews(name));
+    for err in errors {
+        game.report_error(format!("Unable to reload {}: {:#}", name, err));
+    }
+}
+
+fn capture_frame(game: &mut GameData, recorder: &mut Recorder, retrocam: &RetroCamera) {
+    recorder.scale = game.capture_scale;
+    match game.capture.take() {
+        Some(CaptureRequest::Screenshot) => {
+            match capture::screenshot(retrocam.texture(), game.capture_scale, &recorder.dir) {
+                Err(err) => warn!("Unable to save screenshot: {:#?}", err),
+                Ok(path) => info!("Saved {}", path.display()),
+            }
+        }
+        Some(CaptureRequest::Record(seconds, format)) => {
+            if let Err(err) = recorder.start(seconds, format) {
+                warn!("Unable to start recording: {:#?}", err);
+            }
+        }
+        None => {}
+    }
+    recorder.update(game.frame_time);
+    match recorder.capture(retrocam.texture()) {
+        Err(err) => {
+            warn!("Recording failed: {:#?}", err);
+            let _ = recorder.finish();
+        }
+        Ok(Some(path)) => info!("Saved {}", path.display()),
+        Ok(None) => {}
+    }
+    game.recording = recorder.is_recording();
 }
 
 #[macroquad::main("OMG Cows")]
 async fn main() -> Result<()> {
     info!("Hello, World!");
+    // Assets are in the working directory unless told otherwise
+    if let Some(root) = env::args().nth(1) {
+        assets::set_root(root);
+    }
 
     // EDITOR SETUP
     let mut editor = match load_editor() {
@@ -38,17 +151,96 @@ async fn main() -> Result<()> {
     editor.init();
 
     // GAME SETUP
+    match assets::load::<Palette>("palette.hex") {
+        Err(err) => warn!("Unable to load palette, using PICO-8: {:#?}", err),
+        Ok(v) => palette::set_active(v.cloned()),
+    }
     let mut world = World::default();
     world.add_graphic(Graphic::line(40.0, 40.0, 100.0, 200.0, BLUE));
+    let font = match assets::load::<BitmapFont>("font.fnt") {
+        Err(err) => {
+            warn!("Unable to load font: {:#?}", err);
+            None
+        }
+        Ok(v) => Some(v.rc()),
+    };
+    let settings = match assets::load::<DisplaySettings>(DEFAULT_SETTINGS_FILE) {
+        Err(err) => {
+            warn!("Unable to load settings: {:#?}", err);
+            DisplaySettings::default()
+        }
+        Ok(v) => v.cloned(),
+    };
+    let (width, height) = settings.size();
+    world.camera_mut().set_size(width, height);
+    let mut background =
+        match assets::load::<BackgroundBuilder>(BackgroundBuilder::get_default_file_name()) {
+            Err(err) => {
+                warn!("Unable to load background: {:#?}", err);
+                BackgroundBuilder::default()
+            }
+            Ok(v) => v.cloned(),
+        };
+    world.set_background(background.build_sized(width, height));
+    match assets::load::<Tilemap>("stage.tmj") {
+        Err(err) => warn!("Unable to load stage map: {:#?}", err),
+        Ok(v) => world.add_tilemap(LayerId::Parallax, 0, v.cloned()),
+    }
+    // Stage events are optional
+    if ScriptBank::path(DEFAULT_STAGE_SCRIPT).exists() {
+        world.run_script(DEFAULT_STAGE_SCRIPT);
+    }
+    match assets::load::<PickupBank>(DEFAULT_PICKUPS_FILE) {
+        Err(err) => warn!("Unable to load pickups: {:#?}", err),
+        Ok(v) => world.set_pickup_bank(v.cloned()),
+    }
+    match assets::load::<RankCurve>(DEFAULT_RANK_FILE) {
+        Err(err) => warn!("Unable to load rank curve: {:#?}", err),
+        Ok(v) => world.set_rank_curve(v.cloned()),
+    }
+    let weapon = match assets::load::<WeaponDef>("weapon.yaml") {
+        Err(err) => {
+            warn!("Unable to load weapon: {:#?}", err);
+            WeaponDef::default()
+        }
+        Ok(v) => v.cloned(),
+    };
+    world.set_player(Player::new(
+        (width / 2., height - 16.).into(),
+        Weapon::new(weapon),
+    ));
+    let sounds = match assets::load::<SoundBank>(DEFAULT_SOUNDS_FILE) {
+        Err(err) => {
+            warn!("Unable to load sounds: {:#?}", err);
+            SoundBank::default()
+        }
+        Ok(v) => v.cloned(),
+    };
+    let mut audio = Audio::new(&sounds, MacroquadBackend::default());
     let mut game = GameData {
         world,
+        font,
+        settings,
         show_gizmos: true,
         show_editor: true,
+        capture_scale: 1,
+        continues: 3,
         ..GameData::default()
     };
 
+    // Captures and build output may end up inside the asset root, they are not assets
+    let ignored = [capture::DEFAULT_CAPTURE_DIR, "target"];
+    let mut watcher = match AssetWatcher::new(assets::root(), ignored) {
+        Err(err) => {
+            warn!("Unable to watch assets, they will not reload: {:#?}", err);
+            None
+        }
+        Ok(v) => Some(v),
+    };
+
     // Retro Camera Setup
-    let mut retrocam = RetroCamera::default();
+    let mut retrocam = RetroCamera::from_settings(&game.settings);
+    let mut recorder = Recorder::default();
 
     // GAME LOOP
     while !game.state.is_exit() {
@@ -65,12 +257,57 @@ async fn main() -> Result<()> {
             retrocam.reset_canvas(egui_ctx);
         });
 
-        // UPDATE GAME
+        // CAMERA EFFECTS (hit-stop has to be known before the world updates)
+        for effect in game.world.take_camera_effects() {
+            retrocam.apply(effect);
+        }
+        game.hit_stop = retrocam.is_hit_stopped();
+
+        // UPDATE GAME (effects only run out on frames the game moved on, not while paused
+        // between steps)
         game.update(game.frame_time);
+        if game.stepped {
+            retrocam.update(game.frame_time);
+        }
+        if game.apply_settings {
+            game.apply_settings = false;
+            retrocam.apply_settings(&game.settings);
+            let (width, height) = game.settings.size();
+            game.world.camera_mut().set_size(width, height);
+            game.world
+                .set_background(background.build_sized(width, height));
+        }
+
+        // HOT RELOAD
+        if let Some(watcher) = &mut watcher {
+            let changed = watcher.changed();
+            for path in &changed {
+                info!("{} changed", path.display());
+                reload_asset(path, &mut game, &mut editor, &mut background, &mut audio);
+            }
+            // What was replaced is only cached now
+            if !changed.is_empty() {
+                info!("Dropped {} unused assets", assets::collect());
+            }
+        }
+
+        // AUDIO (after the update, so sounds start on the frame that asked for them)
+        audio.update(game.frame_time);
+        audio.listener = game.world.camera().view();
+        for event in game.world.take_sound_events() {
+            audio.trigger(&event);
+        }
 
         // Adjust Cameras and Canvas...
         clear_background(BLACK);
+        retrocam.set_view(game.world.camera());
         retrocam.setup_camera();
+        let mouse = Vec2::from(mouse_position());
+        game.mouse_world = if retrocam.is_on_screen(mouse) {
+            Some(retrocam.window_to_world(mouse).floor())
+        } else {
+            None
+        };
 
         // DRAW (to texture/Retro Camera)
         game.draw();
@@ -80,6 +317,9 @@ async fn main() -> Result<()> {
         retrocam.render();
         egui_macroquad::draw();
 
+        // CAPTURE (the render target is complete now)
+        capture_frame(&mut game, &mut recorder, &retrocam);
+
         // Finally wait for next frame...
         next_frame().await;
     }
@@ -87,6 +327,6 @@ async fn main() -> Result<()> {
     // GAME LOOP EXITED
     // TODO: Should probably support manually loading and saving, instead of always auto-saving...
     // Or maybe both...
-    serde_yaml::to_writer(BufWriter::new(File::create("editor.yaml")?), &editor)?;
+    assets::save_yaml(&assets::path("editor.yaml"), &editor)?;
     Ok(())
 }
@@ -0,0 +1,255 @@
+//! YAML assets carry the `version` of their format, so files saved before a format changed can
+//! still be read. Loading an old document runs the type's migrations on it, in order, before it
+//! is deserialized; saving always writes the current version.
+//!
+//! To change a format, add a migration to the end of the type's `MIGRATIONS` that turns a
+//! document of the previous version into one the new code reads, e.g. renaming a field:
+//!
+//! ```text
+//! impl Versioned for ExplosionBuilder {
+//!     const MIGRATIONS: &'static [Migration] = &[|doc| {
+//!         migrate::rename(doc, "stages", "steps");
+//!         Ok(())
+//!     }];
+//! }
+//! ```
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_yaml::{Mapping, Value};
+
+/// Upgrades a document to the next version
+pub type Migration = fn(&mut Mapping) -> Result<()>;
+
+/// Documents without a `version` were saved before there were versions
+pub const FIRST_VERSION: u32 = 1;
+
+const VERSION_KEY: &str = "version";
+
+/// A type saved as a YAML document
+pub trait Versioned {
+    /// `MIGRATIONS[0]` upgrades version 1 documents to version 2, and so on. Only ever add to
+    /// the end.
+    const MIGRATIONS: &'static [Migration] = &[];
+
+    /// What is written when saving
+    fn version() -> u32 {
+        FIRST_VERSION + Self::MIGRATIONS.len() as u32
+    }
+}
+
+/// Upgrade `value` to the current version of `T`, and take the version out of it.
+/// Only mappings are versioned, anything else is left alone.
+pub fn upgrade<T: Versioned>(value: &mut Value) -> Result<()> {
+    let Value::Mapping(doc) = value else {
+        return Ok(());
+    };
+    let version = match doc.remove(VERSION_KEY) {
+        None => FIRST_VERSION,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .with_context(|| format!("{:?} is not a version", v))?,
+    };
+    if version < FIRST_VERSION {
+        bail!("there is no version {}", version);
+    }
+    if version > T::version() {
+        bail!(
+            "saved as version {}, this build reads up to version {}",
+            version,
+            T::version()
+        );
+    }
+    let from = (version - FIRST_VERSION) as usize;
+    for (i, migration) in T::MIGRATIONS.iter().enumerate().skip(from) {
+        let version = FIRST_VERSION + i as u32;
+        migration(doc).with_context(|| format!("could not upgrade from version {}", version))?;
+    }
+    Ok(())
+}
+
+pub fn from_value<T: Versioned + DeserializeOwned>(mut value: Value) -> Result<T> {
+    upgrade::<T>(&mut value)?;
+    Ok(serde_yaml::from_value(value)?)
+}
+
+pub fn from_slice<T: Versioned + DeserializeOwned>(data: &[u8]) -> Result<T> {
+    from_value(serde_yaml::from_slice(data)?)
+}
+
+pub fn from_str<T: Versioned + DeserializeOwned>(data: &str) -> Result<T> {
+    from_value(serde_yaml::from_str(data)?)
+}
+
+/// `value` with its version first
+pub fn to_value<T: Versioned + Serialize>(value: &T) -> Result<Value> {
+    Ok(match serde_yaml::to_value(value)? {
+        Value::Mapping(fields) => {
+            let mut doc = Mapping::new();
+            doc.insert(VERSION_KEY.into(), T::version().into());
+            doc.extend(fields);
+            Value::Mapping(doc)
+        }
+        value => value,
+    })
+}
+
+pub fn to_writer<T: Versioned + Serialize>(writer: impl Write, value: &T) -> Result<()> {
+    serde_yaml::to_writer(writer, &to_value(value)?)?;
+    Ok(())
+}
+
+/// For migrations, rename a field if it is there
+pub fn rename(doc: &mut Mapping, from: &str, to: &str) {
+    if let Some(v) = doc.remove(from) {
+        doc.insert(to.into(), v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{
+        audio::SoundBank,
+        background::BackgroundBuilder,
+        boss::BossDef,
+        drawable::SpriteSheetDesc,
+        font::GridFontDesc,
+        music::Song,
+        particle::ExplosionBuilder,
+        pattern::{BulletPattern, PatternPreview},
+        pickup::PickupBank,
+        rank::RankCurve,
+        settings::DisplaySettings,
+        sfx::SfxParams,
+        weapon::WeaponDef,
+    };
+
+    /// Version 1 called it `stages`, version 2 `steps`, version 3 added `color`
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Renamed {
+        steps: u32,
+        color: String,
+    }
+
+    impl Versioned for Renamed {
+        const MIGRATIONS: &'static [Migration] = &[
+            |doc| {
+                rename(doc, "stages", "steps");
+                Ok(())
+            },
+            |doc| {
+                doc.insert("color".into(), "red".into());
+                Ok(())
+            },
+        ];
+    }
+
+    #[test]
+    fn unversioned_is_the_first_version() {
+        let v = from_str::<Renamed>("stages: 3").unwrap();
+        assert_eq!(
+            v,
+            Renamed {
+                steps: 3,
+                color: String::from("red")
+            }
+        );
+        assert_eq!(Renamed::version(), 3);
+    }
+
+    #[test]
+    fn only_later_migrations_run() {
+        let v = from_str::<Renamed>("version: 2\nsteps: 4").unwrap();
+        assert_eq!(v.steps, 4);
+        assert_eq!(v.color, "red");
+        let v = from_str::<Renamed>("version: 3\nsteps: 5\ncolor: blue").unwrap();
+        assert_eq!(v.color, "blue");
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let err = from_str::<Renamed>("version: 4\nsteps: 5\ncolor: blue").unwrap_err();
+        assert!(err.to_string().contains("version 4"), "{:#}", err);
+        assert!(from_str::<Renamed>("version: 0\nstages: 5").is_err());
+        assert!(from_str::<Renamed>("version: two\nstages: 5").is_err());
+    }
+
+    #[test]
+    fn failed_migrations_say_which() {
+        struct Broken;
+        impl Versioned for Broken {
+            const MIGRATIONS: &'static [Migration] = &[|_| Ok(()), |_| bail!("broken")];
+        }
+        let mut value = serde_yaml::from_str::<Value>("a: 1").unwrap();
+        let err = upgrade::<Broken>(&mut value).unwrap_err();
+        assert!(format!("{:#}", err).contains("from version 2"), "{:#}", err);
+    }
+
+    #[test]
+    fn saved_with_the_version_first() {
+        let v = Renamed {
+            steps: 5,
+            color: String::from("blue"),
+        };
+        let saved = serde_yaml::to_string(&to_value(&v).unwrap()).unwrap();
+        assert_eq!(saved, "version: 3\nsteps: 5\ncolor: blue\n");
+        assert_eq!(from_str::<Renamed>(&saved).unwrap(), v);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct List(Vec<u32>);
+
+    impl Versioned for List {}
+
+    #[test]
+    fn only_mappings_are_versioned() {
+        assert_eq!(from_str::<List>("[1, 2]").unwrap(), List(vec![1, 2]));
+        let saved = to_value(&List(vec![1])).unwrap();
+        assert!(saved.is_sequence());
+    }
+
+    /// Loads, and what is saved loads again
+    fn check_fixture<T: Versioned + Serialize + DeserializeOwned>(name: &str, data: &[u8]) {
+        let v = from_slice::<T>(data)
+            .with_context(|| format!("fixtures/v1/{}", name))
+            .unwrap();
+        let saved = to_value(&v).unwrap();
+        assert_eq!(saved.get(VERSION_KEY), Some(&Value::from(T::version())));
+        from_value::<T>(saved).unwrap();
+    }
+
+    macro_rules! check_fixtures {
+        ($($file:literal => $id:ty),* $(,)?) => {
+            $(check_fixture::<$id>($file, include_bytes!(concat!("../fixtures/v1/", $file)));)*
+        };
+    }
+
+    /// Every past version of every asset still loads. The v1 fixtures were saved by the code
+    /// from before versioning, the editor and explosion ones by the very first version with its
+    /// `rgba(...)` colors, so never edit them by hand. When a format changes, save a fixture
+    /// into a new `fixtures/v<n>` and check both.
+    #[test]
+    fn version_1_fixtures() {
+        check_fixtures!(
+            "background.yaml" => BackgroundBuilder,
+            "boss.yaml" => BossDef,
+            "explosion.yaml" => ExplosionBuilder,
+            "font.yaml" => GridFontDesc,
+            "pattern.yaml" => BulletPattern,
+            "pattern_preview.yaml" => PatternPreview,
+            "pickups.yaml" => PickupBank,
+            "rank.yaml" => RankCurve,
+            "settings.yaml" => DisplaySettings,
+            "sfx.yaml" => SfxParams,
+            "song.yaml" => Song,
+            "sounds.yaml" => SoundBank,
+            "sprites.yaml" => SpriteSheetDesc,
+            "weapon.yaml" => WeaponDef,
+        );
+    }
+}
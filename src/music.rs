@@ -0,0 +1,721 @@
+//! PICO-8 style music: songs are a list of patterns, each playing up to 4 tracks at once.
+//!
+//! A track (PICO-8 calls it an sfx) is a list of notes played one after the other, every note
+//! with its own instrument, volume and effect. Like `sfx`, rendering is plain math and does not
+//! need an audio device, the same song always renders the same samples.
+use std::{
+    f32::consts::PI,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use ::rand::{rngs::StdRng, Rng, SeedableRng};
+use anyhow::{bail, Context, Result};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    migrate::{self, Versioned},
+    sfx::{play_preview, sound_key, wav_bytes, SAMPLE_RATE},
+};
+
+pub const CHANNELS: usize = 4;
+
+/// PICO-8 ticks 183 samples at 22050Hz, a note lasts `speed` ticks
+const TICK: usize = 183 * SAMPLE_RATE as usize / 22050;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Instrument {
+    #[default]
+    Triangle,
+    TiltedSaw,
+    Sawtooth,
+    Square,
+    Pulse,
+    Organ,
+    Noise,
+    Phaser,
+}
+
+impl Instrument {
+    pub const ALL: [Instrument; 8] = [
+        Instrument::Triangle,
+        Instrument::TiltedSaw,
+        Instrument::Sawtooth,
+        Instrument::Square,
+        Instrument::Pulse,
+        Instrument::Organ,
+        Instrument::Noise,
+        Instrument::Phaser,
+    ];
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Effect {
+    #[default]
+    None,
+    /// Slide pitch and volume from the previous note
+    Slide,
+    Vibrato,
+    /// Pitch falls to nothing over the note
+    Drop,
+    FadeIn,
+    FadeOut,
+    /// Cycle through the 4 notes of this note's group of 4
+    ArpFast,
+    ArpSlow,
+}
+
+impl Effect {
+    pub const ALL: [Effect; 8] = [
+        Effect::None,
+        Effect::Slide,
+        Effect::Vibrato,
+        Effect::Drop,
+        Effect::FadeIn,
+        Effect::FadeOut,
+        Effect::ArpFast,
+        Effect::ArpSlow,
+    ];
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Note {
+    /// Semitones above C0, 33 is A2 (440Hz)
+    pub pitch: u8,
+    pub instrument: Instrument,
+    /// 0 (silent) to 7
+    pub volume: u8,
+    pub effect: Effect,
+}
+
+impl Note {
+    pub fn freq(&self) -> f32 {
+        pitch_freq(self.pitch)
+    }
+}
+
+fn pitch_freq(pitch: u8) -> f32 {
+    440. * ((pitch as f32 - 33.) / 12.).exp2()
+}
+
+/// A PICO-8 sfx
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Track {
+    /// Ticks per note, a tick is 1/120th of a second
+    pub speed: u8,
+    /// Notes from `loop_start` up to `loop_end` repeat when `loop_end` is past `loop_start`. With
+    /// only `loop_start` set, it is the length of the track instead.
+    pub loop_start: u8,
+    pub loop_end: u8,
+    pub notes: Vec<Note>,
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Self {
+            speed: 16,
+            loop_start: 0,
+            loop_end: 0,
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl Track {
+    pub fn is_looping(&self) -> bool {
+        self.loop_end > self.loop_start
+    }
+
+    /// Notes played before the track ends or loops
+    pub fn len(&self) -> usize {
+        if self.is_looping() {
+            (self.loop_end as usize).min(self.notes.len())
+        } else if self.loop_end == 0 && self.loop_start > 0 {
+            (self.loop_start as usize).min(self.notes.len())
+        } else {
+            self.notes.len()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Samples per note
+    pub fn note_len(&self) -> usize {
+        self.speed.max(1) as usize * TICK
+    }
+
+    /// Index of the note playing after `index` notes, `None` once a track that does not loop
+    /// has ended
+    fn note_index(&self, index: usize) -> Option<usize> {
+        let end = self.len();
+        if index < end {
+            Some(index)
+        } else if self.is_looping() && (self.loop_start as usize) < end {
+            let start = self.loop_start as usize;
+            Some(start + (index - start) % (end - start))
+        } else {
+            None
+        }
+    }
+}
+
+/// One step of a song, the tracks each channel plays at the same time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pattern {
+    /// Index into `Song::tracks`, `None` is a silent channel
+    pub channels: [Option<usize>; CHANNELS],
+    pub loop_start: bool,
+    /// Go back to the last `loop_start` pattern
+    pub loop_end: bool,
+    /// The song ends after this pattern
+    pub stop: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Song {
+    pub name: String,
+    pub tracks: Vec<Track>,
+    pub patterns: Vec<Pattern>,
+    pub volume: f32,
+}
+
+impl Default for Song {
+    fn default() -> Self {
+        Self {
+            name: String::from("song"),
+            tracks: Vec::new(),
+            patterns: Vec::new(),
+            volume: 0.5,
+        }
+    }
+}
+
+impl Versioned for Song {}
+
+impl Song {
+    /// Load a YAML song, or the `__sfx__` and `__music__` of a PICO-8 `.p8` cart
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Could not open {}", path.display()))?;
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("p8") => Self::parse_p8(name, &data),
+            _ => migrate::from_str::<Self>(&data),
+        }
+        .with_context(|| format!("could not parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        migrate::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    pub fn parse_p8(name: &str, data: &str) -> Result<Self> {
+        let tracks = p8_section(data, "__sfx__")
+            .map(parse_p8_track)
+            .collect::<Result<Vec<_>>>()?;
+        let patterns = p8_section(data, "__music__")
+            .map(parse_p8_pattern)
+            .collect::<Result<Vec<_>>>()?;
+        if tracks.is_empty() {
+            bail!("cart has no __sfx__");
+        }
+        Ok(Self {
+            name: String::from(name),
+            tracks,
+            patterns,
+            ..Default::default()
+        })
+    }
+
+    /// Samples the pattern lasts: the first channel with a track that does not loop decides, if
+    /// they all loop, the first channel with a track does.
+    pub fn pattern_len(&self, pattern: &Pattern) -> usize {
+        let tracks = pattern
+            .channels
+            .iter()
+            .flatten()
+            .filter_map(|t| self.tracks.get(*t));
+        let mut looping = None;
+        for track in tracks {
+            if !track.is_looping() {
+                return track.len() * track.note_len();
+            }
+            looping.get_or_insert(track);
+        }
+        looping.map_or(0, |t| t.len() * t.note_len())
+    }
+
+    /// Pattern indices in the order they play, going back to the loop start at most `loops`
+    /// times
+    pub fn order(&self, loops: u32) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut loop_start = 0;
+        let mut looped = 0;
+        let mut i = 0;
+        while let Some(pattern) = self.patterns.get(i) {
+            if pattern.loop_start {
+                loop_start = i;
+            }
+            order.push(i);
+            if pattern.stop {
+                break;
+            }
+            if pattern.loop_end && looped < loops {
+                looped += 1;
+                i = loop_start;
+            } else {
+                i += 1;
+            }
+        }
+        order
+    }
+
+    /// Mono samples from -1 to 1 at `SAMPLE_RATE`
+    pub fn render(&self, loops: u32) -> Vec<f32> {
+        let mut mixer = Mixer::default();
+        let mut samples = Vec::new();
+        for i in self.order(loops) {
+            mixer.render_pattern(self, &self.patterns[i], &mut samples);
+        }
+        samples
+    }
+
+    /// Render one track on its own, once through
+    pub fn render_track(&self, track: usize) -> Vec<f32> {
+        let pattern = Pattern {
+            channels: [Some(track), None, None, None],
+            ..Default::default()
+        };
+        let mut samples = Vec::new();
+        Mixer::default().render_pattern(self, &pattern, &mut samples);
+        samples
+    }
+
+    pub fn play(&self, loops: u32) {
+        let key = sound_key(&(&self.tracks, &self.patterns, self.volume.to_bits(), loops));
+        play_preview(key, || wav_bytes(&self.render(loops)));
+    }
+}
+
+/// Lines after `header` up to the next section
+fn p8_section<'a>(data: &'a str, header: &'a str) -> impl Iterator<Item = &'a str> {
+    data.lines()
+        .map(str::trim)
+        .skip_while(move |l| *l != header)
+        .skip(1)
+        .take_while(|l| !(l.starts_with("__") && l.ends_with("__")))
+        .filter(|l| !l.is_empty())
+}
+
+fn hex(s: &str, at: usize, len: usize) -> Result<u8> {
+    s.get(at..at + len)
+        .and_then(|v| u8::from_str_radix(v, 16).ok())
+        .with_context(|| format!("bad hex at {} in {}", at, s))
+}
+
+/// `eessllee` (editor mode, speed, loop start, loop end) then 32 `ppivf` notes
+fn parse_p8_track(line: &str) -> Result<Track> {
+    if line.len() != 8 + 32 * 5 {
+        bail!("sfx line is {} long, not 168: {}", line.len(), line);
+    }
+    let notes = (0..32)
+        .map(|i| {
+            let at = 8 + i * 5;
+            Ok(Note {
+                pitch: hex(line, at, 2)?,
+                // Bit 3 picks one of sfx 0-7 as a custom instrument, those play as the base
+                // waveform
+                instrument: Instrument::ALL[hex(line, at + 2, 1)? as usize & 7],
+                volume: hex(line, at + 3, 1)? & 7,
+                effect: Effect::ALL[hex(line, at + 4, 1)? as usize & 7],
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Track {
+        speed: hex(line, 2, 2)?,
+        loop_start: hex(line, 4, 2)?,
+        loop_end: hex(line, 6, 2)?,
+        notes,
+    })
+}
+
+/// `ff aabbccdd`, flags then the sfx of each channel, with bit 6 set when the channel is off
+fn parse_p8_pattern(line: &str) -> Result<Pattern> {
+    let (flags, channels) = line
+        .split_once(' ')
+        .with_context(|| format!("bad music line {}", line))?;
+    if channels.len() != CHANNELS * 2 {
+        bail!("bad music line {}", line);
+    }
+    let flags = hex(flags, 0, 2)?;
+    let mut pattern = Pattern {
+        loop_start: flags & 1 != 0,
+        loop_end: flags & 2 != 0,
+        stop: flags & 4 != 0,
+        ..Default::default()
+    };
+    for (i, channel) in pattern.channels.iter_mut().enumerate() {
+        let v = hex(channels, i * 2, 2)?;
+        if v & 0x40 == 0 {
+            *channel = Some((v & 0x3f) as usize);
+        }
+    }
+    Ok(pattern)
+}
+
+/// Plays one channel, keeping the phase and the last note between patterns so slides and
+/// waveforms carry on
+#[derive(Debug, Clone)]
+struct Voice {
+    phase: f32,
+    phaser: f32,
+    noise: f32,
+    noise_step: u32,
+    freq: f32,
+    volume: f32,
+    rng: StdRng,
+}
+
+impl Voice {
+    fn new(seed: u64) -> Self {
+        Self {
+            phase: 0.,
+            phaser: 0.,
+            noise: 0.,
+            noise_step: 0,
+            freq: 0.,
+            volume: 0.,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    fn wave(&mut self, instrument: Instrument) -> f32 {
+        let p = self.phase;
+        let tri = |p: f32| 4. * (p - 0.5).abs() - 1.;
+        match instrument {
+            Instrument::Triangle => tri(p),
+            Instrument::TiltedSaw => {
+                if p < 0.875 {
+                    p / 0.875 * 2. - 1.
+                } else {
+                    (1. - p) / 0.125 * 2. - 1.
+                }
+            }
+            Instrument::Sawtooth => 2. * p - 1.,
+            Instrument::Square => {
+                if p < 0.5 {
+                    1.
+                } else {
+                    -1.
+                }
+            }
+            Instrument::Pulse => {
+                if p < 0.3125 {
+                    1.
+                } else {
+                    -1.
+                }
+            }
+            Instrument::Organ => (tri(p) + tri((p * 2.) % 1.)) * 0.5,
+            Instrument::Noise => {
+                // A new value 16 times a period, so higher notes sound brighter
+                let step = (p * 16.) as u32;
+                if step != self.noise_step {
+                    self.noise_step = step;
+                    self.noise = self.rng.gen_range(-1_f32..1.);
+                }
+                self.noise
+            }
+            Instrument::Phaser => (tri(p) + tri(self.phaser)) * 0.5,
+        }
+    }
+
+    /// Add `len` samples of `track` from its start to `out`
+    fn render(&mut self, track: &Track, len: usize, out: &mut [f32]) {
+        let note_len = track.note_len();
+        let dt = 1. / SAMPLE_RATE as f32;
+        let mut from = (self.freq, self.volume);
+        for (i, sample) in out.iter_mut().enumerate().take(len) {
+            let Some(index) = track.note_index(i / note_len) else {
+                break;
+            };
+            let Some(note) = track.notes.get(index) else {
+                break;
+            };
+            let in_note = i % note_len;
+            if in_note == 0 {
+                from = (self.freq, self.volume);
+            }
+            let t = in_note as f32 / note_len as f32;
+            let mut freq = note.freq();
+            let mut volume = note.volume as f32 / 7.;
+            match note.effect {
+                Effect::None => {}
+                Effect::Slide => {
+                    freq = from.0 + (freq - from.0) * t;
+                    volume = from.1 + (volume - from.1) * t;
+                }
+                Effect::Vibrato => {
+                    let seconds = in_note as f32 * dt;
+                    freq *= (0.5 / 12. * (2. * PI * 7.5 * seconds).sin()).exp2();
+                }
+                Effect::Drop => freq *= 1. - t,
+                Effect::FadeIn => volume *= t,
+                Effect::FadeOut => volume *= 1. - t,
+                Effect::ArpFast | Effect::ArpSlow => {
+                    let ticks = if note.effect == Effect::ArpFast { 2 } else { 4 };
+                    let step = (i / (TICK * ticks)) % 4;
+                    let group = index & !3;
+                    if let Some(arp) = track.notes.get(group + step) {
+                        freq = arp.freq();
+                    }
+                }
+            }
+            self.phase = (self.phase + freq * dt) % 1.;
+            self.phaser = (self.phaser + freq * 1.0125 * dt) % 1.;
+            if volume > 0. {
+                *sample += self.wave(note.instrument) * volume;
+            }
+            // Slides start from where the last note really was, effects included
+            if in_note + 1 == note_len {
+                self.freq = freq;
+                self.volume = volume;
+            }
+        }
+    }
+}
+
+/// Renders patterns channel by channel and mixes them down to one buffer
+#[derive(Debug, Clone)]
+pub struct Mixer {
+    voices: [Voice; CHANNELS],
+}
+
+impl Default for Mixer {
+    /// Every channel gets its own fixed seed, so noise is the same on every render
+    fn default() -> Self {
+        Self {
+            voices: [Voice::new(0), Voice::new(1), Voice::new(2), Voice::new(3)],
+        }
+    }
+}
+
+impl Mixer {
+    /// Append the samples of `pattern` to `out`
+    pub fn render_pattern(&mut self, song: &Song, pattern: &Pattern, out: &mut Vec<f32>) {
+        let len = song.pattern_len(pattern);
+        let start = out.len();
+        out.resize(start + len, 0.);
+        for (voice, channel) in self.voices.iter_mut().zip(pattern.channels) {
+            if let Some(track) = channel.and_then(|c| song.tracks.get(c)) {
+                voice.render(track, len, &mut out[start..]);
+            }
+        }
+        let gain = song.volume / CHANNELS as f32;
+        for sample in &mut out[start..] {
+            *sample = (*sample * gain).clamp(-1., 1.);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(pitch: u8, instrument: Instrument, volume: u8) -> Note {
+        Note {
+            pitch,
+            instrument,
+            volume,
+            effect: Effect::None,
+        }
+    }
+
+    fn track(speed: u8, notes: Vec<Note>) -> Track {
+        Track {
+            speed,
+            notes,
+            ..Default::default()
+        }
+    }
+
+    fn pattern(channels: [Option<usize>; CHANNELS]) -> Pattern {
+        Pattern {
+            channels,
+            ..Default::default()
+        }
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0., |m, s| s.abs().max(m))
+    }
+
+    /// A4 square at full volume, four notes of 2 ticks
+    fn square_song() -> Song {
+        Song {
+            tracks: vec![track(2, vec![note(33, Instrument::Square, 7); 4])],
+            patterns: vec![pattern([Some(0), None, None, None])],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pattern_length() {
+        let song = square_song();
+        assert_eq!(TICK, 366);
+        assert_eq!(song.render(0).len(), 4 * 2 * TICK);
+        assert_eq!(song.render_track(0).len(), 4 * 2 * TICK);
+    }
+
+    #[test]
+    fn one_channel_peaks_at_its_share() {
+        // Each channel gets a quarter of the song volume
+        let samples = square_song().render(0);
+        assert_eq!(peak(&samples), 0.5 / 4.);
+        assert!(samples.iter().all(|s| s.abs() == 0.125));
+    }
+
+    #[test]
+    fn channels_add_up() {
+        let mut song = square_song();
+        song.patterns = vec![pattern([Some(0); CHANNELS])];
+        assert_eq!(peak(&song.render(0)), 0.5);
+        song.volume = 4.;
+        assert_eq!(peak(&song.render(0)), 1., "clamped");
+    }
+
+    #[test]
+    fn silence() {
+        let mut song = square_song();
+        song.tracks
+            .push(track(2, vec![note(33, Instrument::Square, 0); 4]));
+        song.patterns = vec![pattern([Some(1), None, None, None])];
+        let samples = song.render(0);
+        assert_eq!(samples.len(), 4 * 2 * TICK);
+        assert!(samples.iter().all(|s| *s == 0.));
+        // Missing tracks and channels are silent too
+        song.patterns = vec![pattern([None, Some(9), None, None])];
+        assert!(song.render(0).is_empty());
+    }
+
+    #[test]
+    fn rests_are_silent() {
+        let mut song = square_song();
+        song.tracks[0].notes[1].volume = 0;
+        let samples = song.render(0);
+        let note_len = 2 * TICK;
+        assert_eq!(peak(&samples[..note_len]), 0.125);
+        assert_eq!(peak(&samples[note_len..2 * note_len]), 0.);
+        assert_eq!(peak(&samples[2 * note_len..]), 0.125);
+    }
+
+    #[test]
+    fn fade_out() {
+        let mut song = square_song();
+        song.tracks[0].notes = vec![Note {
+            effect: Effect::FadeOut,
+            ..note(33, Instrument::Square, 7)
+        }];
+        let samples = song.render(0);
+        let half = samples.len() / 2;
+        assert!(peak(&samples[..10]) > 0.12);
+        assert!(peak(&samples[half..]) <= 0.0626);
+        assert!(peak(&samples[samples.len() - 10..]) < 0.002);
+    }
+
+    #[test]
+    fn loops_and_stops() {
+        let mut song = square_song();
+        let one = song.render(0).len();
+        song.patterns = vec![
+            Pattern {
+                loop_start: true,
+                ..pattern([Some(0), None, None, None])
+            },
+            Pattern {
+                loop_end: true,
+                ..pattern([Some(0), None, None, None])
+            },
+            Pattern {
+                stop: true,
+                ..pattern([Some(0), None, None, None])
+            },
+            pattern([Some(0), None, None, None]),
+        ];
+        assert_eq!(song.order(0), [0, 1, 2]);
+        assert_eq!(song.order(2), [0, 1, 0, 1, 0, 1, 2]);
+        assert_eq!(song.render(2).len(), 7 * one);
+    }
+
+    #[test]
+    fn looping_tracks_follow_the_first_that_ends() {
+        let mut song = square_song();
+        song.tracks.push(Track {
+            loop_start: 0,
+            loop_end: 2,
+            ..track(1, vec![note(40, Instrument::Triangle, 7); 2])
+        });
+        song.patterns = vec![pattern([Some(1), Some(0), None, None])];
+        assert_eq!(song.pattern_len(&song.patterns[0]), 4 * 2 * TICK);
+        song.patterns = vec![pattern([Some(1), None, None, None])];
+        assert_eq!(song.pattern_len(&song.patterns[0]), 2 * TICK);
+    }
+
+    #[test]
+    fn same_song_same_samples() {
+        let mut song = square_song();
+        song.tracks[0].notes = vec![note(20, Instrument::Noise, 5); 4];
+        let samples = song.render(0);
+        assert_eq!(samples, song.render(0));
+        assert!(peak(&samples) > 0.);
+    }
+
+    #[test]
+    fn parse_p8_cart() {
+        // An A4 square then rests, looping over the first 4 notes, and a silent track
+        let first = format!("01100004{}{}", "21350", "00000".repeat(31));
+        let second = format!("000a0000{}", "0c000".repeat(32));
+        let cart = format!(
+            "pico-8 cartridge\nversion 41\n__gfx__\n0000\n__sfx__\n{}\n{}\n\n__music__\n\
+             01 00414243\n06 01004344\n",
+            first, second
+        );
+        let song = Song::parse_p8("cart", &cart).unwrap();
+        assert_eq!(song.name, "cart");
+        assert_eq!(song.tracks.len(), 2);
+        let track = &song.tracks[0];
+        assert_eq!((track.speed, track.loop_start, track.loop_end), (16, 0, 4));
+        assert_eq!(track.notes.len(), 32);
+        assert_eq!(track.notes[0], note(33, Instrument::Square, 5));
+        assert_eq!(track.notes[1].volume, 0);
+        assert_eq!(song.tracks[1].speed, 10);
+        assert_eq!(song.tracks[1].notes[0].pitch, 12);
+
+        assert_eq!(song.patterns.len(), 2);
+        assert_eq!(song.patterns[0].channels, [Some(0), None, None, None]);
+        assert!(song.patterns[0].loop_start);
+        assert_eq!(song.patterns[1].channels, [Some(1), Some(0), None, None]);
+        assert!(song.patterns[1].loop_end && song.patterns[1].stop);
+    }
+
+    #[test]
+    fn parse_p8_errors() {
+        assert!(Song::parse_p8("cart", "__music__\n01 00414243\n").is_err());
+        assert!(Song::parse_p8("cart", "__sfx__\n0110\n").is_err());
+        let sfx = format!("__sfx__\n01100000{}\n", "zz000".repeat(32));
+        assert!(Song::parse_p8("cart", &sfx).is_err());
+    }
+}
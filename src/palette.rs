@@ -0,0 +1,338 @@
+use std::{cell::RefCell, fs, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use macroquad::prelude::Color;
+
+use crate::utils::GameColor;
+
+/// An indexed list of colors, like PICO-8's 16 colors.
+///
+/// Colors created with `GameColor::indexed` are looked up in the active palette (see
+/// `set_active`) every time they are drawn, so swapping the palette or remapping indices changes
+/// everything that uses them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    colors: Vec<Color>,
+}
+
+const PICO8: [u32; 16] = [
+    0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8, 0xff004d,
+    0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+];
+
+fn rgb(v: u32) -> Color {
+    Color::from_rgba((v >> 16) as u8, (v >> 8) as u8, v as u8, 255)
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::pico8()
+    }
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: Vec<Color>) -> Self {
+        Self {
+            name: String::from(name),
+            colors,
+        }
+    }
+
+    pub fn pico8() -> Self {
+        Self::new("PICO-8", PICO8.iter().copied().map(rgb).collect())
+    }
+
+    /// Load a `.hex` (one `rrggbb` per line, as exported by Lospec) or a GIMP `.gpl` palette
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Could not open {}", path.display()))?;
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gpl") => Self::parse_gpl(&data),
+            _ => Self::parse_hex(name, &data),
+        }
+        .with_context(|| format!("could not parse {}", path.display()))
+    }
+
+    pub fn parse_hex(name: &str, data: &str) -> Result<Self> {
+        let colors = data
+            .lines()
+            .map(|l| l.trim().trim_start_matches('#'))
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                if l.len() != 6 {
+                    bail!("{} is not a rrggbb color", l);
+                }
+                u32::from_str_radix(l, 16)
+                    .map(rgb)
+                    .with_context(|| format!("{} is not a rrggbb color", l))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::checked(name, colors)
+    }
+
+    pub fn parse_gpl(data: &str) -> Result<Self> {
+        let mut lines = data.lines();
+        if lines.next().map(|l| l.trim()) != Some("GIMP Palette") {
+            bail!("missing GIMP Palette header");
+        }
+        let mut name = "";
+        let mut colors = Vec::new();
+        for line in lines {
+            let line = line.trim();
+            if let Some(n) = line.strip_prefix("Name:") {
+                name = n.trim();
+                continue;
+            }
+            if line.is_empty() || line.starts_with('#') || line.starts_with("Columns:") {
+                continue;
+            }
+            let channels = line
+                .split_whitespace()
+                .take(3)
+                .map(|c| c.parse::<u8>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| anyhow!("bad color line {}", line))?;
+            if channels.len() != 3 {
+                bail!("bad color line {}", line);
+            }
+            colors.push(Color::from_rgba(channels[0], channels[1], channels[2], 255));
+        }
+        Self::checked(name, colors)
+    }
+
+    fn checked(name: &str, colors: Vec<Color>) -> Result<Self> {
+        if colors.is_empty() {
+            bail!("palette has no colors");
+        }
+        if colors.len() > 256 {
+            bail!("palette has more than 256 colors");
+        }
+        Ok(Self::new(name, colors))
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub fn get(&self, index: u8) -> Option<Color> {
+        self.colors.get(index as usize).copied()
+    }
+
+    /// Every color of the palette, as colors that refer back to their index
+    pub fn indexed_colors(&self) -> Vec<GameColor> {
+        (0..self.colors.len())
+            .map(|i| GameColor::indexed(i as u8, self.colors[i]))
+            .collect()
+    }
+
+    /// Index of the palette color closest to `color`
+    pub fn nearest(&self, color: Color) -> u8 {
+        let dist =
+            |c: &Color| (c.r - color.r).powi(2) + (c.g - color.g).powi(2) + (c.b - color.b).powi(2);
+        self.colors
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| dist(a).total_cmp(&dist(b)))
+            .map(|(i, _)| i as u8)
+            .unwrap_or_default()
+    }
+}
+
+/// Replaces palette indices with other indices when drawing, like PICO-8's `pal(a, b)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteRemap(Vec<u8>);
+
+impl Default for PaletteRemap {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl PaletteRemap {
+    pub fn identity() -> Self {
+        Self((0..=255).collect())
+    }
+
+    /// Draw `from` as `to`
+    pub fn with(mut self, from: u8, to: u8) -> Self {
+        self.0[from as usize] = to;
+        self
+    }
+
+    /// Draw every color as `to`, e.g. white for a hit flash
+    pub fn all_to(to: u8) -> Self {
+        Self(vec![to; 256])
+    }
+
+    /// Darken every color towards black by `amount` (0 is unchanged, 1 is black), picking the
+    /// nearest color of the palette at each step
+    pub fn fade(palette: &Palette, amount: f32) -> Self {
+        let amount = amount.clamp(0., 1.);
+        let mut remap = Self::identity();
+        for i in 0..palette.len() {
+            let c = palette.colors[i];
+            let k = 1. - amount;
+            remap.0[i] = palette.nearest(Color::new(c.r * k, c.g * k, c.b * k, c.a));
+        }
+        remap
+    }
+
+    pub fn map(&self, index: u8) -> u8 {
+        self.0[index as usize]
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.0.iter().enumerate().all(|(i, v)| i == *v as usize)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ActivePalette {
+    palette: Palette,
+    remap: PaletteRemap,
+}
+
+thread_local! {
+    static ACTIVE: RefCell<ActivePalette> = RefCell::new(ActivePalette::default());
+}
+
+/// Make `palette` the one that indexed colors are drawn with
+pub fn set_active(palette: Palette) {
+    ACTIVE.with(|a| a.borrow_mut().palette = palette);
+}
+
+pub fn with_active<R>(f: impl FnOnce(&Palette) -> R) -> R {
+    ACTIVE.with(|a| f(&a.borrow().palette))
+}
+
+/// Remap indices until the remap is replaced or reset. Fades are usually set this way.
+pub fn set_remap(remap: PaletteRemap) {
+    ACTIVE.with(|a| a.borrow_mut().remap = remap);
+}
+
+pub fn reset_remap() {
+    set_remap(PaletteRemap::identity());
+}
+
+pub fn remap() -> PaletteRemap {
+    ACTIVE.with(|a| a.borrow().remap.clone())
+}
+
+/// Remap indices only while drawing inside `f`, e.g. to flash one enemy white
+pub fn with_remap<R>(remap: PaletteRemap, f: impl FnOnce() -> R) -> R {
+    let previous = ACTIVE.with(|a| std::mem::replace(&mut a.borrow_mut().remap, remap));
+    let r = f();
+    set_remap(previous);
+    r
+}
+
+/// The color an index is drawn with right now, after remapping
+pub fn resolve(index: u8) -> Option<Color> {
+    ACTIVE.with(|a| {
+        let a = a.borrow();
+        a.palette.get(a.remap.map(index))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bw() -> Palette {
+        Palette::parse_hex("bw", "000000\n808080\nffffff\n").unwrap()
+    }
+
+    #[test]
+    fn parses_hex() {
+        let p = Palette::parse_hex("test", "#ff0000\n\n  00ff00  \n0000ff\n").unwrap();
+        assert_eq!(p.name, "test");
+        assert_eq!(p.len(), 3);
+        assert_eq!(p.get(0), Some(Color::from_rgba(255, 0, 0, 255)));
+        assert_eq!(p.get(2), Some(Color::from_rgba(0, 0, 255, 255)));
+        assert_eq!(p.get(3), None);
+
+        assert!(Palette::parse_hex("bad", "ff00").is_err());
+        assert!(Palette::parse_hex("bad", "gg0000").is_err());
+        assert!(Palette::parse_hex("empty", "\n\n").is_err());
+        assert!(Palette::parse_hex("big", &"000000\n".repeat(257)).is_err());
+    }
+
+    #[test]
+    fn parses_gpl() {
+        let data = "GIMP Palette\nName: Mono\nColumns: 2\n# comment\n\n  0   0   0\tBlack\n255 255 255 White\n";
+        let p = Palette::parse_gpl(data).unwrap();
+        assert_eq!(p.name, "Mono");
+        assert_eq!(p.len(), 2);
+        assert_eq!(p.get(1), Some(Color::from_rgba(255, 255, 255, 255)));
+
+        assert!(Palette::parse_gpl("Name: Mono\n0 0 0\n").is_err());
+        assert!(Palette::parse_gpl("GIMP Palette\n0 0\n").is_err());
+        assert!(Palette::parse_gpl("GIMP Palette\n0 0 300\n").is_err());
+        assert!(Palette::parse_gpl("GIMP Palette\nName: Empty\n").is_err());
+    }
+
+    #[test]
+    fn nearest_color() {
+        let p = bw();
+        assert_eq!(p.nearest(Color::new(0.1, 0.1, 0.1, 1.)), 0);
+        assert_eq!(p.nearest(Color::new(0.4, 0.6, 0.5, 1.)), 1);
+        assert_eq!(p.nearest(Color::new(1., 0.9, 1., 1.)), 2);
+        assert_eq!(
+            Palette::pico8().nearest(Color::from_rgba(255, 0, 80, 255)),
+            8
+        );
+    }
+
+    #[test]
+    fn remaps() {
+        let remap = PaletteRemap::identity();
+        assert!(remap.is_identity());
+        let remap = remap.with(1, 7);
+        assert!(!remap.is_identity());
+        assert_eq!(remap.map(1), 7);
+        assert_eq!(remap.map(2), 2);
+
+        let flash = PaletteRemap::all_to(7);
+        assert!((0..=255).all(|i| flash.map(i) == 7));
+    }
+
+    #[test]
+    fn fades_towards_black() {
+        let p = bw();
+        assert!(PaletteRemap::fade(&p, 0.).is_identity());
+        let half = PaletteRemap::fade(&p, 0.5);
+        assert_eq!((half.map(0), half.map(1), half.map(2)), (0, 0, 1));
+        let black = PaletteRemap::fade(&p, 2.);
+        assert_eq!((black.map(0), black.map(1), black.map(2)), (0, 0, 0));
+        // Indices past the end of the palette are left alone
+        assert_eq!(black.map(3), 3);
+    }
+
+    #[test]
+    fn resolves_through_the_active_remap() {
+        set_active(bw());
+        reset_remap();
+        assert_eq!(resolve(2), bw().get(2));
+        assert_eq!(resolve(3), None);
+
+        let flashed = with_remap(PaletteRemap::all_to(2), || resolve(0));
+        assert_eq!(flashed, bw().get(2));
+        assert_eq!(resolve(0), bw().get(0));
+
+        set_remap(PaletteRemap::identity().with(0, 1));
+        assert_eq!(resolve(0), bw().get(1));
+        reset_remap();
+        assert!(remap().is_identity());
+        assert_eq!(with_active(|p| p.name.clone()), "bw");
+    }
+}
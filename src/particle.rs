@@ -1,6 +1,6 @@
 mod circle;
 use crate::{
-    alive::IsAlive, buildable::Buildable, drawable::Drawable, minmax::MinMax,
+    alive::IsAlive, buildable::Buildable, drawable::Drawable, migrate::Versioned, minmax::MinMax,
     updateable::Updateable, utils::GameColor, widgets::color_picker::color_edit_palette_button,
     CenterPt, Size, Velocity,
 };
@@ -8,6 +8,7 @@ pub use circle::CircleParticle;
 use egui_macroquad::egui::{self, Grid, Ui};
 use macroquad::{
     prelude::{BLUE, GREEN, ORANGE, YELLOW},
+    rand::gen_range,
     shapes::{draw_circle_lines, draw_line},
 };
 use serde::{Deserialize, Serialize};
@@ -129,7 +130,7 @@ impl ExplosionStage {
             let cp = CircleParticle::new(
                 (cx + ax * r, cy + ay * r).into(),
                 self.radius.rand(),
-                self.color.into(),
+                self.color,
             )
             .with_ttl(t)
             .with_delay(d)
@@ -140,9 +141,6 @@ impl ExplosionStage {
     }
 
     pub fn editor_ui(&mut self, ui: &mut Ui, id: usize) {
-        use crate::utils::*;
-        let palette = PALETTE;
-
         ui.horizontal(|ui| {
             ui.heading(format!("Explosion circle stage #{}", id + 1));
             if ui.small_button("").clicked() {
@@ -175,7 +173,7 @@ impl ExplosionStage {
             ui.end_row();
 
             ui.label("Color");
-            color_edit_palette_button(ui, &mut self.color, &palette);
+            color_edit_palette_button(ui, &mut self.color);
             ui.end_row();
         });
     }
@@ -206,6 +204,8 @@ impl ExplosionStage {
     }
 }
 
+impl Versioned for ExplosionBuilder {}
+
 impl Buildable for ExplosionBuilder {
     type Byproduct = Explosion;
 
@@ -286,6 +286,29 @@ impl Buildable for ExplosionBuilder {
 }
 
 impl ExplosionBuilder {
+    /// A quick orange and yellow blast about `size` pixels across
+    pub fn burst(size: f32) -> Self {
+        Explosion::begin()
+            .with_circle_stage(
+                ExplosionStage::default()
+                    .with_count(3, 6)
+                    .with_dist(0., size)
+                    .with_radius(1., size / 2.)
+                    .with_delay(0., 0.2)
+                    .with_age(0.2, 0.6)
+                    .with_color(crate::utils::ORANGE),
+            )
+            .with_circle_stage(
+                ExplosionStage::default()
+                    .with_count(2, 4)
+                    .with_dist(0., size / 2.)
+                    .with_radius(1., size / 3.)
+                    .with_delay(0.1, 0.3)
+                    .with_age(0.2, 0.5)
+                    .with_color(crate::utils::YELLOW),
+            )
+    }
+
     pub fn with_stages(mut self, stages: Vec<ExplosionStage>) -> Self {
         self.stages = stages;
         self
@@ -308,6 +331,23 @@ impl Explosion {
             ..Default::default()
         }
     }
+
+    /// A few tiny, quick sparks flying away from `from`, like a bullet grazing the player
+    pub fn sparks(center: CenterPt, from: CenterPt, color: GameColor) -> Self {
+        let (cx, cy) = center.get();
+        let (fx, fy) = from.get();
+        let away = (cy - fy).atan2(cx - fx);
+        let circles = (0..gen_range(2, 4))
+            .map(|_| {
+                let angle = away + gen_range(-0.6, 0.6);
+                let speed = gen_range(30., 60.);
+                CircleParticle::new(center, 0.5, color)
+                    .with_ttl(gen_range(0.1, 0.25))
+                    .with_velocity((angle.cos() * speed, angle.sin() * speed).into())
+            })
+            .collect();
+        Self { circles }
+    }
 }
 
 impl Drawable for Explosion {
@@ -1,7 +1,8 @@
 use crate::{
-    alive::IsAlive, drawable::Drawable, updateable::Updateable, Accel, CenterPt, Velocity,
+    alive::IsAlive, drawable::Drawable, updateable::Updateable, utils::GameColor, Accel, CenterPt,
+    Velocity,
 };
-use macroquad::{prelude::Color, shapes::draw_circle};
+use macroquad::shapes::draw_circle;
 
 use super::Particle;
 
@@ -9,7 +10,7 @@ use super::Particle;
 pub struct CircleParticle {
     center: CenterPt,
     radius: f32,
-    color: Color,
+    color: GameColor,
     velocity: Velocity,
     accel: Accel,
     ttl: f32,
@@ -17,11 +18,11 @@ pub struct CircleParticle {
 }
 
 impl CircleParticle {
-    pub fn new(center: CenterPt, radius: f32, color: Color) -> Self {
+    pub fn new(center: CenterPt, radius: f32, color: impl Into<GameColor>) -> Self {
         Self {
             center,
             radius,
-            color,
+            color: color.into(),
             ttl: 5.0,
             ..Default::default()
         }
@@ -63,7 +64,7 @@ impl Particle for CircleParticle {}
 impl Drawable for CircleParticle {
     fn draw(&self) {
         if self.is_visible() {
-            draw_circle(self.center.0, self.center.1, self.radius, self.color)
+            draw_circle(self.center.0, self.center.1, self.radius, self.color.into())
         }
     }
 }
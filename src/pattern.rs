@@ -0,0 +1,1115 @@
+//! Bullet patterns in the spirit of BulletML: actions fire bullets, wait, repeat and steer, and
+//! bullets can run actions of their own. Written in YAML, or imported from BulletML XML.
+//!
+//! Patterns run at a fixed `TICKS_PER_SECOND` with their own random numbers, so a pattern started
+//! with the same seed plays out the same at any frame rate. Directions are degrees clockwise from
+//! straight up, speeds are pixels per tick.
+mod bulletml;
+mod expr;
+
+use std::{collections::BTreeMap, fs, ops::Deref, path::Path};
+
+use ::rand::{rngs::StdRng, SeedableRng};
+use anyhow::{bail, Context as _, Result};
+use egui_macroquad::egui::{self, DragValue, Ui};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use self::expr::Env;
+pub use self::expr::Expr;
+use crate::{
+    alive::IsAlive,
+    assets::{self, Handle},
+    buildable::Buildable,
+    bullet::Bullet,
+    drawable::{Drawable, HasCenter, UpdateCenter, UpdateVelocity},
+    migrate::{self, Versioned},
+    updateable::Updateable,
+    utils::{self, GameColor},
+    widgets::color_picker::color_edit_palette_button,
+    CenterPt, Rc, Velocity,
+};
+
+pub const DEFAULT_PATTERN_FILE: &str = "pattern.yaml";
+pub const TICKS_PER_SECOND: f32 = 60.;
+/// So a long hitch does not freeze the game while patterns catch up
+const MAX_TICKS_PER_UPDATE: u32 = 8;
+/// An action is cut off after this many steps in one tick, a `Repeat` without a `Wait` would
+/// never end
+const MAX_STEPS_PER_TICK: usize = 10_000;
+/// Against actions that call themselves
+const MAX_DEPTH: usize = 64;
+/// Seconds the editor plays a pattern before starting over
+const PREVIEW_TIME: f32 = 8.;
+
+fn expr(source: &str) -> Expr {
+    source.parse().expect("bad built in expression")
+}
+
+/// Shared, so running actions hold on to them cheaply
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<Step>", into = "Vec<Step>")]
+pub struct Steps(Rc<Vec<Step>>);
+
+impl From<Vec<Step>> for Steps {
+    fn from(value: Vec<Step>) -> Self {
+        Self(Rc::new(value))
+    }
+}
+
+impl From<Steps> for Vec<Step> {
+    fn from(value: Steps) -> Self {
+        (*value.0).clone()
+    }
+}
+
+impl Deref for Steps {
+    type Target = [Step];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Degrees clockwise from straight up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Added to the direction towards the target
+    Aim(Expr),
+    Absolute(Expr),
+    /// Added to the direction of whatever runs the action
+    Relative(Expr),
+    /// Added to the direction of the last bullet the action fired. When changing direction,
+    /// added every tick.
+    Sequence(Expr),
+}
+
+impl Default for Direction {
+    fn default() -> Self {
+        Direction::Aim(Expr::num(0.))
+    }
+}
+
+/// Pixels per tick
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Speed {
+    Absolute(Expr),
+    /// Added to the speed of whatever runs the action
+    Relative(Expr),
+    /// Added to the speed of the last bullet the action fired. When changing speed, added every
+    /// tick.
+    Sequence(Expr),
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::Absolute(Expr::num(1.))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BulletDef {
+    /// Used when the `Fire` does not say
+    pub direction: Option<Direction>,
+    pub speed: Option<Speed>,
+    /// Run by the bullet once it is fired
+    pub actions: Steps,
+    /// The pattern's when `None`
+    pub radius: Option<f32>,
+    pub color: Option<GameColor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BulletRef {
+    Inline(BulletDef),
+    /// From `BulletPattern::bullets`, `$1`, `$2`... are `params` in its actions
+    Ref {
+        label: String,
+        #[serde(default)]
+        params: Vec<Expr>,
+    },
+}
+
+impl Default for BulletRef {
+    fn default() -> Self {
+        BulletRef::Inline(BulletDef::default())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fire {
+    /// Aimed at the target when neither this nor the bullet says
+    pub direction: Option<Direction>,
+    /// 1 when neither this nor the bullet says
+    pub speed: Option<Speed>,
+    pub bullet: BulletRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Step {
+    Fire(Fire),
+    /// A fire from `BulletPattern::fires`
+    FireRef {
+        label: String,
+        #[serde(default)]
+        params: Vec<Expr>,
+    },
+    Repeat {
+        times: Expr,
+        actions: Steps,
+    },
+    /// Ticks
+    Wait(Expr),
+    /// Turn over `term` ticks
+    ChangeDirection {
+        direction: Direction,
+        term: Expr,
+    },
+    ChangeSpeed {
+        speed: Speed,
+        term: Expr,
+    },
+    /// Run these before the next step
+    Action(Steps),
+    /// An action from `BulletPattern::actions`, `$1`, `$2`... are `params` in there
+    Call {
+        label: String,
+        #[serde(default)]
+        params: Vec<Expr>,
+    },
+    /// Remove whatever runs the action
+    Vanish,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BulletPattern {
+    /// Actions whose label starts with `top` run side by side when the pattern starts
+    pub actions: BTreeMap<String, Steps>,
+    pub bullets: BTreeMap<String, BulletDef>,
+    pub fires: BTreeMap<String, Fire>,
+    pub radius: f32,
+    pub color: GameColor,
+}
+
+impl Default for BulletPattern {
+    /// A spiral of bullets that brake after a while
+    fn default() -> Self {
+        let spiral = vec![
+            Step::Fire(Fire {
+                direction: Some(Direction::Sequence(Expr::num(13.))),
+                speed: Some(Speed::Absolute(Expr::num(1.2))),
+                bullet: BulletRef::Ref {
+                    label: String::from("brake"),
+                    params: vec![expr("0.3 + $rank * 0.5")],
+                },
+            }),
+            Step::Wait(Expr::num(2.)),
+        ];
+        let brake = BulletDef {
+            actions: vec![
+                Step::Wait(Expr::num(20.)),
+                Step::ChangeSpeed {
+                    speed: Speed::Absolute(expr("$1")),
+                    term: Expr::num(30.),
+                },
+            ]
+            .into(),
+            ..Default::default()
+        };
+        Self {
+            actions: BTreeMap::from([(
+                String::from("top"),
+                vec![Step::Repeat {
+                    times: expr("60 + $rank * 60"),
+                    actions: spiral.into(),
+                }]
+                .into(),
+            )]),
+            bullets: BTreeMap::from([(String::from("brake"), brake)]),
+            fires: BTreeMap::new(),
+            radius: 1.5,
+            color: utils::PINK,
+        }
+    }
+}
+
+impl Versioned for BulletPattern {}
+
+impl BulletPattern {
+    /// BulletML when the file ends in `.xml`, YAML otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let pattern = if path.extension().is_some_and(|e| e == "xml") {
+            bulletml::import(path)?
+        } else {
+            let data =
+                fs::read(path).with_context(|| format!("Could not open {}", path.display()))?;
+            migrate::from_slice::<Self>(&data)
+                .with_context(|| format!("could not parse {}", path.display()))?
+        };
+        pattern
+            .validate()
+            .with_context(|| format!("bad pattern in {}", path.display()))?;
+        Ok(pattern)
+    }
+
+    /// `<name>.xml` if there is one, `<name>.yaml` otherwise
+    pub fn load_named(name: &str) -> Result<Handle<Self>> {
+        let xml = format!("{}.xml", name);
+        if assets::exists(&xml) {
+            assets::load(xml)
+        } else {
+            assets::load(format!("{}.yaml", name))
+        }
+    }
+
+    pub fn top_actions(&self) -> impl Iterator<Item = &Steps> {
+        self.actions
+            .iter()
+            .filter(|(label, _)| label.starts_with("top"))
+            .map(|(_, steps)| steps)
+    }
+
+    /// Every label used has to be defined, and there has to be a top action
+    pub fn validate(&self) -> Result<()> {
+        if self.top_actions().next().is_none() {
+            bail!("no action labelled top");
+        }
+        for steps in self.actions.values() {
+            self.check_steps(steps)?;
+        }
+        for bullet in self.bullets.values() {
+            self.check_steps(&bullet.actions)?;
+        }
+        for fire in self.fires.values() {
+            self.check_fire(fire)?;
+        }
+        Ok(())
+    }
+
+    fn check_steps(&self, steps: &[Step]) -> Result<()> {
+        for step in steps {
+            match step {
+                Step::Fire(fire) => self.check_fire(fire)?,
+                Step::FireRef { label, .. } if !self.fires.contains_key(label) => {
+                    bail!("no fire labelled {}", label)
+                }
+                Step::Call { label, .. } if !self.actions.contains_key(label) => {
+                    bail!("no action labelled {}", label)
+                }
+                Step::Repeat { actions, .. } | Step::Action(actions) => {
+                    self.check_steps(actions)?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check_fire(&self, fire: &Fire) -> Result<()> {
+        match &fire.bullet {
+            BulletRef::Inline(bullet) => self.check_steps(&bullet.actions),
+            BulletRef::Ref { label, .. } if !self.bullets.contains_key(label) => {
+                bail!("no bullet labelled {}", label)
+            }
+            BulletRef::Ref { .. } => Ok(()),
+        }
+    }
+}
+
+/// Spread over some ticks
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    per_tick: f32,
+    ticks: u32,
+}
+
+/// How much changes this tick, clears the change when it is done
+fn apply(change: &mut Option<Change>) -> f32 {
+    let Some(c) = change else {
+        return 0.;
+    };
+    let v = c.per_tick;
+    c.ticks = c.ticks.saturating_sub(1);
+    if c.ticks == 0 {
+        *change = None;
+    }
+    v
+}
+
+/// Degrees clockwise from straight up, from `from` towards `to`
+fn aim(from: Vec2, to: Vec2) -> f32 {
+    (to.x - from.x).atan2(from.y - to.y).to_degrees()
+}
+
+/// The shortest turn from `from` to `to`, in degrees
+fn turn(from: f32, to: f32) -> f32 {
+    (to - from + 180.).rem_euclid(360.) - 180.
+}
+
+/// What runs an action, the emitter or a bullet
+#[derive(Debug, Clone, Default)]
+struct Mover {
+    pos: Vec2,
+    direction: f32,
+    speed: f32,
+    turning: Option<Change>,
+    accelerating: Option<Change>,
+    vanished: bool,
+}
+
+impl Mover {
+    fn velocity(&self) -> Vec2 {
+        let angle = self.direction.to_radians();
+        vec2(angle.sin(), -angle.cos()) * self.speed
+    }
+
+    fn apply_changes(&mut self) {
+        self.direction += apply(&mut self.turning);
+        self.speed += apply(&mut self.accelerating);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    steps: Steps,
+    index: usize,
+    repeats_left: u32,
+    params: Rc<[f32]>,
+}
+
+/// Everything actions need during a tick
+struct Context<'a> {
+    pattern: &'a BulletPattern,
+    rng: &'a mut StdRng,
+    target: Vec2,
+    rank: f32,
+    fired: Vec<PatternBullet>,
+}
+
+impl Context<'_> {
+    fn eval(&mut self, expr: &Expr, params: &[f32]) -> f32 {
+        expr.eval(&mut Env {
+            rank: self.rank,
+            params,
+            rng: self.rng,
+        })
+    }
+
+    fn eval_all(&mut self, exprs: &[Expr], params: &[f32]) -> Rc<[f32]> {
+        exprs.iter().map(|e| self.eval(e, params)).collect()
+    }
+}
+
+/// Runs one action, a step at a time
+#[derive(Debug, Clone)]
+struct ActionRunner {
+    frames: Vec<Frame>,
+    wait: u32,
+    last_direction: f32,
+    last_speed: f32,
+}
+
+impl ActionRunner {
+    fn new(steps: Steps, params: Rc<[f32]>) -> Self {
+        Self {
+            frames: vec![Frame {
+                steps,
+                index: 0,
+                repeats_left: 0,
+                params,
+            }],
+            wait: 0,
+            last_direction: 0.,
+            last_speed: 0.,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    fn push(&mut self, steps: Steps, repeats_left: u32, params: Rc<[f32]>) {
+        if self.frames.len() < MAX_DEPTH {
+            self.frames.push(Frame {
+                steps,
+                index: 0,
+                repeats_left,
+                params,
+            });
+        }
+    }
+
+    /// Runs steps until a `Wait`, or the end of the action
+    fn run(&mut self, mover: &mut Mover, ctx: &mut Context) {
+        if self.wait > 0 {
+            self.wait -= 1;
+            if self.wait > 0 {
+                return;
+            }
+        }
+        let pattern = ctx.pattern;
+        for _ in 0..MAX_STEPS_PER_TICK {
+            let Some(frame) = self.frames.last_mut() else {
+                return;
+            };
+            if frame.index >= frame.steps.len() {
+                if frame.repeats_left > 0 {
+                    frame.repeats_left -= 1;
+                    frame.index = 0;
+                } else {
+                    self.frames.pop();
+                }
+                continue;
+            }
+            let steps = frame.steps.clone();
+            let params = frame.params.clone();
+            let step = &steps[frame.index];
+            frame.index += 1;
+            match step {
+                Step::Fire(fire) => self.fire(fire, &params, mover, ctx),
+                Step::FireRef {
+                    label,
+                    params: args,
+                } => {
+                    let args = ctx.eval_all(args, &params);
+                    if let Some(fire) = pattern.fires.get(label) {
+                        self.fire(fire, &args, mover, ctx);
+                    }
+                }
+                Step::Repeat { times, actions } => {
+                    let times = ctx.eval(times, &params).round();
+                    if times >= 1. {
+                        self.push(actions.clone(), times as u32 - 1, params);
+                    }
+                }
+                Step::Wait(ticks) => {
+                    let ticks = ctx.eval(ticks, &params).round();
+                    if ticks >= 1. {
+                        self.wait = ticks as u32;
+                        return;
+                    }
+                }
+                Step::ChangeDirection { direction, term } => {
+                    let term = ctx.eval(term, &params).round().max(1.);
+                    let per_tick = match direction {
+                        Direction::Aim(v) => {
+                            let to = aim(mover.pos, ctx.target) + ctx.eval(v, &params);
+                            turn(mover.direction, to) / term
+                        }
+                        Direction::Absolute(v) => {
+                            turn(mover.direction, ctx.eval(v, &params)) / term
+                        }
+                        Direction::Relative(v) => ctx.eval(v, &params) / term,
+                        Direction::Sequence(v) => ctx.eval(v, &params),
+                    };
+                    mover.turning = Some(Change {
+                        per_tick,
+                        ticks: term as u32,
+                    });
+                }
+                Step::ChangeSpeed { speed, term } => {
+                    let term = ctx.eval(term, &params).round().max(1.);
+                    let per_tick = match speed {
+                        Speed::Absolute(v) => (ctx.eval(v, &params) - mover.speed) / term,
+                        Speed::Relative(v) => ctx.eval(v, &params) / term,
+                        Speed::Sequence(v) => ctx.eval(v, &params),
+                    };
+                    mover.accelerating = Some(Change {
+                        per_tick,
+                        ticks: term as u32,
+                    });
+                }
+                Step::Action(actions) => self.push(actions.clone(), 0, params),
+                Step::Call {
+                    label,
+                    params: args,
+                } => {
+                    let args = ctx.eval_all(args, &params);
+                    if let Some(actions) = pattern.actions.get(label) {
+                        self.push(actions.clone(), 0, args);
+                    }
+                }
+                Step::Vanish => {
+                    mover.vanished = true;
+                    self.frames.clear();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn fire(&mut self, fire: &Fire, params: &Rc<[f32]>, mover: &Mover, ctx: &mut Context) {
+        let pattern = ctx.pattern;
+        let (def, bullet_params) = match &fire.bullet {
+            BulletRef::Inline(def) => (def, params.clone()),
+            BulletRef::Ref {
+                label,
+                params: args,
+            } => match pattern.bullets.get(label) {
+                Some(def) => (def, ctx.eval_all(args, params)),
+                None => return,
+            },
+        };
+        let direction = match fire.direction.as_ref().or(def.direction.as_ref()) {
+            None => aim(mover.pos, ctx.target),
+            Some(Direction::Aim(v)) => aim(mover.pos, ctx.target) + ctx.eval(v, params),
+            Some(Direction::Absolute(v)) => ctx.eval(v, params),
+            Some(Direction::Relative(v)) => mover.direction + ctx.eval(v, params),
+            Some(Direction::Sequence(v)) => self.last_direction + ctx.eval(v, params),
+        };
+        let speed = match fire.speed.as_ref().or(def.speed.as_ref()) {
+            None => 1.,
+            Some(Speed::Absolute(v)) => ctx.eval(v, params),
+            Some(Speed::Relative(v)) => mover.speed + ctx.eval(v, params),
+            Some(Speed::Sequence(v)) => self.last_speed + ctx.eval(v, params),
+        };
+        self.last_direction = direction;
+        self.last_speed = speed;
+
+        let mover = Mover {
+            pos: mover.pos,
+            direction,
+            speed,
+            ..Default::default()
+        };
+        let velocity = mover.velocity() * TICKS_PER_SECOND;
+        let bullet = Bullet::new(
+            CenterPt::new(mover.pos.x, mover.pos.y),
+            Velocity::new(velocity.x, velocity.y),
+            def.radius.unwrap_or(pattern.radius),
+            def.color.unwrap_or(pattern.color),
+        );
+        let runner = (!def.actions.is_empty())
+            .then(|| ActionRunner::new(def.actions.clone(), bullet_params));
+        ctx.fired.push(PatternBullet {
+            bullet,
+            mover,
+            runner,
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PatternBullet {
+    bullet: Bullet,
+    mover: Mover,
+    runner: Option<ActionRunner>,
+}
+
+/// A running pattern and the bullets it fired. Bullets only move on ticks, `Bullet::update` is
+/// never called on them.
+#[derive(Debug, Clone)]
+pub struct PatternRunner {
+    pattern: Rc<BulletPattern>,
+    emitter: Mover,
+    runners: Vec<ActionRunner>,
+    bullets: Vec<PatternBullet>,
+    rng: StdRng,
+    /// Where `Aim` points, straight down when `None`
+    target: Option<CenterPt>,
+    /// See `Rank::fraction`
+    rank: f32,
+    ticks: u32,
+    accumulator: f32,
+}
+
+impl PatternRunner {
+    pub fn new(pattern: Rc<BulletPattern>, center: CenterPt, seed: u64) -> Self {
+        let runners = pattern
+            .top_actions()
+            .map(|steps| ActionRunner::new(steps.clone(), Rc::from([])))
+            .collect();
+        Self {
+            pattern,
+            emitter: Mover {
+                pos: center.get().into(),
+                ..Default::default()
+            },
+            runners,
+            bullets: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+            target: None,
+            rank: 0.,
+            ticks: 0,
+            accumulator: 0.,
+        }
+    }
+
+    /// Move where new bullets are fired from
+    pub fn set_center(&mut self, center: CenterPt) {
+        self.emitter.pos = center.get().into();
+    }
+
+    pub fn aim_at(&mut self, target: Option<CenterPt>) {
+        self.target = target;
+    }
+
+    /// What `$rank` is, from 0 to 1 like `Rank::fraction`
+    pub fn set_rank(&mut self, rank: f32) {
+        self.rank = rank;
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    pub fn bullets(&self) -> impl Iterator<Item = &Bullet> {
+        self.bullets.iter().map(|b| &b.bullet)
+    }
+
+    /// Killed bullets are removed on the next tick
+    pub fn bullets_mut(&mut self) -> impl Iterator<Item = &mut Bullet> {
+        self.bullets.iter_mut().map(|b| &mut b.bullet)
+    }
+
+    /// The pattern keeps firing new ones
+    pub fn take_bullets(&mut self) -> Vec<Bullet> {
+        self.bullets
+            .drain(..)
+            .map(|b| b.bullet)
+            .filter(|b| b.is_alive())
+            .collect()
+    }
+
+    pub fn clear_bullets(&mut self) {
+        self.bullets.clear();
+    }
+
+    /// Drop bullets outside `bounds`, they never come back
+    pub fn retain_in(&mut self, bounds: Rect) {
+        self.bullets
+            .retain(|b| bounds.contains(b.bullet.center().get().into()));
+    }
+
+    /// Every action ended, and every bullet is gone
+    pub fn is_finished(&self) -> bool {
+        self.runners.is_empty() && self.bullets.is_empty()
+    }
+
+    /// Advance exactly one tick: run actions, then move bullets
+    pub fn tick(&mut self) {
+        let target = match self.target {
+            Some(t) => t.get().into(),
+            None => self.emitter.pos + vec2(0., 1.),
+        };
+        let mut ctx = Context {
+            pattern: &self.pattern,
+            rng: &mut self.rng,
+            target,
+            rank: self.rank,
+            fired: Vec::new(),
+        };
+        for runner in &mut self.runners {
+            runner.run(&mut self.emitter, &mut ctx);
+        }
+        self.emitter.apply_changes();
+        for b in &mut self.bullets {
+            if let Some(runner) = &mut b.runner {
+                runner.run(&mut b.mover, &mut ctx);
+            }
+            b.mover.apply_changes();
+            b.mover.pos += b.mover.velocity();
+            let velocity = b.mover.velocity() * TICKS_PER_SECOND;
+            b.bullet
+                .update_center(CenterPt::new(b.mover.pos.x, b.mover.pos.y));
+            b.bullet
+                .update_velocity(Velocity::new(velocity.x, velocity.y));
+        }
+        let fired = ctx.fired;
+        self.bullets
+            .retain(|b| !b.mover.vanished && b.bullet.is_alive());
+        self.bullets.extend(fired);
+        if self.emitter.vanished {
+            self.runners.clear();
+        }
+        self.runners.retain(|r| !r.is_done());
+        self.ticks += 1;
+    }
+}
+
+impl Updateable for PatternRunner {
+    fn update(&mut self, delta_time: f32) {
+        let tick = 1. / TICKS_PER_SECOND;
+        self.accumulator += delta_time;
+        for _ in 0..MAX_TICKS_PER_UPDATE {
+            if self.accumulator < tick {
+                return;
+            }
+            self.tick();
+            self.accumulator -= tick;
+        }
+        self.accumulator = self.accumulator.min(tick);
+    }
+}
+
+impl Drawable for PatternRunner {
+    fn draw(&self) {
+        self.bullets().for_each(|b| b.draw());
+    }
+
+    fn draw_gizmos(&self) {
+        self.bullets().for_each(|b| b.draw_gizmos());
+        let Vec2 { x, y } = self.emitter.pos;
+        draw_line(x - 2., y, x + 2., y, 0.5, ORANGE);
+        draw_line(x, y - 2., x, y + 2., 0.5, ORANGE);
+    }
+}
+
+/// A pattern in the editor, with the rank it is previewed at. Saved as just the pattern, serde
+/// cannot flatten the tagged enums in it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "BulletPattern", into = "BulletPattern")]
+pub struct PatternPreview {
+    pub pattern: BulletPattern,
+    pub rank: f32,
+    /// BulletML file to import
+    bulletml: String,
+}
+
+impl From<BulletPattern> for PatternPreview {
+    fn from(pattern: BulletPattern) -> Self {
+        Self {
+            pattern,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<PatternPreview> for BulletPattern {
+    fn from(value: PatternPreview) -> Self {
+        value.pattern
+    }
+}
+
+impl Versioned for PatternPreview {}
+
+impl PatternPreview {
+    /// Fired from above `center` at a target below it
+    fn places(center: CenterPt) -> (CenterPt, CenterPt) {
+        let (x, y) = center.get();
+        (CenterPt::new(x, y - 40.), CenterPt::new(x, y + 40.))
+    }
+}
+
+impl Buildable for PatternPreview {
+    type Byproduct = PatternRunner;
+
+    fn build(self, center: CenterPt) -> Option<Self::Byproduct> {
+        let (from, target) = Self::places(center);
+        let mut runner = PatternRunner::new(Rc::new(self.pattern), from, 0);
+        runner.aim_at(Some(target));
+        runner.set_rank(self.rank);
+        Some(runner)
+    }
+
+    fn max_loop_time(&self) -> f32 {
+        PREVIEW_TIME
+    }
+
+    fn draw_gizmos_at(&self, center: CenterPt) {
+        let (x, y) = Self::places(center).1.get();
+        draw_circle_lines(x, y, 3., 0.5, GREEN);
+    }
+
+    fn editor_ui(&mut self, ui: &mut Ui) {
+        ui.heading("Bullet Pattern");
+        egui::Grid::new("pattern").num_columns(2).show(ui, |ui| {
+            ui.label("Rank");
+            ui.add(egui::Slider::new(&mut self.rank, 0. ..=1.));
+            ui.end_row();
+
+            ui.label("Radius");
+            ui.add(
+                DragValue::new(&mut self.pattern.radius)
+                    .speed(0.1)
+                    .clamp_range(0.5..=8.),
+            );
+            ui.end_row();
+
+            ui.label("Color");
+            color_edit_palette_button(ui, &mut self.pattern.color);
+            ui.end_row();
+        });
+
+        ui.separator();
+        ui.label("BulletML");
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.bulletml);
+            if ui.button("Import").clicked() {
+                match assets::reload::<BulletPattern>(&self.bulletml) {
+                    Ok(pattern) => self.pattern = pattern.cloned(),
+                    Err(err) => error!("Can't import: {:?}", err),
+                }
+            }
+        });
+
+        ui.separator();
+        for (name, labels) in [
+            ("Actions", self.pattern.actions.keys().collect::<Vec<_>>()),
+            ("Bullets", self.pattern.bullets.keys().collect()),
+            ("Fires", self.pattern.fires.keys().collect()),
+        ] {
+            if !labels.is_empty() {
+                ui.collapsing(name, |ui| {
+                    labels.into_iter().for_each(|l| {
+                        ui.label(l);
+                    });
+                });
+            }
+        }
+    }
+
+    fn get_base_id() -> &'static str {
+        "pattern"
+    }
+
+    fn get_default_file_name() -> &'static str {
+        DEFAULT_PATTERN_FILE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(yaml: &str) -> Rc<BulletPattern> {
+        let pattern = serde_yaml::from_str::<BulletPattern>(yaml).unwrap();
+        pattern.validate().unwrap();
+        Rc::new(pattern)
+    }
+
+    fn centers(runner: &PatternRunner) -> Vec<(f32, f32)> {
+        runner.bullets().map(|b| b.center().get()).collect()
+    }
+
+    /// Starts at 0,0 and ticks `ticks` times
+    fn run(yaml: &str, rank: f32, ticks: u32) -> Vec<(f32, f32)> {
+        let mut runner = PatternRunner::new(pattern(yaml), CenterPt::new(0., 0.), 1);
+        runner.set_rank(rank);
+        for _ in 0..ticks {
+            runner.tick();
+        }
+        centers(&runner)
+    }
+
+    #[track_caller]
+    fn assert_at(got: &[(f32, f32)], expected: &[(f32, f32)]) {
+        assert_eq!(got.len(), expected.len(), "{:?} != {:?}", got, expected);
+        for (g, e) in got.iter().zip(expected) {
+            let close = (g.0 - e.0).abs() < 1e-3 && (g.1 - e.1).abs() < 1e-3;
+            assert!(close, "{:?} != {:?}", got, expected);
+        }
+    }
+
+    #[test]
+    fn fire_moves_from_the_next_tick() {
+        let yaml = "
+actions:
+  top:
+  - !Fire
+    direction: !Absolute 180
+    speed: !Absolute 2
+";
+        assert_at(&run(yaml, 0., 1), &[(0., 0.)]);
+        assert_at(&run(yaml, 0., 3), &[(0., 4.)]);
+    }
+
+    #[test]
+    fn fire_aims_at_the_target() {
+        let yaml = "
+actions:
+  top:
+  - !Fire {}
+";
+        let mut runner = PatternRunner::new(pattern(yaml), CenterPt::new(0., 0.), 1);
+        runner.aim_at(Some(CenterPt::new(10., 0.)));
+        runner.tick();
+        runner.tick();
+        assert_at(&centers(&runner), &[(1., 0.)]);
+    }
+
+    #[test]
+    fn repeat_and_wait() {
+        let yaml = "
+actions:
+  top:
+  - !Repeat
+    times: 3
+    actions:
+    - !Fire
+      direction: !Absolute 90
+      speed: !Absolute 1
+    - !Wait 2
+";
+        assert_at(&run(yaml, 0., 2), &[(1., 0.)]);
+        assert_at(&run(yaml, 0., 5), &[(4., 0.), (2., 0.), (0., 0.)]);
+        assert_at(&run(yaml, 0., 8), &[(7., 0.), (5., 0.), (3., 0.)]);
+    }
+
+    #[test]
+    fn change_direction() {
+        let yaml = "
+actions:
+  top:
+  - !Fire
+    direction: !Absolute 180
+    speed: !Absolute 1
+    bullet: !Inline
+      actions:
+      - !ChangeDirection
+        direction: !Absolute 90
+        term: 2
+";
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert_at(&run(yaml, 0., 2), &[(half, half)]);
+        assert_at(&run(yaml, 0., 4), &[(2. + half, half)]);
+    }
+
+    #[test]
+    fn change_speed() {
+        let yaml = "
+actions:
+  top:
+  - !Fire
+    direction: !Absolute 90
+    speed: !Absolute 1
+    bullet: !Inline
+      actions:
+      - !ChangeSpeed
+        speed: !Absolute 3
+        term: 2
+";
+        assert_at(&run(yaml, 0., 2), &[(2., 0.)]);
+        assert_at(&run(yaml, 0., 4), &[(8., 0.)]);
+    }
+
+    #[test]
+    fn sequence_adds_to_the_last_bullet() {
+        let yaml = "
+actions:
+  top:
+  - !Repeat
+    times: 3
+    actions:
+    - !Fire
+      direction: !Sequence 90
+      speed: !Sequence 1
+";
+        assert_at(&run(yaml, 0., 2), &[(1., 0.), (0., 2.), (-3., 0.)]);
+    }
+
+    #[test]
+    fn call_with_params() {
+        let yaml = "
+actions:
+  top:
+  - !Call
+    label: shot
+    params: [2, 1 + 2]
+  shot:
+  - !Fire
+    direction: !Absolute $1 * 45
+    speed: !Absolute $2
+";
+        assert_at(&run(yaml, 0., 2), &[(3., 0.)]);
+    }
+
+    #[test]
+    fn bullet_ref_params() {
+        let yaml = "
+actions:
+  top:
+  - !Fire
+    direction: !Absolute 90
+    speed: !Absolute 1
+    bullet: !Ref
+      label: stop
+      params: [0]
+bullets:
+  stop:
+    actions:
+    - !ChangeSpeed
+      speed: !Absolute $1
+      term: 1
+";
+        assert_at(&run(yaml, 0., 5), &[(0., 0.)]);
+    }
+
+    #[test]
+    fn rank_scales_counts_and_speeds() {
+        let yaml = "
+actions:
+  top:
+  - !Repeat
+    times: 1 + $rank * 2
+    actions:
+    - !Fire
+      direction: !Absolute 90
+      speed: !Absolute 1 + $rank
+";
+        assert_at(&run(yaml, 0., 2), &[(1., 0.)]);
+        assert_at(&run(yaml, 1., 2), &[(2., 0.), (2., 0.), (2., 0.)]);
+    }
+
+    #[test]
+    fn vanish_removes_the_bullet() {
+        let yaml = "
+actions:
+  top:
+  - !Fire
+    bullet: !Inline
+      actions:
+      - !Wait 2
+      - !Vanish
+";
+        assert_eq!(run(yaml, 0., 2).len(), 1);
+        assert_eq!(run(yaml, 0., 4).len(), 0);
+    }
+
+    #[test]
+    fn same_seed_same_bullets() {
+        let yaml = "
+actions:
+  top:
+  - !Repeat
+    times: 10
+    actions:
+    - !Fire
+      direction: !Absolute $rand * 360
+      speed: !Absolute 1 + $rand
+";
+        let a = run(yaml, 0., 10);
+        assert_eq!(a, run(yaml, 0., 10));
+        assert_eq!(a.len(), 10);
+    }
+
+    #[test]
+    fn preview_saves_as_the_pattern() {
+        let preview = PatternPreview::default();
+        let yaml = serde_yaml::to_string(&preview).unwrap();
+        let loaded = serde_yaml::from_str::<PatternPreview>(&yaml).unwrap();
+        assert_eq!(loaded.pattern, preview.pattern);
+        assert_eq!(
+            serde_yaml::from_str::<BulletPattern>(&yaml).unwrap(),
+            preview.pattern
+        );
+    }
+
+    #[test]
+    fn validate_finds_missing_labels() {
+        let yaml = "
+actions:
+  top:
+  - !Call
+    label: nowhere
+";
+        let pattern = serde_yaml::from_str::<BulletPattern>(yaml).unwrap();
+        assert!(pattern.validate().is_err());
+        let pattern = serde_yaml::from_str::<BulletPattern>("actions: {}").unwrap();
+        assert!(pattern.validate().is_err());
+    }
+}
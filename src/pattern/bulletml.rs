@@ -0,0 +1,276 @@
+//! Import BulletML (`.xml`) documents. Labels are shared by the whole document, labelled actions,
+//! bullets and fires can be referenced wherever they are defined.
+//!
+//! `accel` is not supported, and `horizontal` documents are read as if they were `vertical`.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use roxmltree::{Document, Node};
+
+use super::{BulletDef, BulletPattern, BulletRef, Direction, Expr, Fire, Speed, Step, Steps};
+
+pub(super) fn import(path: &Path) -> Result<BulletPattern> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Could not open {}", path.display()))?;
+    parse(&text).with_context(|| format!("could not parse {}", path.display()))
+}
+
+pub(super) fn parse(text: &str) -> Result<BulletPattern> {
+    let doc = Document::parse(text)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "bulletml" {
+        bail!("expected <bulletml>, found <{}>", root.tag_name().name());
+    }
+    let mut pattern = BulletPattern {
+        actions: BTreeMap::new(),
+        bullets: BTreeMap::new(),
+        fires: BTreeMap::new(),
+        ..Default::default()
+    };
+    for node in elements(root) {
+        match node.tag_name().name() {
+            "action" => {
+                action(&mut pattern, node)?;
+            }
+            "bullet" => {
+                bullet(&mut pattern, node)?;
+            }
+            "fire" => {
+                fire(&mut pattern, node)?;
+            }
+            other => bail!("unexpected <{}> in <bulletml>", other),
+        }
+    }
+    Ok(pattern)
+}
+
+fn elements<'a, 'input>(node: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(|n| n.is_element())
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    elements(node).find(|n| n.tag_name().name() == name)
+}
+
+fn label(node: Node) -> Result<String> {
+    match node.attribute("label") {
+        Some(label) => Ok(String::from(label)),
+        None => bail!("<{}> needs a label", node.tag_name().name()),
+    }
+}
+
+fn expr(node: Node) -> Result<Expr> {
+    node.text().unwrap_or_default().parse()
+}
+
+/// The `<param>`s of a reference
+fn params(node: Node) -> Result<Vec<Expr>> {
+    elements(node)
+        .filter(|n| n.tag_name().name() == "param")
+        .map(expr)
+        .collect()
+}
+
+fn direction(node: Node) -> Result<Direction> {
+    let value = expr(node)?;
+    Ok(match node.attribute("type").unwrap_or("aim") {
+        "aim" => Direction::Aim(value),
+        "absolute" => Direction::Absolute(value),
+        "relative" => Direction::Relative(value),
+        "sequence" => Direction::Sequence(value),
+        other => bail!("unknown direction type {}", other),
+    })
+}
+
+fn speed(node: Node) -> Result<Speed> {
+    let value = expr(node)?;
+    Ok(match node.attribute("type").unwrap_or("absolute") {
+        "absolute" => Speed::Absolute(value),
+        "relative" => Speed::Relative(value),
+        "sequence" => Speed::Sequence(value),
+        other => bail!("unknown speed type {}", other),
+    })
+}
+
+fn required<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Result<Node<'a, 'input>> {
+    match child(node, name) {
+        Some(n) => Ok(n),
+        None => bail!("<{}> needs a <{}>", node.tag_name().name(), name),
+    }
+}
+
+/// An `<action>` or `<actionRef>` as steps
+fn action_or_ref(pattern: &mut BulletPattern, node: Node) -> Result<Steps> {
+    match node.tag_name().name() {
+        "actionRef" => Ok(vec![Step::Call {
+            label: label(node)?,
+            params: params(node)?,
+        }]
+        .into()),
+        _ => action(pattern, node),
+    }
+}
+
+fn action(pattern: &mut BulletPattern, node: Node) -> Result<Steps> {
+    let steps = elements(node)
+        .map(|n| step(pattern, n))
+        .collect::<Result<Vec<_>>>()?
+        .into();
+    if let Some(label) = node.attribute("label") {
+        pattern
+            .actions
+            .insert(String::from(label), Steps::clone(&steps));
+    }
+    Ok(steps)
+}
+
+fn step(pattern: &mut BulletPattern, node: Node) -> Result<Step> {
+    Ok(match node.tag_name().name() {
+        "fire" => Step::Fire(fire(pattern, node)?),
+        "fireRef" => Step::FireRef {
+            label: label(node)?,
+            params: params(node)?,
+        },
+        "repeat" => {
+            let actions = elements(node)
+                .find(|n| matches!(n.tag_name().name(), "action" | "actionRef"))
+                .context("<repeat> needs an <action> or <actionRef>")?;
+            Step::Repeat {
+                times: expr(required(node, "times")?)?,
+                actions: action_or_ref(pattern, actions)?,
+            }
+        }
+        "wait" => Step::Wait(expr(node)?),
+        "changeDirection" => Step::ChangeDirection {
+            direction: direction(required(node, "direction")?)?,
+            term: expr(required(node, "term")?)?,
+        },
+        "changeSpeed" => Step::ChangeSpeed {
+            speed: speed(required(node, "speed")?)?,
+            term: expr(required(node, "term")?)?,
+        },
+        "vanish" => Step::Vanish,
+        "action" => Step::Action(action(pattern, node)?),
+        "actionRef" => Step::Call {
+            label: label(node)?,
+            params: params(node)?,
+        },
+        other => bail!("<{}> is not supported", other),
+    })
+}
+
+fn bullet(pattern: &mut BulletPattern, node: Node) -> Result<BulletDef> {
+    let mut actions = Vec::new();
+    for n in elements(node).filter(|n| matches!(n.tag_name().name(), "action" | "actionRef")) {
+        // Run one after the other, BulletML runs them side by side
+        actions.push(Step::Action(action_or_ref(pattern, n)?));
+    }
+    let def = BulletDef {
+        direction: child(node, "direction").map(direction).transpose()?,
+        speed: child(node, "speed").map(speed).transpose()?,
+        actions: actions.into(),
+        ..Default::default()
+    };
+    if let Some(label) = node.attribute("label") {
+        pattern.bullets.insert(String::from(label), def.clone());
+    }
+    Ok(def)
+}
+
+fn fire(pattern: &mut BulletPattern, node: Node) -> Result<Fire> {
+    let bullet = if let Some(n) = child(node, "bulletRef") {
+        BulletRef::Ref {
+            label: label(n)?,
+            params: params(n)?,
+        }
+    } else {
+        BulletRef::Inline(bullet(pattern, required(node, "bullet")?)?)
+    };
+    let fire = Fire {
+        direction: child(node, "direction").map(direction).transpose()?,
+        speed: child(node, "speed").map(speed).transpose()?,
+        bullet,
+    };
+    if let Some(label) = node.attribute("label") {
+        pattern.fires.insert(String::from(label), fire.clone());
+    }
+    Ok(fire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPREAD: &str = r#"<?xml version="1.0" ?>
+<bulletml type="vertical" xmlns="http://www.asahi-net.or.jp/~cs8k-cyu/bulletml">
+  <action label="top">
+    <repeat>
+      <times>3 + $rank</times>
+      <action>
+        <fireRef label="shot">
+          <param>2</param>
+        </fireRef>
+        <wait>5</wait>
+      </action>
+    </repeat>
+  </action>
+  <fire label="shot">
+    <direction type="sequence">15</direction>
+    <speed>$1</speed>
+    <bulletRef label="brake" />
+  </fire>
+  <bullet label="brake">
+    <action>
+      <changeSpeed>
+        <speed type="relative">-1</speed>
+        <term>10</term>
+      </changeSpeed>
+    </action>
+  </bullet>
+</bulletml>
+"#;
+
+    #[test]
+    fn parse_labels_and_steps() {
+        let pattern = parse(SPREAD).unwrap();
+        pattern.validate().unwrap();
+        assert_eq!(pattern.actions.keys().collect::<Vec<_>>(), ["top"]);
+        assert!(pattern.fires.contains_key("shot"));
+        assert!(pattern.bullets.contains_key("brake"));
+
+        let Step::Repeat { times, actions } = &pattern.actions["top"][0] else {
+            panic!("expected a repeat, got {:?}", pattern.actions["top"]);
+        };
+        assert_eq!(times, &"3 + $rank".parse::<Expr>().unwrap());
+        assert!(
+            matches!(&actions[0], Step::FireRef { label, params } if label == "shot" && params.len() == 1)
+        );
+        assert!(matches!(&actions[1], Step::Wait(_)));
+
+        let fire = &pattern.fires["shot"];
+        assert!(matches!(fire.direction, Some(Direction::Sequence(_))));
+        assert!(matches!(fire.speed, Some(Speed::Absolute(_))));
+        assert!(matches!(&fire.bullet, BulletRef::Ref { label, .. } if label == "brake"));
+
+        // One action per <action> in a bullet
+        let brake = &pattern.bullets["brake"];
+        let Step::Action(steps) = &brake.actions[0] else {
+            panic!("expected an action, got {:?}", brake.actions);
+        };
+        assert!(matches!(
+            &steps[0],
+            Step::ChangeSpeed {
+                speed: Speed::Relative(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("<pattern />").is_err());
+        assert!(parse("<bulletml><action label=\"top\"><repeat /></action></bulletml>").is_err());
+        assert!(parse("<bulletml><action label=\"top\"><accel /></action></bulletml>").is_err());
+        assert!(parse("<bulletml><fireRef /></bulletml>").is_err());
+    }
+}
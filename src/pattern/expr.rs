@@ -0,0 +1,297 @@
+//! Numbers in patterns can be expressions like `180 + $rand * 40` or `4 + $rank * 8`, as in
+//! BulletML. `$rank` goes from 0 to 1, `$rand` is a new number from 0 to 1 every time, and `$1`,
+//! `$2`... are the parameters the action was called with.
+use std::fmt;
+
+use ::rand::{rngs::StdRng, Rng};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Num(f32),
+    Rank,
+    Rand,
+    /// From 1, like `$1`
+    Param(usize),
+    Neg(Box<Node>),
+    Bin(char, Box<Node>, Box<Node>),
+}
+
+/// What `$rank`, `$rand` and `$1` are while evaluating
+pub struct Env<'a> {
+    pub rank: f32,
+    pub params: &'a [f32],
+    pub rng: &'a mut StdRng,
+}
+
+/// Stored as the text it was parsed from, or as a plain number when it is one
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ExprSource", into = "ExprSource")]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ExprSource {
+    Number(f32),
+    Text(String),
+}
+
+impl TryFrom<ExprSource> for Expr {
+    type Error = anyhow::Error;
+
+    fn try_from(value: ExprSource) -> Result<Self> {
+        match value {
+            ExprSource::Number(v) => Ok(Expr::num(v)),
+            ExprSource::Text(s) => s.parse(),
+        }
+    }
+}
+
+impl From<Expr> for ExprSource {
+    fn from(value: Expr) -> Self {
+        match value.node {
+            Node::Num(v) => ExprSource::Number(v),
+            _ => ExprSource::Text(value.source),
+        }
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Default for Expr {
+    fn default() -> Self {
+        Expr::num(0.)
+    }
+}
+
+impl std::str::FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let tokens = tokenize(s).with_context(|| format!("bad expression {:?}", s))?;
+        let mut parser = Parser { tokens, at: 0 };
+        let node = parser
+            .expr()
+            .with_context(|| format!("bad expression {:?}", s))?;
+        if parser.at < parser.tokens.len() {
+            bail!(
+                "bad expression {:?}, unexpected {:?}",
+                s,
+                parser.tokens[parser.at]
+            );
+        }
+        Ok(Self {
+            source: String::from(s.trim()),
+            node,
+        })
+    }
+}
+
+impl Expr {
+    pub fn num(v: f32) -> Self {
+        Self {
+            source: v.to_string(),
+            node: Node::Num(v),
+        }
+    }
+
+    pub fn eval(&self, env: &mut Env) -> f32 {
+        eval(&self.node, env)
+    }
+}
+
+fn eval(node: &Node, env: &mut Env) -> f32 {
+    match node {
+        Node::Num(v) => *v,
+        Node::Rank => env.rank,
+        Node::Rand => env.rng.gen_range(0_f32..1.),
+        Node::Param(i) => env.params.get(i - 1).copied().unwrap_or(0.),
+        Node::Neg(n) => -eval(n, env),
+        Node::Bin(op, a, b) => {
+            let (a, b) = (eval(a, env), eval(b, env));
+            match op {
+                '+' => a + b,
+                '-' => a - b,
+                '*' => a * b,
+                '/' if b != 0. => a / b,
+                '%' if b != 0. => a % b,
+                _ => 0.,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f32),
+    Var(String),
+    Op(char),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                number.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Num(number.parse()?));
+        } else if c == '$' {
+            chars.next();
+            let mut name = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Var(name));
+        } else if "+-*/%()".contains(c) {
+            tokens.push(Token::Op(c));
+            chars.next();
+        } else {
+            bail!("unexpected {:?}", c);
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent, `*` `/` `%` before `+` `-`
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn peek_op(&self, ops: &str) -> Option<char> {
+        match self.tokens.get(self.at) {
+            Some(Token::Op(c)) if ops.contains(*c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> Result<Node> {
+        let mut node = self.term()?;
+        while let Some(op) = self.peek_op("+-") {
+            self.at += 1;
+            node = Node::Bin(op, Box::new(node), Box::new(self.term()?));
+        }
+        Ok(node)
+    }
+
+    fn term(&mut self) -> Result<Node> {
+        let mut node = self.factor()?;
+        while let Some(op) = self.peek_op("*/%") {
+            self.at += 1;
+            node = Node::Bin(op, Box::new(node), Box::new(self.factor()?));
+        }
+        Ok(node)
+    }
+
+    fn factor(&mut self) -> Result<Node> {
+        match self.next() {
+            Some(Token::Num(v)) => Ok(Node::Num(v)),
+            Some(Token::Op('-')) => Ok(Node::Neg(Box::new(self.factor()?))),
+            Some(Token::Op('(')) => {
+                let node = self.expr()?;
+                match self.next() {
+                    Some(Token::Op(')')) => Ok(node),
+                    _ => bail!("missing )"),
+                }
+            }
+            Some(Token::Var(name)) => match name.as_str() {
+                "rank" => Ok(Node::Rank),
+                "rand" => Ok(Node::Rand),
+                n => match n.parse::<usize>() {
+                    Ok(i) if i > 0 => Ok(Node::Param(i)),
+                    _ => bail!("unknown variable ${}", n),
+                },
+            },
+            Some(token) => bail!("unexpected {:?}", token),
+            None => bail!("unexpected end"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::SeedableRng;
+
+    use super::*;
+
+    fn eval_with(source: &str, rank: f32, params: &[f32]) -> f32 {
+        let mut rng = StdRng::seed_from_u64(1);
+        let expr = source.parse::<Expr>().unwrap();
+        expr.eval(&mut Env {
+            rank,
+            params,
+            rng: &mut rng,
+        })
+    }
+
+    fn eval(source: &str) -> f32 {
+        eval_with(source, 0., &[])
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7.);
+        assert_eq!(eval("(1 + 2) * 3"), 9.);
+        assert_eq!(eval("10 - 4 - 3"), 3.);
+        assert_eq!(eval("12 / 2 / 3"), 2.);
+        assert_eq!(eval("-2 * 3"), -6.);
+        assert_eq!(eval("2 - -1"), 3.);
+        assert_eq!(eval("7 % 4 + 1"), 4.);
+        assert_eq!(eval(".5 + 1.25"), 1.75);
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(eval_with("$rank * 10", 0.5, &[]), 5.);
+        assert_eq!(eval_with("$1 * 2 + $2", 0., &[3., 1.]), 7.);
+        // Missing parameters are 0
+        assert_eq!(eval_with("$3 + 1", 0., &[3., 1.]), 1.);
+        let r = eval("$rand");
+        assert!((0. ..1.).contains(&r));
+    }
+
+    #[test]
+    fn divide_by_zero_is_zero() {
+        assert_eq!(eval("1 / 0"), 0.);
+        assert_eq!(eval("5 % 0"), 0.);
+        assert_eq!(eval("1 / (1 - 1) + 2"), 2.);
+    }
+
+    #[test]
+    fn bad_expressions() {
+        for source in ["", "1 +", "(1", "1 2", "$foo", "$0", "2 # 3", "1..2"] {
+            assert!(source.parse::<Expr>().is_err(), "{:?} parsed", source);
+        }
+    }
+
+    #[test]
+    fn serialized_as_number_or_text() {
+        assert_eq!(serde_yaml::to_string(&Expr::num(2.)).unwrap(), "2.0\n");
+        let expr = serde_yaml::from_str::<Expr>("1 + $rank").unwrap();
+        assert_eq!(serde_yaml::to_string(&expr).unwrap(), "1 + $rank\n");
+        let expr = serde_yaml::from_str::<Expr>("3").unwrap();
+        assert_eq!(expr, Expr::num(3.));
+    }
+}
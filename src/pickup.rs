@@ -0,0 +1,337 @@
+//! Pickups dropped by enemies: power for the weapon, points, bombs and lives.
+use std::collections::BTreeMap;
+
+use macroquad::{prelude::*, rand::gen_range};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alive::IsAlive, drawable::Drawable, migrate::Versioned, minmax::MinMax, timers::AliveTimer,
+    updateable::Updateable, utils::GameColor, CenterPt, Velocity,
+};
+
+pub const DEFAULT_PICKUPS_FILE: &str = "pickups.yaml";
+
+/// What happens to the player when they collect the pickup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PickupEffect {
+    Power(u32),
+    Score(u32),
+    Bomb,
+    Life,
+}
+
+impl Default for PickupEffect {
+    fn default() -> Self {
+        PickupEffect::Power(1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PickupDef {
+    pub effect: PickupEffect,
+    pub color: GameColor,
+    pub radius: f32,
+    /// Pickups closer than this to the player fly towards them
+    pub magnet_radius: f32,
+    /// Pixels per second
+    pub magnet_speed: f32,
+    /// Seconds before the pickup disappears
+    pub lifetime: f32,
+    /// The pickup blinks for this many seconds before it disappears
+    pub blink_time: f32,
+    /// Pixels per second, pickups drift down the screen
+    pub fall_speed: f32,
+    /// Sound event when collected, see `SoundBank`
+    pub sound: Option<String>,
+}
+
+impl Default for PickupDef {
+    fn default() -> Self {
+        Self {
+            effect: PickupEffect::default(),
+            color: crate::utils::ORANGE,
+            radius: 2.,
+            magnet_radius: 24.,
+            magnet_speed: 90.,
+            lifetime: 8.,
+            blink_time: 2.,
+            fall_speed: 15.,
+            sound: Some(String::from("pickup")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DropEntry {
+    /// Name in `PickupBank::pickups`, `None` drops nothing
+    pub pickup: Option<String>,
+    pub weight: f32,
+    /// How many drop when this entry is picked
+    pub count: MinMax<u8>,
+}
+
+impl Default for DropEntry {
+    fn default() -> Self {
+        Self {
+            pickup: None,
+            weight: 1.,
+            count: MinMax::new(1, 1),
+        }
+    }
+}
+
+/// One entry is picked at random, entries with more weight are picked more often
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DropTable {
+    pub entries: Vec<DropEntry>,
+    /// Pickups are scattered this far from where the enemy died
+    pub scatter: MinMax<f32>,
+}
+
+impl DropTable {
+    pub fn roll(&self) -> Option<&DropEntry> {
+        let total: f32 = self.entries.iter().map(|e| e.weight.max(0.)).sum();
+        if total <= 0. {
+            return None;
+        }
+        let mut pick = gen_range(0., total);
+        for entry in &self.entries {
+            pick -= entry.weight.max(0.);
+            if pick < 0. {
+                return Some(entry);
+            }
+        }
+        self.entries.last()
+    }
+}
+
+/// Every pickup and drop table, by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PickupBank {
+    pub pickups: BTreeMap<String, PickupDef>,
+    pub drop_tables: BTreeMap<String, DropTable>,
+}
+
+impl Versioned for PickupBank {}
+
+impl PickupBank {
+    /// Roll `table` and create what it drops around `center`
+    pub fn drop(&self, table: &str, center: CenterPt) -> Vec<Pickup> {
+        let Some(table) = self.drop_tables.get(table) else {
+            warn!("No drop table named {}", table);
+            return Vec::new();
+        };
+        let Some(entry) = table.roll() else {
+            return Vec::new();
+        };
+        let Some(name) = &entry.pickup else {
+            return Vec::new();
+        };
+        let Some(def) = self.pickups.get(name) else {
+            warn!("No pickup named {}", name);
+            return Vec::new();
+        };
+        let (x, y) = center.get();
+        // Both ends of the count can drop, a max below the min is the min
+        let (min, max) = (entry.count.min as u32, entry.count.max as u32);
+        let count = gen_range(min, max.max(min) + 1);
+        (0..count)
+            .map(|_| {
+                let angle = gen_range(0., std::f32::consts::TAU);
+                let dist = table.scatter.rand();
+                let at = CenterPt::new(x + angle.cos() * dist, y + angle.sin() * dist);
+                Pickup::new(name, def.clone(), at)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pickup {
+    pub name: String,
+    pub def: PickupDef,
+    center: CenterPt,
+    ttl: AliveTimer,
+    collected: bool,
+}
+
+impl Pickup {
+    pub fn new(name: &str, def: PickupDef, center: CenterPt) -> Self {
+        Self {
+            name: String::from(name),
+            ttl: AliveTimer::new(def.lifetime),
+            def,
+            center,
+            collected: false,
+        }
+    }
+
+    pub fn is_blinking(&self) -> bool {
+        self.ttl.ttl() < self.def.blink_time
+    }
+
+    /// Fly towards `target` when it is within the magnet radius. Returns true when the pickup
+    /// touches a `target` with `radius`, the pickup is then dead.
+    pub fn attract(&mut self, target: CenterPt, radius: f32, delta_time: f32) -> bool {
+        if self.collected {
+            return false;
+        }
+        let (x, y) = self.center.get();
+        let (tx, ty) = target.get();
+        let to = vec2(tx - x, ty - y);
+        let dist = to.length();
+        if dist < self.def.radius + radius {
+            self.collected = true;
+            return true;
+        }
+        if dist < self.def.magnet_radius {
+            let step = (self.def.magnet_speed * delta_time).min(dist);
+            let v = to / dist * step;
+            self.center = CenterPt::new(x + v.x, y + v.y);
+        }
+        false
+    }
+}
+
+crate::impl_pts!(center Pickup);
+
+impl IsAlive for Pickup {
+    fn is_alive(&self) -> bool {
+        !self.collected && self.ttl.is_alive()
+    }
+}
+
+impl Updateable for Pickup {
+    fn update(&mut self, delta_time: f32) {
+        self.ttl.update(delta_time);
+        self.center = self.center + Velocity::new(0., self.def.fall_speed) * delta_time;
+    }
+}
+
+impl Drawable for Pickup {
+    fn draw(&self) {
+        // Off for half of every tenth of a second
+        if self.is_blinking() && (self.ttl.ttl() * 10.).fract() < 0.5 {
+            return;
+        }
+        let (x, y) = self.center.get();
+        draw_circle(x, y, self.def.radius, self.def.color.into());
+    }
+
+    fn draw_gizmos(&self) {
+        let (x, y) = self.center.get();
+        draw_circle_lines(x, y, self.def.magnet_radius, 0.5, GREEN);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawable::HasCenter;
+
+    fn entry(pickup: Option<&str>, weight: f32, min: u8, max: u8) -> DropEntry {
+        DropEntry {
+            pickup: pickup.map(String::from),
+            weight,
+            count: MinMax::new(min, max),
+        }
+    }
+
+    fn bank(entries: Vec<DropEntry>) -> PickupBank {
+        let mut bank = PickupBank::default();
+        bank.pickups
+            .insert(String::from("power"), PickupDef::default());
+        let table = DropTable {
+            entries,
+            scatter: MinMax::new(0., 4.),
+        };
+        bank.drop_tables.insert(String::from("enemy"), table);
+        bank
+    }
+
+    #[test]
+    fn entries_without_weight_are_never_rolled() {
+        let table = DropTable {
+            entries: vec![
+                entry(Some("a"), 0., 1, 1),
+                entry(Some("b"), 1., 1, 1),
+                entry(Some("c"), -3., 1, 1),
+            ],
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            assert_eq!(table.roll().unwrap().pickup.as_deref(), Some("b"));
+        }
+        let table = DropTable {
+            entries: vec![entry(Some("a"), 0., 1, 1), entry(Some("c"), -1., 1, 1)],
+            ..Default::default()
+        };
+        assert!(table.roll().is_none());
+        assert!(DropTable::default().roll().is_none());
+    }
+
+    #[test]
+    fn counts_include_both_ends() {
+        let bank = bank(vec![entry(Some("power"), 1., 1, 3)]);
+        let mut seen = [false; 4];
+        for _ in 0..200 {
+            let dropped = bank.drop("enemy", CenterPt::new(10., 10.));
+            assert!((1..=3).contains(&dropped.len()));
+            seen[dropped.len()] = true;
+            for pickup in dropped {
+                let (x, y) = pickup.center().get();
+                assert!(vec2(x - 10., y - 10.).length() <= 4.001);
+            }
+        }
+        assert_eq!(seen, [false, true, true, true]);
+
+        let bank = self::bank(vec![entry(Some("power"), 1., 2, 2)]);
+        assert_eq!(bank.drop("enemy", CenterPt::new(0., 0.)).len(), 2);
+        // A max below the min drops the min
+        let bank = self::bank(vec![entry(Some("power"), 1., 2, 0)]);
+        assert_eq!(bank.drop("enemy", CenterPt::new(0., 0.)).len(), 2);
+    }
+
+    #[test]
+    fn nothing_drops_from_empty_or_unknown_entries() {
+        let center = CenterPt::new(0., 0.);
+        assert!(bank(vec![entry(None, 1., 1, 1)])
+            .drop("enemy", center)
+            .is_empty());
+        assert!(bank(vec![entry(Some("missing"), 1., 1, 1)])
+            .drop("enemy", center)
+            .is_empty());
+        assert!(bank(vec![entry(Some("power"), 1., 1, 1)])
+            .drop("boss", center)
+            .is_empty());
+    }
+
+    #[test]
+    fn attract_pulls_in_and_collects_once() {
+        let def = PickupDef {
+            radius: 2.,
+            magnet_radius: 20.,
+            magnet_speed: 10.,
+            ..Default::default()
+        };
+        let player = CenterPt::new(0., 0.);
+        // Out of reach, stays put
+        let mut pickup = Pickup::new("power", def.clone(), CenterPt::new(30., 0.));
+        assert!(!pickup.attract(player, 1., 1.));
+        assert_eq!(pickup.center().get(), (30., 0.));
+
+        let mut pickup = Pickup::new("power", def, CenterPt::new(15., 0.));
+        assert!(!pickup.attract(player, 1., 1.));
+        assert_eq!(pickup.center().get(), (5., 0.));
+        // Never overshoots
+        assert!(!pickup.attract(player, 1., 10.));
+        assert_eq!(pickup.center().get(), (0., 0.));
+        assert!(pickup.attract(player, 1., 1.));
+        assert!(!pickup.is_alive());
+        assert!(!pickup.attract(player, 1., 1.));
+    }
+}
@@ -0,0 +1,400 @@
+use macroquad::prelude::*;
+
+use crate::{
+    alive::IsAlive,
+    bullet::Bullet,
+    drawable::{Drawable, HasCenter},
+    timers::AliveTimer,
+    updateable::Updateable,
+    utils::{self, GameColor},
+    weapon::Weapon,
+    CenterPt,
+};
+
+/// Seconds between exploding and flying back in
+const RESPAWN_DELAY: f32 = 1.5;
+/// Seconds to fly back in from the bottom of the screen
+const FLY_IN: f32 = 1.;
+/// Seconds of invulnerability after respawning, counted from the start of the fly-in
+const RESPAWN_INVULNERABLE: f32 = 3.;
+/// Seconds of invulnerability after a bomb
+const BOMB_INVULNERABLE: f32 = 1.;
+/// Points for a graze, before the multiplier
+const GRAZE_SCORE: u32 = 10;
+/// Every graze adds this much to the score multiplier
+const GRAZE_MULTIPLIER: f32 = 0.05;
+const MAX_MULTIPLIER: f32 = 4.;
+/// The multiplier goes back towards 1 by this much every second
+const MULTIPLIER_DECAY: f32 = 0.1;
+
+/// What the player is pressing this frame, filled in by the game from the keyboard
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PlayerInput {
+    /// Each axis from -1 to 1
+    pub direction: Vec2,
+    pub fire: bool,
+    /// Pressed this frame, not held
+    pub bomb: bool,
+}
+
+/// Where the player is in their life cycle. Every timer is an `AliveTimer`, they only run
+/// while the world updates.
+#[derive(Debug, Clone)]
+pub enum Life {
+    Alive,
+    /// Exploded, waiting to respawn
+    Dead(AliveTimer),
+    /// Flying back in from the bottom of the screen, can't shoot yet
+    FlyingIn(AliveTimer),
+    /// Out of lives, see `Player::continue_game`
+    GameOver,
+}
+
+const START_LIVES: u32 = 3;
+const START_BOMBS: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct Player {
+    center: CenterPt,
+    /// Pixels per second
+    pub speed: f32,
+    /// Hit box, much smaller than the ship
+    pub radius: f32,
+    /// Enemy bullets passing this close are grazed
+    pub graze_radius: f32,
+    pub color: GameColor,
+    pub weapon: Weapon,
+    pub score: u64,
+    /// Bullets grazed this game
+    pub grazes: u32,
+    /// Applied to points from `add_score`, grows with every graze
+    multiplier: f32,
+    pub bombs: u32,
+    /// Ships left, including the one playing
+    pub lives: u32,
+    life: Life,
+    invulnerable: AliveTimer,
+}
+
+impl Player {
+    pub fn new(center: CenterPt, weapon: Weapon) -> Self {
+        Self {
+            center,
+            speed: 60.,
+            radius: 1.5,
+            graze_radius: 8.,
+            color: utils::SKYBLUE,
+            weapon,
+            score: 0,
+            grazes: 0,
+            multiplier: 1.,
+            bombs: START_BOMBS,
+            lives: START_LIVES,
+            life: Life::Alive,
+            invulnerable: AliveTimer::default(),
+        }
+    }
+
+    pub fn life(&self) -> &Life {
+        &self.life
+    }
+
+    /// Can be hit by bullets
+    pub fn is_vulnerable(&self) -> bool {
+        matches!(self.life, Life::Alive) && !self.invulnerable.is_alive()
+    }
+
+    pub fn is_game_over(&self) -> bool {
+        matches!(self.life, Life::GameOver)
+    }
+
+    pub fn multiplier(&self) -> f32 {
+        self.multiplier
+    }
+
+    /// Points times the graze multiplier
+    pub fn add_score(&mut self, points: u32) {
+        self.score += (points as f32 * self.multiplier).round() as u64;
+    }
+
+    /// A bullet passed within `graze_radius` but missed the hit box, see `Bullet::graze`
+    pub fn graze(&mut self) {
+        self.grazes += 1;
+        self.add_score(GRAZE_SCORE);
+        self.multiplier = (self.multiplier + GRAZE_MULTIPLIER).min(MAX_MULTIPLIER);
+    }
+
+    /// Graze the bullets inside `graze_radius` that miss the hit box, each bullet only once.
+    /// Returns where the grazed bullets are.
+    pub fn graze_bullets<'a>(
+        &mut self,
+        bullets: impl Iterator<Item = &'a mut Bullet>,
+    ) -> Vec<CenterPt> {
+        let mut grazed = Vec::new();
+        for bullet in bullets {
+            if bullet.is_alive()
+                && bullet.overlaps(self.center, self.graze_radius)
+                && !bullet.overlaps(self.center, self.radius)
+                && bullet.graze()
+            {
+                self.graze();
+                grazed.push(bullet.center());
+            }
+        }
+        grazed
+    }
+
+    /// Lose a ship and a weapon level. Returns false when the player could not be hit.
+    pub fn kill(&mut self) -> bool {
+        if !self.is_vulnerable() {
+            return false;
+        }
+        self.lives = self.lives.saturating_sub(1);
+        self.weapon.lose_levels(1);
+        self.multiplier = 1.;
+        self.life = if self.lives > 0 {
+            Life::Dead(AliveTimer::new(RESPAWN_DELAY))
+        } else {
+            Life::GameOver
+        };
+        true
+    }
+
+    /// Returns true when a bomb went off
+    pub fn use_bomb(&mut self) -> bool {
+        if self.bombs == 0 || !matches!(self.life, Life::Alive) {
+            return false;
+        }
+        self.bombs -= 1;
+        if self.invulnerable.ttl() < BOMB_INVULNERABLE {
+            self.invulnerable = AliveTimer::new(BOMB_INVULNERABLE);
+        }
+        true
+    }
+
+    /// Start over with full lives and bombs, the score goes back to 0
+    pub fn continue_game(&mut self) {
+        self.lives = START_LIVES;
+        self.bombs = START_BOMBS;
+        self.score = 0;
+        self.grazes = 0;
+        self.life = Life::Dead(AliveTimer::new(0.));
+    }
+
+    /// Move and shoot, staying inside `bounds`. Returns the bullets fired.
+    pub fn update_input(
+        &mut self,
+        input: PlayerInput,
+        delta_time: f32,
+        bounds: Rect,
+    ) -> Vec<Bullet> {
+        self.invulnerable.update(delta_time);
+        self.weapon.update(delta_time);
+        self.multiplier = (self.multiplier - MULTIPLIER_DECAY * delta_time).max(1.);
+        match &mut self.life {
+            Life::Alive => {}
+            Life::GameOver => return Vec::new(),
+            Life::Dead(timer) => {
+                timer.update(delta_time);
+                if !timer.is_alive() {
+                    self.life = Life::FlyingIn(AliveTimer::new(FLY_IN));
+                    self.invulnerable = AliveTimer::new(RESPAWN_INVULNERABLE);
+                    self.center = CenterPt::new(bounds.center().x, bounds.bottom() + 8.);
+                }
+                return Vec::new();
+            }
+            Life::FlyingIn(timer) => {
+                timer.update(delta_time);
+                // From just below the screen up to the spawn point
+                let (from, to) = (bounds.bottom() + 8., bounds.bottom() - 16.);
+                let t = timer.ttl() / FLY_IN;
+                self.center = CenterPt::new(self.center.get().0, to + (from - to) * t);
+                if !timer.is_alive() {
+                    self.life = Life::Alive;
+                }
+                return Vec::new();
+            }
+        }
+        let (x, y) = self.center.get();
+        let step = input.direction.clamp_length_max(1.) * self.speed * delta_time;
+        let x = (x + step.x).clamp(bounds.left() + 3., bounds.right() - 3.);
+        let y = (y + step.y).clamp(bounds.top() + 3., bounds.bottom() - 3.);
+        self.center = CenterPt::new(x, y);
+        if input.fire {
+            self.weapon.fire(self.center)
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+crate::impl_pts!(center Player);
+
+impl Drawable for Player {
+    fn draw(&self) {
+        if matches!(self.life, Life::Dead(_) | Life::GameOver) {
+            return;
+        }
+        // Blink while invulnerable
+        if self.invulnerable.is_alive() && (self.invulnerable.ttl() * 15.).fract() < 0.5 {
+            return;
+        }
+        let (x, y) = self.center.get();
+        draw_triangle(
+            vec2(x, y - 4.),
+            vec2(x - 3., y + 3.),
+            vec2(x + 3., y + 3.),
+            self.color.into(),
+        );
+    }
+
+    fn draw_gizmos(&self) {
+        let (x, y) = self.center.get();
+        draw_circle_lines(x, y, self.radius, 0.5, RED);
+        draw_circle_lines(x, y, self.graze_radius, 0.5, YELLOW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{weapon::WeaponDef, Velocity};
+
+    const STEP: f32 = 0.25;
+    const BOUNDS: Rect = Rect {
+        x: 0.,
+        y: 0.,
+        w: 100.,
+        h: 100.,
+    };
+
+    fn player() -> Player {
+        Player::new(CenterPt::new(50., 50.), Weapon::new(WeaponDef::default()))
+    }
+
+    /// Updates in `STEP`s, holding fire, returns the bullets fired
+    fn run(player: &mut Player, seconds: f32) -> usize {
+        let input = PlayerInput {
+            fire: true,
+            ..Default::default()
+        };
+        let steps = (seconds / STEP).round() as usize;
+        (0..steps)
+            .map(|_| player.update_input(input, STEP, BOUNDS).len())
+            .sum()
+    }
+
+    #[test]
+    fn dies_respawns_and_flies_in() {
+        let mut player = player();
+        player.weapon.add_power(5);
+        assert!(player.kill());
+        assert_eq!(player.lives, START_LIVES - 1);
+        assert_eq!(player.weapon.level(), 0);
+        assert!(matches!(player.life(), Life::Dead(_)));
+        assert!(!player.kill(), "a wreck can't be hit again");
+
+        assert_eq!(run(&mut player, RESPAWN_DELAY - STEP), 0);
+        assert!(matches!(player.life(), Life::Dead(_)));
+        run(&mut player, STEP);
+        assert!(matches!(player.life(), Life::FlyingIn(_)));
+        assert_eq!(player.center.get(), (50., 108.));
+
+        assert_eq!(run(&mut player, FLY_IN - STEP), 0, "can't shoot flying in");
+        run(&mut player, STEP);
+        assert!(matches!(player.life(), Life::Alive));
+        assert_eq!(player.center.get(), (50., 84.));
+        assert!(run(&mut player, STEP) > 0);
+
+        // Invulnerable for a while after flying in
+        run(&mut player, RESPAWN_INVULNERABLE - FLY_IN - 2. * STEP);
+        assert!(!player.is_vulnerable());
+        run(&mut player, STEP);
+        assert!(player.is_vulnerable());
+    }
+
+    #[test]
+    fn bombs_make_invulnerable() {
+        let mut player = player();
+        assert!(player.use_bomb());
+        assert_eq!(player.bombs, START_BOMBS - 1);
+        assert!(!player.kill());
+        run(&mut player, BOMB_INVULNERABLE - STEP);
+        assert!(!player.is_vulnerable());
+        run(&mut player, STEP);
+        assert!(player.kill());
+        assert!(!player.use_bomb(), "no bombs while dead");
+        assert_eq!(player.bombs, START_BOMBS - 1);
+    }
+
+    #[test]
+    fn game_over_and_continue() {
+        let mut player = player();
+        player.add_score(100);
+        for _ in 0..START_LIVES {
+            assert!(player.kill());
+            // Sit out the respawn
+            run(&mut player, RESPAWN_DELAY + RESPAWN_INVULNERABLE);
+        }
+        assert!(player.is_game_over());
+        assert_eq!(player.lives, 0);
+        assert_eq!(run(&mut player, 10.), 0);
+        assert!(player.is_game_over());
+
+        player.continue_game();
+        assert_eq!(
+            (player.lives, player.bombs, player.score),
+            (START_LIVES, START_BOMBS, 0)
+        );
+        run(&mut player, STEP);
+        assert!(matches!(player.life(), Life::FlyingIn(_)));
+        run(&mut player, FLY_IN);
+        assert!(matches!(player.life(), Life::Alive));
+    }
+
+    fn bullet_at(x: f32, y: f32) -> Bullet {
+        Bullet::new(CenterPt::new(x, y), Velocity::new(0., 0.), 1., utils::WHITE)
+    }
+
+    #[test]
+    fn each_bullet_is_grazed_once() {
+        let mut player = player();
+        let mut bullets = [bullet_at(55., 50.)];
+        for _ in 0..5 {
+            player.graze_bullets(bullets.iter_mut());
+            run(&mut player, STEP);
+        }
+        assert_eq!(player.grazes, 1);
+        assert_eq!(player.score, GRAZE_SCORE as u64);
+    }
+
+    #[test]
+    fn hits_and_misses_are_not_grazes() {
+        let mut player = player();
+        // On the hit box, and far away
+        let mut bullets = [bullet_at(51., 50.), bullet_at(80., 50.)];
+        assert!(player.graze_bullets(bullets.iter_mut()).is_empty());
+        assert_eq!(player.grazes, 0);
+        assert_eq!(player.multiplier(), 1.);
+    }
+
+    #[test]
+    fn multiplier_is_capped_and_decays() {
+        let mut player = player();
+        for _ in 0..200 {
+            player.graze();
+        }
+        assert_eq!(player.multiplier(), MAX_MULTIPLIER);
+        player.add_score(10);
+        let score = player.score;
+        player.add_score(10);
+        assert_eq!(player.score - score, 40);
+
+        run(&mut player, 10.);
+        assert!((player.multiplier() - (MAX_MULTIPLIER - 10. * MULTIPLIER_DECAY)).abs() < 1e-4);
+        run(&mut player, 100.);
+        assert_eq!(player.multiplier(), 1.);
+        assert!(player.kill());
+        assert_eq!(player.multiplier(), 1.);
+    }
+}
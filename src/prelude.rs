@@ -1,8 +1,8 @@
-pub use anyhow::{Context, Result};
+pub use anyhow::Result;
 pub use cowshmup::{
     drawable::{Drawable, Graphic},
     updateable::Updateable,
-    world::{World, GAME_HEIGHT, GAME_WIDTH},
+    world::World,
 };
 pub use egui_macroquad::egui;
 pub use macroquad::prelude::*;
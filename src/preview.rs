@@ -1,10 +1,10 @@
-use std::{
-    fs::File,
-    io::{BufReader, BufWriter},
+use cowshmup::{
+    assets::{self, Asset},
+    buildable::Buildable,
+    migrate::Versioned,
+    retro_camera::RetroCamera,
 };
 
-use cowshmup::{buildable::Buildable, retro_camera::RetroCamera};
-
 use crate::prelude::*;
 
 /// Create a preview
@@ -13,6 +13,8 @@ use crate::prelude::*;
 pub trait Preview {
     fn update(&mut self, delta_time: f32);
     fn load(&mut self) -> anyhow::Result<()>;
+    /// What `load` and `save` use
+    fn file_name(&self) -> &str;
     fn save(&mut self) -> anyhow::Result<()>;
     fn update_ui(&mut self, delta_time: f32, ui: &mut egui::Ui);
     fn draw_ui(&mut self, ui: &mut egui::Ui);
@@ -41,7 +43,7 @@ where
 
 impl<Editing> Default for PreviewBuildableData<Editing>
 where
-    Editing: Buildable + Serialize + for<'de> Deserialize<'de>,
+    Editing: Buildable + Asset + Versioned + Serialize,
 {
     fn default() -> Self {
         let mut obj = Self {
@@ -61,7 +63,7 @@ where
 
 impl<Editing> Preview for PreviewBuildableData<Editing>
 where
-    Editing: Buildable + Serialize + for<'de> Deserialize<'de>,
+    Editing: Buildable + Asset + Versioned + Serialize,
 {
     fn update(&mut self, delta_time: f32) {
         if let Some(game_obj) = &mut self.game_object {
@@ -78,18 +80,20 @@ where
     }
 
     fn load(&mut self) -> anyhow::Result<()> {
-        let rdr = BufReader::new(
-            File::open(&self.filename)
-                .with_context(|| format!("Could not open {}", self.filename))?,
-        );
-        let obj = serde_yaml::from_reader::<_, Editing>(rdr)
-            .with_context(|| format!("could not parse {}", self.filename))?;
-        self.builder = obj;
+        self.builder = assets::reload::<Editing>(&self.filename)?.cloned();
+        // Start over with what was loaded
+        self.game_object = None;
         Ok(())
     }
 
+    fn file_name(&self) -> &str {
+        &self.filename
+    }
+
     fn save(&mut self) -> anyhow::Result<()> {
-        serde_yaml::to_writer(BufWriter::new(File::create(&self.filename)?), &self.builder)?;
+        assets::save_yaml(&assets::path(&self.filename), &self.builder)?;
+        // The game loads what was saved next time
+        assets::forget(&self.filename);
         Ok(())
     }
 
@@ -0,0 +1,163 @@
+//! Dynamic difficulty. Rank creeps up while the player does well and drops when they die, bullet
+//! patterns and spawners read it to get faster and busier.
+use serde::{Deserialize, Serialize};
+
+use crate::{migrate::Versioned, minmax::MinMax};
+
+pub const DEFAULT_RANK_FILE: &str = "rank.yaml";
+
+/// How rank changes, and what it does at its lowest and highest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RankCurve {
+    pub range: MinMax<f32>,
+    pub start: f32,
+    /// Rank gained every second the player is alive
+    pub per_second: f32,
+    /// Rank gained for every 1000 points scored
+    pub per_thousand_points: f32,
+    /// Rank gained every second for every weapon level above the first
+    pub per_power_level: f32,
+    /// Rank lost when the player dies
+    pub on_death: f32,
+    /// Multiplies bullet speed, at the lowest and highest rank
+    pub bullet_speed: MinMax<f32>,
+    /// Multiplies the number of bullets in a volley
+    pub bullet_density: MinMax<f32>,
+    /// Enemies spawners add to each spawn
+    pub extra_enemies: MinMax<f32>,
+}
+
+impl Default for RankCurve {
+    fn default() -> Self {
+        Self {
+            range: MinMax::new(0., 100.),
+            start: 20.,
+            per_second: 0.2,
+            per_thousand_points: 0.5,
+            per_power_level: 0.1,
+            on_death: 15.,
+            bullet_speed: MinMax::new(0.8, 1.6),
+            bullet_density: MinMax::new(0.75, 2.),
+            extra_enemies: MinMax::new(0., 2.),
+        }
+    }
+}
+
+impl Versioned for RankCurve {}
+
+#[derive(Debug, Clone)]
+pub struct Rank {
+    pub curve: RankCurve,
+    value: f32,
+    /// Score at the last update, to see how fast the player is scoring
+    last_score: u64,
+}
+
+impl Default for Rank {
+    fn default() -> Self {
+        Self::new(RankCurve::default())
+    }
+}
+
+impl Rank {
+    pub fn new(curve: RankCurve) -> Self {
+        Self {
+            value: curve.start,
+            curve,
+            last_score: 0,
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: f32) {
+        // Not `clamp`, a bad range from YAML should not panic
+        self.value = value.max(self.curve.range.min).min(self.curve.range.max);
+    }
+
+    /// From 0 at the lowest rank to 1 at the highest
+    pub fn fraction(&self) -> f32 {
+        let range = self.curve.range;
+        let width = range.max - range.min;
+        if width <= 0. {
+            return 0.;
+        }
+        ((self.value - range.min) / width).clamp(0., 1.)
+    }
+
+    fn lerp(&self, v: MinMax<f32>) -> f32 {
+        v.min + (v.max - v.min) * self.fraction()
+    }
+
+    pub fn bullet_speed(&self) -> f32 {
+        self.lerp(self.curve.bullet_speed)
+    }
+
+    pub fn bullet_density(&self) -> f32 {
+        self.lerp(self.curve.bullet_density)
+    }
+
+    /// Bullets in a volley of `count` at this rank, never less than 1
+    pub fn bullet_count(&self, count: u32) -> u32 {
+        ((count as f32 * self.bullet_density()).round() as u32).max(1)
+    }
+
+    pub fn extra_enemies(&self) -> u32 {
+        self.lerp(self.curve.extra_enemies).floor() as u32
+    }
+
+    /// Call every update while the player is alive. `power_level` is the weapon level, from 0.
+    pub fn update(&mut self, delta_time: f32, score: u64, power_level: usize) {
+        let scored = score.saturating_sub(self.last_score) as f32;
+        self.last_score = score;
+        let gain = self.curve.per_second * delta_time
+            + self.curve.per_thousand_points * scored / 1000.
+            + self.curve.per_power_level * power_level as f32 * delta_time;
+        self.set_value(self.value + gain);
+    }
+
+    /// The score went back to 0, e.g. after a continue
+    pub fn reset_score(&mut self) {
+        self.last_score = 0;
+    }
+
+    pub fn on_death(&mut self) {
+        self.set_value(self.value - self.curve.on_death);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only points count
+    fn rank() -> Rank {
+        Rank::new(RankCurve {
+            per_second: 0.,
+            per_power_level: 0.,
+            per_thousand_points: 1.,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn points_raise_rank() {
+        let mut rank = rank();
+        rank.update(1., 2000, 0);
+        assert_eq!(rank.value(), 22.);
+        rank.update(1., 2000, 0);
+        assert_eq!(rank.value(), 22.);
+    }
+
+    #[test]
+    fn points_count_again_after_a_continue() {
+        let mut rank = rank();
+        rank.update(1., 5000, 0);
+        rank.reset_score();
+        rank.update(1., 1000, 0);
+        assert_eq!(rank.value(), 26.);
+    }
+}
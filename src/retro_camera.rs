@@ -1,15 +1,129 @@
 use egui_macroquad::egui;
 use macroquad::prelude::*;
 
-use crate::CenterPt;
+use crate::{
+    alive::IsAlive,
+    scroll_camera::ScrollCamera,
+    settings::{AspectMode, DisplaySettings},
+    timers::AliveTimer,
+    updateable::Updateable,
+    utils::GameColor,
+    CenterPt,
+};
+
+/// Something gameplay (or the editor) wants the camera to do, see `World::camera_effect`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraEffect {
+    /// Add trauma, from 0 to 1. The shake grows with the square of the trauma, which decays over
+    /// time, so small hits barely move the screen while big ones stack up.
+    Shake(f32),
+    /// Freeze the game for this many seconds
+    HitStop(f32),
+    /// Cover the screen with a color that fades out over `duration` seconds
+    Flash { color: GameColor, duration: f32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraEffects {
+    trauma: f32,
+    /// How much trauma goes away every second
+    pub trauma_decay: f32,
+    /// Offset, in game pixels, at full trauma
+    pub max_shake: f32,
+    offset: Vec2,
+    hit_stop: AliveTimer,
+    flash: AliveTimer,
+    flash_color: GameColor,
+    flash_duration: f32,
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self {
+            trauma: 0.,
+            trauma_decay: 1.5,
+            max_shake: 4.,
+            offset: Vec2::ZERO,
+            hit_stop: AliveTimer::default(),
+            flash: AliveTimer::default(),
+            flash_color: GameColor::default(),
+            flash_duration: 0.,
+        }
+    }
+}
+
+impl CameraEffects {
+    pub fn apply(&mut self, effect: CameraEffect) {
+        match effect {
+            CameraEffect::Shake(trauma) => self.trauma = (self.trauma + trauma).clamp(0., 1.),
+            CameraEffect::HitStop(time) => {
+                if time > self.hit_stop.ttl() {
+                    self.hit_stop = AliveTimer::new(time);
+                }
+            }
+            CameraEffect::Flash { color, duration } => {
+                self.flash = AliveTimer::new(duration);
+                self.flash_color = color;
+                self.flash_duration = duration;
+            }
+        }
+    }
+
+    pub fn is_hit_stopped(&self) -> bool {
+        self.hit_stop.is_alive()
+    }
+
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+
+    /// Current shake, always in whole pixels so the retro screen does not get blurry
+    pub fn offset(&self) -> Vec2 {
+        self.offset
+    }
+
+    /// The flash color, with alpha fading out, if there is a flash
+    pub fn flash(&self) -> Option<Color> {
+        if !self.flash.is_alive() || self.flash_duration <= 0. {
+            return None;
+        }
+        let mut color: Color = self.flash_color.into();
+        color.a *= self.flash.ttl() / self.flash_duration;
+        Some(color)
+    }
+}
+
+impl Updateable for CameraEffects {
+    fn update(&mut self, delta_time: f32) {
+        self.hit_stop.update(delta_time);
+        self.flash.update(delta_time);
+        self.trauma = (self.trauma - self.trauma_decay * delta_time).max(0.);
+        let shake = self.max_shake * self.trauma * self.trauma;
+        self.offset = if shake > 0. {
+            Vec2::new(
+                (shake * rand::gen_range(-1., 1.)).round(),
+                (shake * rand::gen_range(-1., 1.)).round(),
+            )
+        } else {
+            Vec2::ZERO
+        };
+    }
+}
 
 pub struct RetroCamera {
     render_target: RenderTarget,
     game_canvas: Rect,
+    /// The space the game may use, letterbox and pillarbox crop what does not fit in it
+    available: Rect,
     camera: Camera2D,
-    zoom: f32,
+    /// Separate x and y zoom, they only differ for `AspectMode::Stretch`
+    zoom: Vec2,
     size: Vec2,
     allow_non_int_scaling: bool,
+    aspect: AspectMode,
+    effects: CameraEffects,
+    /// The part of the world to render
+    view: ScrollCamera,
 }
 
 impl std::fmt::Debug for RetroCamera {
@@ -18,28 +132,117 @@ impl std::fmt::Debug for RetroCamera {
             .field("game_canvas", &self.game_canvas)
             .field("zoom", &self.zoom)
             .field("size", &self.size)
+            .field("effects", &self.effects)
             .finish()
     }
 }
 
 impl RetroCamera {
     pub fn new(width: f32, height: f32) -> Self {
-        let render_target = render_target(width as u32, height as u32);
-        render_target.texture.set_filter(FilterMode::Nearest);
-        let mut camera = Camera2D::from_display_rect(Rect::new(0., 0., width, height));
-        camera.render_target = Some(render_target);
-        camera.zoom.y *= -1.;
+        let (render_target, camera) = Self::create_target(width, height, FilterMode::Nearest);
         let game_canvas = Rect::new(0., 0., screen_width(), screen_height());
         Self {
             render_target,
             game_canvas,
+            available: game_canvas,
             camera,
-            zoom: 1.,
+            zoom: Vec2::ONE,
             size: Vec2::new(width, height),
             allow_non_int_scaling: false,
+            aspect: AspectMode::Fit,
+            effects: CameraEffects::default(),
+            view: ScrollCamera::new(width, height),
         }
     }
 
+    /// Render the part of the world `camera` is on, usually `World::camera`
+    pub fn set_view(&mut self, camera: &ScrollCamera) {
+        self.view = camera.clone();
+    }
+
+    /// Window (mouse) coordinates to game screen pixels. The result is outside of `size` when
+    /// the point is not on the game canvas.
+    pub fn window_to_screen(&self, p: Vec2) -> Vec2 {
+        (p - Vec2::new(self.game_canvas.x, self.game_canvas.y)) / self.zoom
+    }
+
+    pub fn screen_to_window(&self, p: Vec2) -> Vec2 {
+        Vec2::new(self.game_canvas.x, self.game_canvas.y) + p * self.zoom
+    }
+
+    /// Where the world is drawn, which moves with the shake
+    pub fn screen_to_world(&self, p: Vec2) -> Vec2 {
+        self.view.screen_to_world(p - self.effects.offset())
+    }
+
+    pub fn world_to_screen(&self, p: Vec2) -> Vec2 {
+        self.view.world_to_screen(p) + self.effects.offset()
+    }
+
+    pub fn window_to_world(&self, p: Vec2) -> Vec2 {
+        self.screen_to_world(self.window_to_screen(p))
+    }
+
+    pub fn world_to_window(&self, p: Vec2) -> Vec2 {
+        self.screen_to_window(self.world_to_screen(p))
+    }
+
+    /// Is the window point over the game canvas?
+    pub fn is_on_screen(&self, p: Vec2) -> bool {
+        let s = self.window_to_screen(p);
+        s.x >= 0. && s.y >= 0. && s.x < self.size.x && s.y < self.size.y
+    }
+
+    pub fn apply(&mut self, effect: CameraEffect) {
+        self.effects.apply(effect);
+    }
+
+    pub fn effects(&self) -> &CameraEffects {
+        &self.effects
+    }
+
+    pub fn effects_mut(&mut self) -> &mut CameraEffects {
+        &mut self.effects
+    }
+
+    /// While this is true the game should not update the world
+    pub fn is_hit_stopped(&self) -> bool {
+        self.effects.is_hit_stopped()
+    }
+
+    fn create_target(width: f32, height: f32, filter: FilterMode) -> (RenderTarget, Camera2D) {
+        let render_target = render_target(width as u32, height as u32);
+        render_target.texture.set_filter(filter);
+        let mut camera = Camera2D::from_display_rect(Rect::new(0., 0., width, height));
+        camera.render_target = Some(render_target);
+        camera.zoom.y *= -1.;
+        (render_target, camera)
+    }
+
+    pub fn from_settings(settings: &DisplaySettings) -> Self {
+        let (width, height) = settings.size();
+        let mut camera = Self::new(width, height);
+        camera.apply_settings(settings);
+        camera
+    }
+
+    /// Change resolution and scaling at runtime. The render target is only recreated when the
+    /// resolution changes.
+    pub fn apply_settings(&mut self, settings: &DisplaySettings) {
+        let (width, height) = settings.size();
+        let filter: FilterMode = settings.filter.into();
+        if self.size != Vec2::new(width, height) {
+            self.render_target.delete();
+            let (render_target, camera) = Self::create_target(width, height, filter);
+            self.render_target = render_target;
+            self.camera = camera;
+            self.size = Vec2::new(width, height);
+        }
+        self.render_target.texture.set_filter(filter);
+        self.aspect = settings.aspect;
+        self.allow_non_int_scaling = settings.allow_non_int_scaling;
+    }
+
     pub fn free_scale(&mut self) {
         self.allow_non_int_scaling = true;
     }
@@ -48,16 +251,16 @@ impl RetroCamera {
     /// space
     pub fn calculate_canvas_position_for_int_scale(&mut self) {
         let game_canvas = &mut self.game_canvas;
-        let mut zoom = game_canvas.w / self.size.x;
-        if !self.allow_non_int_scaling {
-            zoom = zoom.floor();
-        }
-        zoom = zoom.min(game_canvas.h / self.size.y);
-        if !self.allow_non_int_scaling {
-            zoom = zoom.floor();
-        }
-        game_canvas.x += (game_canvas.w - (self.size.x * zoom)) / 2.;
-        game_canvas.y += (game_canvas.h - (self.size.y * zoom)) / 2.;
+        self.available = *game_canvas;
+        let available = Vec2::new(game_canvas.w, game_canvas.h);
+        let zoom = canvas_zoom(
+            self.aspect,
+            available,
+            self.size,
+            self.allow_non_int_scaling,
+        );
+        game_canvas.x += (game_canvas.w - (self.size.x * zoom.x)) / 2.;
+        game_canvas.y += (game_canvas.h - (self.size.y * zoom.y)) / 2.;
         self.zoom = zoom
     }
 
@@ -78,6 +281,7 @@ impl RetroCamera {
 
     pub fn setup_camera(&mut self) {
         self.calculate_canvas_position_for_int_scale();
+        self.camera.target = self.screen_to_world(self.size / 2.);
         push_camera_state();
         set_camera(&self.camera);
     }
@@ -87,22 +291,83 @@ impl RetroCamera {
         self.render_target.texture
     }
 
+    /// The game screen at its native resolution, without popping the camera like
+    /// `render_texture` does. Only complete once the frame was drawn.
+    pub fn texture(&self) -> Texture2D {
+        self.render_target.texture
+    }
+
     pub fn center(&self) -> CenterPt {
         CenterPt::new(self.size.x / 2., self.size.y / 2.)
     }
 
+    /// The part of the game screen that fits into the available space, in game pixels, and
+    /// where it goes on the window
+    fn visible(&self) -> (Rect, Rect) {
+        let screen = Rect::new(
+            self.game_canvas.x,
+            self.game_canvas.y,
+            self.size.x * self.zoom.x,
+            self.size.y * self.zoom.y,
+        );
+        let dest = screen
+            .intersect(self.available)
+            .unwrap_or(Rect::new(screen.x, screen.y, 0., 0.));
+        let source = Rect::new(
+            (dest.x - screen.x) / self.zoom.x,
+            (dest.y - screen.y) / self.zoom.y,
+            dest.w / self.zoom.x,
+            dest.h / self.zoom.y,
+        );
+        (source, dest)
+    }
+
     pub fn render(&self) {
         pop_camera_state();
+        let (source, dest) = self.visible();
         draw_texture_ex(
             self.render_target.texture,
-            self.game_canvas.x,
-            self.game_canvas.y,
+            dest.x,
+            dest.y,
             WHITE,
             DrawTextureParams {
-                dest_size: Some(Vec2::new(self.size.x * self.zoom, self.size.y * self.zoom)),
+                dest_size: Some(dest.size()),
+                source: Some(source),
                 ..Default::default()
             },
         );
+        if let Some(color) = self.effects.flash() {
+            draw_rectangle(dest.x, dest.y, dest.w, dest.h, color);
+        }
+    }
+}
+
+/// How much a game screen of `size` is scaled up to fit into `available`
+fn canvas_zoom(
+    aspect: AspectMode,
+    available: Vec2,
+    size: Vec2,
+    allow_non_int_scaling: bool,
+) -> Vec2 {
+    let snap = |zoom: f32| {
+        if allow_non_int_scaling {
+            zoom
+        } else {
+            zoom.floor()
+        }
+    };
+    let zoom = available / size;
+    match aspect {
+        AspectMode::Fit => Vec2::splat(snap(zoom.x.min(zoom.y))),
+        AspectMode::Letterbox => Vec2::splat(snap(zoom.x)),
+        AspectMode::Pillarbox => Vec2::splat(snap(zoom.y)),
+        AspectMode::Stretch => zoom,
+    }
+}
+
+impl Updateable for RetroCamera {
+    fn update(&mut self, delta_time: f32) {
+        self.effects.update(delta_time);
     }
 }
 
@@ -111,3 +376,49 @@ impl Default for RetroCamera {
         Self::new(crate::world::GAME_WIDTH, crate::world::GAME_HEIGHT)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: Vec2 = Vec2::new(100., 50.);
+
+    fn zoom(aspect: AspectMode, w: f32, h: f32, allow_non_int_scaling: bool) -> Vec2 {
+        canvas_zoom(aspect, Vec2::new(w, h), SIZE, allow_non_int_scaling)
+    }
+
+    #[test]
+    fn every_mode_on_a_wide_and_a_tall_window() {
+        // 4 x 2.5 and 2.5 x 8 times the game screen
+        let (wide, tall) = (Vec2::new(400., 125.), Vec2::new(250., 400.));
+        let cases = [
+            (AspectMode::Fit, (2., 2.5), (2., 2.5)),
+            (AspectMode::Letterbox, (4., 4.), (2., 2.5)),
+            (AspectMode::Pillarbox, (2., 2.5), (8., 8.)),
+        ];
+        for (aspect, on_wide, on_tall) in cases {
+            for (available, (int, free)) in [(wide, on_wide), (tall, on_tall)] {
+                let zoom = |allow| canvas_zoom(aspect, available, SIZE, allow);
+                assert_eq!(zoom(false), Vec2::splat(int), "{:?} {}", aspect, available);
+                assert_eq!(zoom(true), Vec2::splat(free), "{:?} {}", aspect, available);
+            }
+        }
+        for allow_non_int_scaling in [false, true] {
+            let zoom = |available| {
+                canvas_zoom(AspectMode::Stretch, available, SIZE, allow_non_int_scaling)
+            };
+            assert_eq!(zoom(wide), Vec2::new(4., 2.5));
+            assert_eq!(zoom(tall), Vec2::new(2.5, 8.));
+        }
+    }
+
+    #[test]
+    fn stretch_fills_the_window() {
+        for allow_non_int_scaling in [false, true] {
+            assert_eq!(
+                zoom(AspectMode::Stretch, 250., 400., allow_non_int_scaling),
+                Vec2::new(2.5, 8.)
+            );
+        }
+    }
+}
@@ -0,0 +1,544 @@
+//! Rhai scripts for enemy behaviors and stage events. A script runs top to bottom as a coroutine:
+//! `wait(ticks)` hands control back to the game, which resumes it that many ticks later. Rhai can
+//! not suspend a script halfway, so each script runs on its own thread with its own engine, but
+//! only ever one at a time and in lockstep with the game, so a stage plays out the same every
+//! time. The world caps how many run at once, see `MAX_SCRIPTS`.
+//!
+//! Scripts can not touch files, and one that runs too long without a `wait` is stopped. What they
+//! ask for, like moving or firing, comes back to the world as `Command`s.
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+    time::SystemTime,
+};
+
+use ::rand::{rngs::StdRng, Rng, SeedableRng};
+use anyhow::{Context, Result};
+use macroquad::prelude::info;
+use rhai::{module_resolvers::DummyModuleResolver, Dynamic, Engine, EvalAltResult, FLOAT, INT};
+
+use crate::{assets, CenterPt, Rc, Velocity};
+
+/// The script a stage starts with
+pub const DEFAULT_STAGE_SCRIPT: &str = "stage";
+/// Scripts are resumed at most this often
+pub const TICKS_PER_SECOND: f32 = 60.;
+/// Between two `wait`s, against scripts that loop forever
+const MAX_OPERATIONS_PER_TICK: u64 = 100_000;
+/// Scripts running at once, every one is a thread
+pub const MAX_SCRIPTS: usize = 256;
+
+/// What a script sees when it is resumed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Snapshot {
+    /// Ticks since the script started
+    pub tick: u64,
+    /// The enemy running the script, or the middle of the screen for stage scripts
+    pub center: CenterPt,
+    pub player: Option<CenterPt>,
+    /// From 0 to 1, see `Rank::fraction`
+    pub rank: f32,
+    pub hp: f32,
+}
+
+/// What a script asked for, for the world to carry out. Angles are degrees clockwise from
+/// straight up, speeds are pixels per second.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// An enemy running the script `script`
+    Spawn {
+        script: String,
+        center: CenterPt,
+    },
+    MoveTo(CenterPt),
+    MoveBy(f32, f32),
+    SetVelocity(Velocity),
+    Fire {
+        angle: f32,
+        speed: f32,
+    },
+    /// Start a `BulletPattern` by name
+    Pattern(String),
+    Sound(String),
+    Shake(f32),
+    SetHp(f32),
+    SetScore(u32),
+    /// Drop table rolled when the enemy is killed
+    SetDrops(String),
+    /// Remove the enemy without an explosion or points
+    Vanish,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptError {
+    pub script: String,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.script, self.message)
+    }
+}
+
+#[derive(Debug)]
+enum Reply {
+    /// Resume after `wait` ticks
+    Yield {
+        commands: Vec<Command>,
+        wait: u32,
+    },
+    Done {
+        commands: Vec<Command>,
+    },
+    Failed {
+        message: String,
+    },
+}
+
+/// The script thread's side of a coroutine
+struct Host {
+    state: RefCell<HostState>,
+    resume: Receiver<Snapshot>,
+    replies: Sender<Reply>,
+}
+
+struct HostState {
+    snapshot: Snapshot,
+    commands: Vec<Command>,
+    rng: StdRng,
+    operations: u64,
+    operations_at_wait: u64,
+    /// The game dropped the coroutine
+    stopped: bool,
+}
+
+impl Host {
+    fn push(&self, command: Command) {
+        self.state.borrow_mut().commands.push(command);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.state.borrow().snapshot
+    }
+
+    fn wait(&self, ticks: INT) -> Result<(), Box<EvalAltResult>> {
+        let commands = std::mem::take(&mut self.state.borrow_mut().commands);
+        let wait = ticks.clamp(1, u32::MAX as INT) as u32;
+        let resumed = self
+            .replies
+            .send(Reply::Yield { commands, wait })
+            .ok()
+            .and_then(|_| self.resume.recv().ok());
+        let mut state = self.state.borrow_mut();
+        match resumed {
+            Some(snapshot) => {
+                state.snapshot = snapshot;
+                state.operations_at_wait = state.operations;
+                Ok(())
+            }
+            None => {
+                state.stopped = true;
+                Err("stopped".into())
+            }
+        }
+    }
+}
+
+/// Scripts pass whole numbers and decimals alike
+fn num(v: &Dynamic) -> f32 {
+    v.as_float()
+        .map(|f| f as f32)
+        .or_else(|_| v.as_int().map(|i| i as f32))
+        .unwrap_or(0.)
+}
+
+fn engine(name: &str, host: &Rc<Host>) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(4096)
+        .set_max_array_size(1024)
+        .set_max_map_size(256)
+        .disable_symbol("eval");
+
+    let h = host.clone();
+    engine.on_progress(move |operations| {
+        let mut state = h.state.borrow_mut();
+        state.operations = operations;
+        (operations - state.operations_at_wait > MAX_OPERATIONS_PER_TICK).then_some(Dynamic::UNIT)
+    });
+    let script = String::from(name);
+    engine.on_print(move |s| info!("{}: {}", script, s));
+    let script = String::from(name);
+    engine.on_debug(move |s, _, pos| info!("{} {}: {}", script, pos, s));
+
+    let h = host.clone();
+    engine.register_fn("wait", move || h.wait(1));
+    let h = host.clone();
+    engine.register_fn("wait", move |ticks: INT| h.wait(ticks));
+
+    let h = host.clone();
+    engine.register_fn("x", move || h.snapshot().center.get().0 as FLOAT);
+    let h = host.clone();
+    engine.register_fn("y", move || h.snapshot().center.get().1 as FLOAT);
+    let h = host.clone();
+    engine.register_fn("player_alive", move || h.snapshot().player.is_some());
+    let h = host.clone();
+    engine.register_fn("player_x", move || {
+        let s = h.snapshot();
+        s.player.unwrap_or(s.center).get().0 as FLOAT
+    });
+    let h = host.clone();
+    engine.register_fn("player_y", move || {
+        let s = h.snapshot();
+        s.player.unwrap_or(s.center).get().1 as FLOAT
+    });
+    let h = host.clone();
+    engine.register_fn("aim", move || {
+        let s = h.snapshot();
+        let (x, y) = s.center.get();
+        let (px, py) = s.player.map_or((x, y + 1.), |p| p.get());
+        (px - x).atan2(y - py).to_degrees() as FLOAT
+    });
+    let h = host.clone();
+    engine.register_fn("tick", move || h.snapshot().tick as INT);
+    let h = host.clone();
+    engine.register_fn("rank", move || h.snapshot().rank as FLOAT);
+    let h = host.clone();
+    engine.register_fn("hp", move || h.snapshot().hp as FLOAT);
+    let h = host.clone();
+    engine.register_fn("rand", move || {
+        h.state.borrow_mut().rng.gen_range(0. ..1.) as FLOAT
+    });
+    let h = host.clone();
+    engine.register_fn("rand", move |min: Dynamic, max: Dynamic| {
+        let (min, max) = (num(&min), num(&max));
+        if max <= min {
+            return min as FLOAT;
+        }
+        h.state.borrow_mut().rng.gen_range(min..max) as FLOAT
+    });
+
+    let h = host.clone();
+    engine.register_fn("spawn", move |script: &str, x: Dynamic, y: Dynamic| {
+        h.push(Command::Spawn {
+            script: String::from(script),
+            center: CenterPt::new(num(&x), num(&y)),
+        })
+    });
+    let h = host.clone();
+    engine.register_fn("move_to", move |x: Dynamic, y: Dynamic| {
+        h.push(Command::MoveTo(CenterPt::new(num(&x), num(&y))))
+    });
+    let h = host.clone();
+    engine.register_fn("move_by", move |dx: Dynamic, dy: Dynamic| {
+        h.push(Command::MoveBy(num(&dx), num(&dy)))
+    });
+    let h = host.clone();
+    engine.register_fn("set_velocity", move |vx: Dynamic, vy: Dynamic| {
+        h.push(Command::SetVelocity(Velocity::new(num(&vx), num(&vy))))
+    });
+    let h = host.clone();
+    engine.register_fn("fire", move |angle: Dynamic, speed: Dynamic| {
+        h.push(Command::Fire {
+            angle: num(&angle),
+            speed: num(&speed),
+        })
+    });
+    let h = host.clone();
+    engine.register_fn("pattern", move |name: &str| {
+        h.push(Command::Pattern(String::from(name)))
+    });
+    let h = host.clone();
+    engine.register_fn("sound", move |name: &str| {
+        h.push(Command::Sound(String::from(name)))
+    });
+    let h = host.clone();
+    engine.register_fn("shake", move |amount: Dynamic| {
+        h.push(Command::Shake(num(&amount)))
+    });
+    let h = host.clone();
+    engine.register_fn("set_hp", move |hp: Dynamic| {
+        h.push(Command::SetHp(num(&hp)))
+    });
+    let h = host.clone();
+    engine.register_fn("set_score", move |score: INT| {
+        h.push(Command::SetScore(score.max(0) as u32))
+    });
+    let h = host.clone();
+    engine.register_fn("set_drops", move |table: &str| {
+        h.push(Command::SetDrops(String::from(table)))
+    });
+    let h = host.clone();
+    engine.register_fn("vanish", move || h.push(Command::Vanish));
+    engine
+}
+
+/// The script thread, it waits for the first tick before it runs
+fn run(
+    name: String,
+    source: String,
+    seed: u64,
+    resume: Receiver<Snapshot>,
+    replies: Sender<Reply>,
+) {
+    let Ok(snapshot) = resume.recv() else {
+        return;
+    };
+    let host = Rc::new(Host {
+        state: RefCell::new(HostState {
+            snapshot,
+            commands: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+            operations: 0,
+            operations_at_wait: 0,
+            stopped: false,
+        }),
+        resume,
+        replies: replies.clone(),
+    });
+    let engine = engine(&name, &host);
+    let result = engine
+        .compile(&source)
+        .map_err(|err| err.to_string())
+        .and_then(|ast| {
+            engine.run_ast(&ast).map_err(|err| match *err {
+                EvalAltResult::ErrorTerminated(_, pos) => {
+                    format!("ran too long without a wait ({})", pos)
+                }
+                err => err.to_string(),
+            })
+        });
+    let mut state = host.state.borrow_mut();
+    if state.stopped {
+        return;
+    }
+    let reply = match result {
+        Ok(()) => Reply::Done {
+            commands: std::mem::take(&mut state.commands),
+        },
+        Err(message) => Reply::Failed { message },
+    };
+    let _ = replies.send(reply);
+}
+
+/// A running script. Dropping it stops the script at its next `wait`.
+#[derive(Debug)]
+pub struct Coroutine {
+    name: String,
+    resume: Sender<Snapshot>,
+    replies: Receiver<Reply>,
+    /// Ticks left to sleep
+    wait: u32,
+    ticks: u64,
+    finished: bool,
+}
+
+impl Coroutine {
+    pub fn start(name: &str, source: &str, seed: u64) -> Result<Self, ScriptError> {
+        let (resume, resumed) = channel();
+        let (reply, replies) = channel();
+        let (script, source) = (String::from(name), String::from(source));
+        thread::Builder::new()
+            .name(format!("script {}", name))
+            .spawn(move || run(script, source, seed, resumed, reply))
+            .map_err(|err| ScriptError {
+                script: String::from(name),
+                message: format!("could not start: {}", err),
+            })?;
+        Ok(Self {
+            name: String::from(name),
+            resume,
+            replies,
+            wait: 0,
+            ticks: 0,
+            finished: false,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The script ended, or failed
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Runs the script until its next `wait` if it is not asleep. `snapshot.tick` is filled in.
+    pub fn tick(&mut self, mut snapshot: Snapshot) -> Result<Vec<Command>, ScriptError> {
+        if self.finished {
+            return Ok(Vec::new());
+        }
+        self.ticks += 1;
+        if self.wait > 0 {
+            self.wait -= 1;
+            return Ok(Vec::new());
+        }
+        snapshot.tick = self.ticks - 1;
+        let reply = self
+            .resume
+            .send(snapshot)
+            .ok()
+            .and_then(|_| self.replies.recv().ok());
+        match reply {
+            Some(Reply::Yield { commands, wait }) => {
+                self.wait = wait - 1;
+                Ok(commands)
+            }
+            Some(Reply::Done { commands }) => {
+                self.finished = true;
+                Ok(commands)
+            }
+            Some(Reply::Failed { message }) => {
+                self.finished = true;
+                Err(ScriptError {
+                    script: self.name.clone(),
+                    message,
+                })
+            }
+            None => {
+                self.finished = true;
+                Err(ScriptError {
+                    script: self.name.clone(),
+                    message: String::from("the script stopped unexpectedly"),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LoadedScript {
+    source: String,
+    modified: Option<SystemTime>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Script sources by name, `<name>.rhai`, loaded once and reloaded when they change on disk
+#[derive(Debug, Default)]
+pub struct ScriptBank {
+    scripts: BTreeMap<String, LoadedScript>,
+}
+
+impl ScriptBank {
+    /// `<name>.rhai` in the asset root
+    pub fn path(name: &str) -> PathBuf {
+        assets::path(format!("{}.rhai", name))
+    }
+
+    pub fn source(&mut self, name: &str) -> Result<&str> {
+        if !self.scripts.contains_key(name) {
+            let path = Self::path(name);
+            let source = fs::read_to_string(&path)
+                .with_context(|| format!("Could not open {}", path.display()))?;
+            let modified = modified(&path);
+            self.scripts
+                .insert(String::from(name), LoadedScript { source, modified });
+        }
+        Ok(&self.scripts[name].source)
+    }
+
+    /// Reload scripts that changed on disk since they were loaded, returns their names
+    pub fn reload_changed(&mut self) -> Vec<String> {
+        let mut changed = Vec::new();
+        for (name, script) in &mut self.scripts {
+            let path = Self::path(name);
+            let now = modified(&path);
+            if now == script.modified {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(source) => {
+                    script.source = source;
+                    script.modified = now;
+                    changed.push(name.clone());
+                }
+                // Probably half written, try again next time
+                Err(_) => continue,
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(source: &str) -> Coroutine {
+        Coroutine::start("test", source, 1).unwrap()
+    }
+
+    fn tick(script: &mut Coroutine) -> Vec<Command> {
+        script.tick(Snapshot::default()).unwrap()
+    }
+
+    #[test]
+    fn wait_hands_back_control() {
+        let mut script = start("move_by(1, 2); wait(3); fire(90, 100);");
+        assert_eq!(tick(&mut script), [Command::MoveBy(1., 2.)]);
+        assert!(tick(&mut script).is_empty());
+        assert!(tick(&mut script).is_empty());
+        assert!(!script.is_finished());
+        assert_eq!(
+            tick(&mut script),
+            [Command::Fire {
+                angle: 90.,
+                speed: 100.
+            }]
+        );
+        assert!(script.is_finished());
+        assert!(tick(&mut script).is_empty());
+    }
+
+    #[test]
+    fn scripts_see_the_snapshot() {
+        let mut script = start("loop { move_to(x() + 1, tick()); wait(2); }");
+        let snapshot = Snapshot {
+            center: CenterPt::new(10., 0.),
+            ..Default::default()
+        };
+        let mut moves = Vec::new();
+        for _ in 0..5 {
+            moves.extend(script.tick(snapshot).unwrap());
+        }
+        assert_eq!(
+            moves,
+            [0., 2., 4.].map(|t| Command::MoveTo(CenterPt::new(11., t)))
+        );
+    }
+
+    #[test]
+    fn same_seed_same_script() {
+        let rolls = |seed| {
+            let mut script = Coroutine::start("test", "set_hp(rand(0, 100));", seed).unwrap();
+            tick(&mut script)
+        };
+        assert_eq!(rolls(1), rolls(1));
+        assert_ne!(rolls(1), rolls(2));
+    }
+
+    #[test]
+    fn errors_end_the_script() {
+        let mut script = start("move_by(1, ");
+        let err = script.tick(Snapshot::default()).unwrap_err();
+        assert_eq!(err.script, "test");
+        assert!(script.is_finished());
+
+        let mut script = start("wait(); loop {}");
+        assert!(tick(&mut script).is_empty());
+        let err = script.tick(Snapshot::default()).unwrap_err();
+        assert!(err.message.contains("ran too long"), "{}", err);
+    }
+}
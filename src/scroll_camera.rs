@@ -0,0 +1,164 @@
+use macroquad::prelude::{Rect, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::{updateable::Updateable, CenterPt, Size, Velocity};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScrollMode {
+    /// Only moves when told to
+    #[default]
+    Fixed,
+    /// Keep the target (see `ScrollCamera::set_target`) inside a box around the center of the
+    /// screen. `lerp` is how much of the remaining distance is covered every second, 0 snaps.
+    Follow { deadzone: Size, lerp: f32 },
+    /// Move at a constant speed, like the vertical scroll of a shmup stage
+    AutoScroll(Velocity),
+}
+
+/// Which part of the world is on screen. The world can be much larger than the screen, the
+/// `RetroCamera` uses the position to render the right part of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrollCamera {
+    /// World position of the top left corner of the screen
+    position: Vec2,
+    size: Vec2,
+    /// The camera never shows anything outside of these, when set
+    bounds: Option<Rect>,
+    mode: ScrollMode,
+    target: Option<CenterPt>,
+}
+
+impl Default for ScrollCamera {
+    fn default() -> Self {
+        Self::new(crate::world::GAME_WIDTH, crate::world::GAME_HEIGHT)
+    }
+}
+
+impl ScrollCamera {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self {
+            position: Vec2::ZERO,
+            size: Vec2::new(width, height),
+            bounds: None,
+            mode: ScrollMode::Fixed,
+            target: None,
+        }
+    }
+
+    pub fn with_bounds(mut self, bounds: Rect) -> Self {
+        self.bounds = Some(bounds);
+        self.clamp();
+        self
+    }
+
+    pub fn with_mode(mut self, mode: ScrollMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn set_mode(&mut self, mode: ScrollMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> ScrollMode {
+        self.mode
+    }
+
+    pub fn set_bounds(&mut self, bounds: Option<Rect>) {
+        self.bounds = bounds;
+        self.clamp();
+    }
+
+    pub fn set_size(&mut self, width: f32, height: f32) {
+        self.size = Vec2::new(width, height);
+        self.clamp();
+    }
+
+    /// What `ScrollMode::Follow` follows, usually the player
+    pub fn set_target(&mut self, target: CenterPt) {
+        self.target = Some(target);
+    }
+
+    pub fn position(&self) -> Vec2 {
+        self.position
+    }
+
+    pub fn move_to(&mut self, position: Vec2) {
+        self.position = position;
+        self.clamp();
+    }
+
+    /// Center the screen on a point
+    pub fn look_at(&mut self, center: CenterPt) {
+        let (x, y) = center.into();
+        self.move_to(Vec2::new(x, y) - self.size / 2.);
+    }
+
+    /// The part of the world that is on screen
+    pub fn view(&self) -> Rect {
+        Rect::new(self.position.x, self.position.y, self.size.x, self.size.y)
+    }
+
+    pub fn center(&self) -> CenterPt {
+        let c = self.position + self.size / 2.;
+        CenterPt::new(c.x, c.y)
+    }
+
+    pub fn world_to_screen(&self, p: Vec2) -> Vec2 {
+        p - self.position.round()
+    }
+
+    pub fn screen_to_world(&self, p: Vec2) -> Vec2 {
+        p + self.position.round()
+    }
+
+    fn clamp(&mut self) {
+        let Some(bounds) = self.bounds else {
+            return;
+        };
+        let clamp_axis = |pos: f32, size: f32, min: f32, extent: f32| {
+            if extent <= size {
+                // Bounds smaller than the screen, keep them centered
+                min - (size - extent) / 2.
+            } else {
+                pos.clamp(min, min + extent - size)
+            }
+        };
+        self.position.x = clamp_axis(self.position.x, self.size.x, bounds.x, bounds.w);
+        self.position.y = clamp_axis(self.position.y, self.size.y, bounds.y, bounds.h);
+    }
+}
+
+impl Updateable for ScrollCamera {
+    fn update(&mut self, delta_time: f32) {
+        match self.mode {
+            ScrollMode::Fixed => {}
+            ScrollMode::AutoScroll(v) => {
+                let (vx, vy) = v.into();
+                self.position += Vec2::new(vx, vy) * delta_time;
+            }
+            ScrollMode::Follow { deadzone, lerp } => {
+                if let Some(target) = self.target {
+                    let (tx, ty) = target.into();
+                    let (dw, dh) = deadzone.into();
+                    let center = self.position + self.size / 2.;
+                    let mut desired = self.position;
+                    let dx = tx - center.x;
+                    if dx.abs() > dw / 2. {
+                        desired.x += dx - dx.signum() * dw / 2.;
+                    }
+                    let dy = ty - center.y;
+                    if dy.abs() > dh / 2. {
+                        desired.y += dy - dy.signum() * dh / 2.;
+                    }
+                    self.position = if lerp <= 0. {
+                        desired
+                    } else {
+                        self.position + (desired - self.position) * (lerp * delta_time).min(1.)
+                    };
+                }
+            }
+        }
+        self.clamp();
+    }
+}
@@ -0,0 +1,81 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::Result;
+use macroquad::texture::FilterMode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    migrate::{self, Versioned},
+    world::{GAME_HEIGHT, GAME_WIDTH},
+};
+
+pub const DEFAULT_SETTINGS_FILE: &str = "settings.yaml";
+
+/// How the game screen is fit into the window
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AspectMode {
+    /// As large as fits, bars wherever they are needed
+    #[default]
+    Fit,
+    /// Fill the width, bars above and below on a tall window. On a wide window the top and bottom
+    /// are cropped.
+    Letterbox,
+    /// Fill the height, bars left and right on a wide window. On a tall window the sides are
+    /// cropped.
+    Pillarbox,
+    /// Fill the window, ignoring the aspect ratio and integer scaling
+    Stretch,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Filter {
+    /// Crisp pixels
+    #[default]
+    Nearest,
+    Linear,
+}
+
+impl From<Filter> for FilterMode {
+    fn from(value: Filter) -> Self {
+        match value {
+            Filter::Nearest => FilterMode::Nearest,
+            Filter::Linear => FilterMode::Linear,
+        }
+    }
+}
+
+/// Resolution and scaling of the game screen, see `RetroCamera::apply_settings`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    pub width: u32,
+    pub height: u32,
+    pub aspect: AspectMode,
+    pub allow_non_int_scaling: bool,
+    pub filter: Filter,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            width: GAME_WIDTH as u32,
+            height: GAME_HEIGHT as u32,
+            aspect: AspectMode::Fit,
+            allow_non_int_scaling: false,
+            filter: Filter::Nearest,
+        }
+    }
+}
+
+impl Versioned for DisplaySettings {}
+
+impl DisplaySettings {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        migrate::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    pub fn size(&self) -> (f32, f32) {
+        (self.width.max(1) as f32, self.height.max(1) as f32)
+    }
+}
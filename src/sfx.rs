@@ -0,0 +1,668 @@
+//! Retro sound effects synthesized from a handful of parameters, in the spirit of sfxr.
+//!
+//! Rendering is plain math on `f32` samples, it does not need a window or an audio device. Only
+//! `Sfx::play` does.
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, HashMap},
+    f32::consts::PI,
+    fs,
+    hash::{Hash, Hasher},
+    io::Cursor,
+    path::Path,
+};
+
+use ::rand::{rngs::StdRng, Rng, SeedableRng};
+use anyhow::{Context, Result};
+use egui_macroquad::egui::{self, ComboBox, Grid, Slider, Ui};
+use macroquad::{
+    audio::{load_sound_from_bytes, play_sound, stop_sound, PlaySoundParams, Sound},
+    experimental::coroutines::start_coroutine,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buildable::Buildable, drawable::Drawable, migrate::Versioned, updateable::Updateable, CenterPt,
+};
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sawtooth,
+    Sine,
+    Triangle,
+    /// A new random value every period, so the frequency still matters
+    Noise,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 5] = [
+        Waveform::Square,
+        Waveform::Sawtooth,
+        Waveform::Sine,
+        Waveform::Triangle,
+        Waveform::Noise,
+    ];
+}
+
+/// Everything that makes up a sound effect. Times are in seconds, frequencies in Hz.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SfxParams {
+    /// Used as the file name when exporting
+    pub name: String,
+    pub waveform: Waveform,
+    pub attack: f32,
+    pub sustain: f32,
+    /// Extra volume at the start of the sustain, fading out over it
+    pub sustain_punch: f32,
+    pub decay: f32,
+    pub base_freq: f32,
+    /// The sound stops when the frequency slides below this, 0 never stops
+    pub min_freq: f32,
+    /// Octaves per second
+    pub freq_slide: f32,
+    /// Change of `freq_slide` per second
+    pub freq_delta_slide: f32,
+    /// Fraction of the frequency
+    pub vibrato_depth: f32,
+    pub vibrato_speed: f32,
+    /// How much of the period a square wave is high
+    pub duty: f32,
+    /// Change of `duty` per second
+    pub duty_sweep: f32,
+    /// White noise mixed into the waveform, 0 to 1
+    pub noise: f32,
+    pub volume: f32,
+    /// The noise is the same every time for the same seed
+    pub seed: u64,
+}
+
+impl Default for SfxParams {
+    fn default() -> Self {
+        Self {
+            name: String::from("sfx"),
+            waveform: Waveform::Square,
+            attack: 0.,
+            sustain: 0.1,
+            sustain_punch: 0.,
+            decay: 0.2,
+            base_freq: 440.,
+            min_freq: 0.,
+            freq_slide: 0.,
+            freq_delta_slide: 0.,
+            vibrato_depth: 0.,
+            vibrato_speed: 0.,
+            duty: 0.5,
+            duty_sweep: 0.,
+            noise: 0.,
+            volume: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+impl Versioned for SfxParams {}
+
+impl SfxParams {
+    /// Length in seconds, the sound may end earlier because of `min_freq`
+    pub fn duration(&self) -> f32 {
+        self.attack.max(0.) + self.sustain.max(0.) + self.decay.max(0.)
+    }
+
+    fn envelope(&self, t: f32) -> f32 {
+        if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.sustain {
+            1. + self.sustain_punch * (1. - (t - self.attack) / self.sustain)
+        } else if self.decay > 0. {
+            (1. - (t - self.attack - self.sustain) / self.decay).max(0.)
+        } else {
+            0.
+        }
+    }
+
+    /// Mono samples from -1 to 1 at `SAMPLE_RATE`. The same parameters always give the same
+    /// samples.
+    pub fn render(&self) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let count = (self.duration() * SAMPLE_RATE as f32) as usize;
+        let dt = 1. / SAMPLE_RATE as f32;
+        let mut samples = Vec::with_capacity(count);
+        let mut phase = 0_f32;
+        let mut held_noise = rng.gen_range(-1_f32..1.);
+        for i in 0..count {
+            let t = i as f32 * dt;
+            let octaves = self.freq_slide * t + 0.5 * self.freq_delta_slide * t * t;
+            let mut freq = self.base_freq * octaves.exp2();
+            if self.min_freq > 0. && freq < self.min_freq {
+                break;
+            }
+            if self.vibrato_depth > 0. {
+                freq *= 1. + self.vibrato_depth * (2. * PI * self.vibrato_speed * t).sin();
+            }
+            phase += freq.max(0.) * dt;
+            if phase >= 1. {
+                phase %= 1.;
+                held_noise = rng.gen_range(-1_f32..1.);
+            }
+            let duty = (self.duty + self.duty_sweep * t).clamp(0.05, 0.95);
+            let wave = match self.waveform {
+                Waveform::Square => {
+                    if phase < duty {
+                        1.
+                    } else {
+                        -1.
+                    }
+                }
+                Waveform::Sawtooth => 1. - 2. * phase,
+                Waveform::Sine => (2. * PI * phase).sin(),
+                Waveform::Triangle => 4. * (phase - 0.5).abs() - 1.,
+                Waveform::Noise => held_noise,
+            };
+            let noise = self.noise.clamp(0., 1.);
+            let out = if noise > 0. {
+                wave * (1. - noise) + rng.gen_range(-1_f32..1.) * noise
+            } else {
+                wave
+            };
+            samples.push((out * self.envelope(t) * self.volume).clamp(-1., 1.));
+        }
+        samples
+    }
+
+    pub fn laser(rng: &mut impl Rng) -> Self {
+        Self {
+            name: String::from("laser"),
+            waveform: [Waveform::Square, Waveform::Sawtooth, Waveform::Sine][rng.gen_range(0..3)],
+            sustain: rng.gen_range(0.02..0.1),
+            decay: rng.gen_range(0.05..0.2),
+            base_freq: rng.gen_range(500. ..1500.),
+            min_freq: rng.gen_range(50. ..200.),
+            freq_slide: rng.gen_range(-10. ..-3.),
+            duty: rng.gen_range(0.2..0.5),
+            duty_sweep: rng.gen_range(-1. ..1.),
+            seed: rng.gen(),
+            ..Default::default()
+        }
+    }
+
+    pub fn explosion(rng: &mut impl Rng) -> Self {
+        Self {
+            name: String::from("explosion"),
+            waveform: Waveform::Noise,
+            sustain: rng.gen_range(0.05..0.25),
+            sustain_punch: rng.gen_range(0.2..0.7),
+            decay: rng.gen_range(0.3..0.8),
+            base_freq: rng.gen_range(200. ..1200.),
+            freq_slide: rng.gen_range(-3. ..0.),
+            vibrato_depth: if rng.gen_bool(0.5) {
+                rng.gen_range(0. ..0.3)
+            } else {
+                0.
+            },
+            vibrato_speed: rng.gen_range(5. ..20.),
+            seed: rng.gen(),
+            ..Default::default()
+        }
+    }
+
+    pub fn pickup(rng: &mut impl Rng) -> Self {
+        Self {
+            name: String::from("pickup"),
+            waveform: [Waveform::Square, Waveform::Sine][rng.gen_range(0..2)],
+            sustain: rng.gen_range(0.02..0.08),
+            sustain_punch: rng.gen_range(0.3..0.6),
+            decay: rng.gen_range(0.1..0.3),
+            base_freq: rng.gen_range(600. ..1400.),
+            freq_slide: if rng.gen_bool(0.5) {
+                rng.gen_range(0.5..3.)
+            } else {
+                0.
+            },
+            duty: rng.gen_range(0.3..0.6),
+            seed: rng.gen(),
+            ..Default::default()
+        }
+    }
+
+    pub fn hit(rng: &mut impl Rng) -> Self {
+        Self {
+            name: String::from("hit"),
+            waveform: [Waveform::Square, Waveform::Sawtooth, Waveform::Noise][rng.gen_range(0..3)],
+            sustain: rng.gen_range(0.01..0.05),
+            decay: rng.gen_range(0.05..0.2),
+            base_freq: rng.gen_range(150. ..600.),
+            freq_slide: rng.gen_range(-8. ..-2.),
+            duty: rng.gen_range(0.2..0.6),
+            noise: rng.gen_range(0. ..0.3),
+            seed: rng.gen(),
+            ..Default::default()
+        }
+    }
+
+    /// Anything goes, usually awful but sometimes great
+    pub fn random(rng: &mut impl Rng) -> Self {
+        Self {
+            name: String::from("random"),
+            waveform: Waveform::ALL[rng.gen_range(0..Waveform::ALL.len())],
+            attack: rng.gen_range(0. ..0.1_f32).powi(2) * 10.,
+            sustain: rng.gen_range(0.01..0.3),
+            sustain_punch: rng.gen_range(0. ..0.8),
+            decay: rng.gen_range(0.05..0.6),
+            base_freq: rng.gen_range(50. ..2000.),
+            freq_slide: rng.gen_range(-6. ..6.),
+            freq_delta_slide: rng.gen_range(-4. ..4.),
+            vibrato_depth: rng.gen_range(0. ..0.2),
+            vibrato_speed: rng.gen_range(0. ..30.),
+            duty: rng.gen_range(0.1..0.9),
+            duty_sweep: rng.gen_range(-1. ..1.),
+            noise: rng.gen_range(0. ..0.2),
+            seed: rng.gen(),
+            ..Default::default()
+        }
+    }
+
+    /// Nudge every parameter a little, `amount` from 0 to 1
+    pub fn mutate(&mut self, rng: &mut impl Rng, amount: f32) {
+        let mut nudge = |v: &mut f32, range: f32, min: f32, max: f32| {
+            *v = (*v + rng.gen_range(-1. ..1.) * range * amount).clamp(min, max);
+        };
+        nudge(&mut self.attack, 0.05, 0., 2.);
+        nudge(&mut self.sustain, 0.05, 0., 2.);
+        nudge(&mut self.sustain_punch, 0.2, 0., 2.);
+        nudge(&mut self.decay, 0.1, 0., 2.);
+        let freq_range = self.base_freq * 0.2;
+        nudge(&mut self.base_freq, freq_range, 20., 4000.);
+        nudge(&mut self.freq_slide, 1., -20., 20.);
+        nudge(&mut self.freq_delta_slide, 1., -20., 20.);
+        nudge(&mut self.vibrato_depth, 0.05, 0., 1.);
+        nudge(&mut self.vibrato_speed, 5., 0., 50.);
+        nudge(&mut self.duty, 0.1, 0., 1.);
+        nudge(&mut self.duty_sweep, 0.2, -2., 2.);
+        nudge(&mut self.noise, 0.05, 0., 1.);
+        self.seed = rng.gen();
+    }
+
+    /// The same for parameters that render the same samples
+    pub fn key(&self) -> SoundKey {
+        let floats = [
+            self.attack,
+            self.sustain,
+            self.sustain_punch,
+            self.decay,
+            self.base_freq,
+            self.min_freq,
+            self.freq_slide,
+            self.freq_delta_slide,
+            self.vibrato_depth,
+            self.vibrato_speed,
+            self.duty,
+            self.duty_sweep,
+            self.noise,
+            self.volume,
+        ];
+        let bits = floats.map(f32::to_bits);
+        sound_key(&(&self.name, self.waveform, bits, self.seed))
+    }
+}
+
+/// 16 bit mono WAV
+pub fn wav_bytes(samples: &[f32]) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut bytes, spec)?;
+    for s in samples {
+        writer.write_sample((s.clamp(-1., 1.) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(bytes.into_inner())
+}
+
+pub fn save_wav(samples: &[f32], path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    fs::write(path, wav_bytes(samples)?)
+        .with_context(|| format!("Could not save {}", path.display()))
+}
+
+/// Identifies a loaded sound, see `play_cached`
+pub type SoundKey = u64;
+
+thread_local! {
+    /// Sounds handed to macroquad, `None` while loading
+    static SOUNDS: RefCell<HashMap<SoundKey, Option<Sound>>> = RefCell::new(HashMap::new());
+    /// What `play_preview` played last
+    static PREVIEW: Cell<Option<SoundKey>> = const { Cell::new(None) };
+}
+
+/// A key for anything that identifies a sound, e.g. its name and parameters
+pub fn sound_key(value: &impl Hash) -> SoundKey {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Play the sound loaded for `key`. Only the first time is `bytes` called, for WAV (or ogg)
+/// bytes to load, and the sound may start a frame late.
+pub fn play_cached(key: SoundKey, volume: f32, bytes: impl FnOnce() -> Result<Vec<u8>>) {
+    let params = PlaySoundParams {
+        looped: false,
+        volume,
+    };
+    match SOUNDS.with(|s| s.borrow().get(&key).copied()) {
+        Some(Some(sound)) => return play_sound(sound, params),
+        // Plays once it is loaded
+        Some(None) => return,
+        None => {}
+    }
+    let bytes = match bytes() {
+        Err(err) => return warn!("Unable to play sound: {:#?}", err),
+        Ok(bytes) => bytes,
+    };
+    SOUNDS.with(|s| s.borrow_mut().insert(key, None));
+    start_coroutine(async move {
+        match load_sound_from_bytes(&bytes).await {
+            Err(err) => warn!("Unable to load sound: {:#?}", err),
+            Ok(sound) => {
+                let wanted = SOUNDS.with(|s| match s.borrow_mut().get_mut(&key) {
+                    Some(slot) => slot.replace(sound).is_none(),
+                    None => false,
+                });
+                // Unless it was evicted while loading
+                if wanted {
+                    play_sound(sound, params);
+                }
+            }
+        }
+    });
+}
+
+/// Stop every playing copy of the sound
+pub fn stop_key(key: SoundKey) {
+    if let Some(Some(sound)) = SOUNDS.with(|s| s.borrow().get(&key).copied()) {
+        stop_sound(sound);
+    }
+}
+
+/// Stop the sound and forget it, when what it was made from changed. Macroquad 0.3 can't free
+/// a loaded sound, so only sounds that are still wanted stay loaded.
+pub fn evict(key: SoundKey) {
+    stop_key(key);
+    SOUNDS.with(|s| s.borrow_mut().remove(&key));
+}
+
+/// Play a sound being edited, evicting the one played before if it changed
+pub fn play_preview(key: SoundKey, bytes: impl FnOnce() -> Result<Vec<u8>>) {
+    if let Some(old) = PREVIEW.with(|p| p.replace(Some(key))) {
+        if old != key {
+            evict(old);
+        }
+    }
+    play_cached(key, 1., bytes);
+}
+
+/// A rendered sound effect. Drawing it shows the waveform, with a cursor while it plays.
+#[derive(Debug, Clone)]
+pub struct Sfx {
+    key: SoundKey,
+    samples: Vec<f32>,
+    center: CenterPt,
+    time: f32,
+    played: bool,
+}
+
+impl Sfx {
+    pub fn new(params: &SfxParams) -> Self {
+        Self {
+            key: params.key(),
+            samples: params.render(),
+            center: CenterPt::default(),
+            time: 0.,
+            played: false,
+        }
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32 / SAMPLE_RATE as f32
+    }
+
+    pub fn play(&self) {
+        play_preview(self.key, || wav_bytes(&self.samples));
+    }
+}
+
+impl Updateable for Sfx {
+    fn update(&mut self, delta_time: f32) {
+        if !self.played {
+            self.played = true;
+            self.play();
+        }
+        self.time += delta_time;
+    }
+}
+
+impl Drawable for Sfx {
+    /// The waveform, as wide as twice the distance to the left of `center`
+    fn draw(&self) {
+        let (cx, cy) = self.center.into();
+        let width = (cx * 2.).max(1.);
+        let height = cy * 0.8;
+        let step = (self.samples.len() as f32 / width).max(1.);
+        let mut x = 0.;
+        while x < width {
+            let from = (x * step) as usize;
+            let to = ((x + 1.) * step) as usize;
+            if from >= self.samples.len() {
+                break;
+            }
+            let (lo, hi) = self.samples[from..to.min(self.samples.len())]
+                .iter()
+                .fold((0_f32, 0_f32), |(lo, hi), s| (lo.min(*s), hi.max(*s)));
+            draw_line(x, cy - hi * height, x, cy - lo * height + 1., 1., GREEN);
+            x += 1.;
+        }
+        let duration = self.duration();
+        if duration > 0. && self.time < duration {
+            let x = (self.time / duration * width).floor();
+            draw_line(x, 0., x, cy * 2., 1., YELLOW);
+        }
+    }
+}
+
+impl Buildable for SfxParams {
+    type Byproduct = Sfx;
+
+    fn build(self, center: CenterPt) -> Option<Self::Byproduct> {
+        let mut sfx = Sfx::new(&self);
+        sfx.center = center;
+        Some(sfx)
+    }
+
+    /// A pause between repeats
+    fn max_loop_time(&self) -> f32 {
+        self.duration() + 0.75
+    }
+
+    fn draw_gizmos_at(&self, _center: CenterPt) {}
+
+    fn editor_ui(&mut self, ui: &mut Ui) {
+        // The editor seeds macroquad's generator every frame, that would make every click the same
+        let mut rng = ::rand::thread_rng();
+        ui.horizontal_wrapped(|ui| {
+            if ui.button("Laser").clicked() {
+                *self = Self::laser(&mut rng);
+            }
+            if ui.button("Explosion").clicked() {
+                *self = Self::explosion(&mut rng);
+            }
+            if ui.button("Pickup").clicked() {
+                *self = Self::pickup(&mut rng);
+            }
+            if ui.button("Hit").clicked() {
+                *self = Self::hit(&mut rng);
+            }
+            if ui.button("Random").clicked() {
+                *self = Self::random(&mut rng);
+            }
+            if ui.button("Mutate").clicked() {
+                self.mutate(&mut rng, 0.3);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.name);
+            if ui.button("Export WAV").clicked() {
+                let path = format!("{}.wav", self.name);
+                match save_wav(&self.render(), &path) {
+                    Err(err) => error!("Can't export: {:?}", err),
+                    Ok(()) => info!("Saved {}", path),
+                }
+            }
+        });
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            Grid::new("sfx_params").show(ui, |ui| {
+                ui.label("Waveform");
+                ComboBox::from_id_source("sfx_waveform")
+                    .selected_text(format!("{:?}", self.waveform))
+                    .show_ui(ui, |ui| {
+                        for w in Waveform::ALL {
+                            ui.selectable_value(&mut self.waveform, w, format!("{:?}", w));
+                        }
+                    });
+                ui.end_row();
+
+                let row = |ui: &mut Ui, label: &str, v: &mut f32, range| {
+                    ui.label(label);
+                    ui.add(Slider::new(v, range));
+                    ui.end_row();
+                };
+                row(ui, "Attack", &mut self.attack, 0. ..=2.);
+                row(ui, "Sustain", &mut self.sustain, 0. ..=2.);
+                row(ui, "Punch", &mut self.sustain_punch, 0. ..=2.);
+                row(ui, "Decay", &mut self.decay, 0. ..=2.);
+                row(ui, "Frequency", &mut self.base_freq, 20. ..=4000.);
+                row(ui, "Min frequency", &mut self.min_freq, 0. ..=2000.);
+                row(ui, "Slide", &mut self.freq_slide, -20. ..=20.);
+                row(ui, "Delta slide", &mut self.freq_delta_slide, -20. ..=20.);
+                row(ui, "Vibrato depth", &mut self.vibrato_depth, 0. ..=1.);
+                row(ui, "Vibrato speed", &mut self.vibrato_speed, 0. ..=50.);
+                row(ui, "Duty", &mut self.duty, 0. ..=1.);
+                row(ui, "Duty sweep", &mut self.duty_sweep, -2. ..=2.);
+                row(ui, "Noise", &mut self.noise, 0. ..=1.);
+                row(ui, "Volume", &mut self.volume, 0. ..=1.);
+            });
+        });
+    }
+
+    fn get_base_id() -> &'static str {
+        "sfx"
+    }
+
+    fn get_default_file_name() -> &'static str {
+        "sfx.yaml"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noisy(seed: u64) -> SfxParams {
+        SfxParams {
+            noise: 0.5,
+            seed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_seed_same_samples() {
+        assert_eq!(noisy(7).render(), noisy(7).render());
+        assert_ne!(noisy(7).render(), noisy(8).render());
+    }
+
+    #[test]
+    fn length_is_attack_sustain_decay() {
+        let params = SfxParams {
+            attack: 0.05,
+            sustain: 0.1,
+            decay: 0.25,
+            ..Default::default()
+        };
+        assert_eq!(params.render().len(), (0.4 * SAMPLE_RATE as f32) as usize);
+        let negative = SfxParams {
+            attack: -1.,
+            ..params.clone()
+        };
+        assert_eq!(
+            negative.render().len(),
+            (0.35 * SAMPLE_RATE as f32) as usize
+        );
+    }
+
+    #[test]
+    fn min_freq_ends_early() {
+        let params = SfxParams {
+            freq_slide: -10.,
+            min_freq: 220.,
+            ..Default::default()
+        };
+        // One octave down after a tenth of a second
+        let len = params.render().len();
+        assert!(len.abs_diff(SAMPLE_RATE as usize / 10) <= 1, "{}", len);
+    }
+
+    #[test]
+    fn samples_stay_in_range() {
+        let params = SfxParams {
+            sustain_punch: 2.,
+            volume: 1.,
+            noise: 1.,
+            ..Default::default()
+        };
+        assert!(params.render().iter().all(|s| (-1. ..=1.).contains(s)));
+    }
+
+    #[test]
+    fn keys_follow_the_parameters() {
+        assert_eq!(noisy(7).key(), noisy(7).key());
+        assert_ne!(noisy(7).key(), noisy(8).key());
+        let louder = SfxParams {
+            volume: 0.6,
+            ..noisy(7)
+        };
+        assert_ne!(louder.key(), noisy(7).key());
+    }
+
+    #[test]
+    fn wav_header() {
+        let samples = [0., 0.5, -0.5, 1.];
+        let bytes = wav_bytes(&samples).unwrap();
+        let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        // PCM, mono, 16 bit
+        assert_eq!(u16_at(20), 1);
+        assert_eq!(u16_at(22), 1);
+        assert_eq!(u32_at(24), SAMPLE_RATE);
+        assert_eq!(u16_at(34), 16);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(40), 2 * samples.len() as u32);
+        assert_eq!(u16_at(46) as i16, i16::MAX / 2);
+        assert_eq!(u16_at(50) as i16, i16::MAX);
+    }
+}
@@ -5,6 +5,8 @@ pub enum State {
     Step,
     Playing,
     Paused,
+    /// Out of lives, counting down while a continue can be used
+    GameOver,
     Exit,
 }
 
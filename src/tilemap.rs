@@ -0,0 +1,349 @@
+mod tiled;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use macroquad::prelude::*;
+
+use crate::{drawable::Drawable, updateable::Updateable};
+
+/// Tiled stores flips in the top bits of a tile id
+const FLIP_X: u32 = 0x8000_0000;
+const FLIP_Y: u32 = 0x4000_0000;
+const FLIP_DIAGONAL: u32 = 0x2000_0000;
+const GID_MASK: u32 = !(FLIP_X | FLIP_Y | FLIP_DIAGONAL);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileAnimationFrame {
+    /// Tile in the same tileset
+    pub tile: u32,
+    /// Seconds
+    pub duration: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tileset {
+    texture: Texture2D,
+    /// Global id of the first tile, tile layers refer to tiles of all tilesets by global id
+    pub first_gid: u32,
+    pub tile_width: f32,
+    pub tile_height: f32,
+    pub columns: u32,
+    pub tile_count: u32,
+    pub margin: f32,
+    pub spacing: f32,
+    pub animations: BTreeMap<u32, Vec<TileAnimationFrame>>,
+}
+
+impl Tileset {
+    fn contains(&self, gid: u32) -> bool {
+        gid >= self.first_gid && gid < self.first_gid + self.tile_count
+    }
+
+    /// The tile to show at `time`, for animated tiles
+    fn animated(&self, tile: u32, time: f32) -> u32 {
+        let Some(frames) = self.animations.get(&tile) else {
+            return tile;
+        };
+        let total: f32 = frames.iter().map(|f| f.duration).sum();
+        if total <= 0. {
+            return tile;
+        }
+        let mut t = time % total;
+        for frame in frames {
+            if t < frame.duration {
+                return frame.tile;
+            }
+            t -= frame.duration;
+        }
+        tile
+    }
+
+    fn source(&self, tile: u32) -> Rect {
+        let columns = self.columns.max(1);
+        let (col, row) = ((tile % columns) as f32, (tile / columns) as f32);
+        Rect::new(
+            self.margin + col * (self.tile_width + self.spacing),
+            self.margin + row * (self.tile_height + self.spacing),
+            self.tile_width,
+            self.tile_height,
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub name: String,
+    /// In tiles
+    pub width: u32,
+    pub height: u32,
+    /// Global tile ids row by row, 0 is empty
+    pub tiles: Vec<u32>,
+    pub visible: bool,
+    pub opacity: f32,
+    pub offset: Vec2,
+}
+
+impl TileLayer {
+    pub fn tile(&self, x: u32, y: u32) -> Option<u32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.tiles
+            .get((y * self.width + x) as usize)
+            .map(|gid| gid & GID_MASK)
+            .filter(|gid| *gid != 0)
+    }
+}
+
+/// Something placed in an object layer, like an enemy spawn point or a trigger for the stage
+/// timeline. Which one is up to its type (called class in newer versions of Tiled).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapObject {
+    pub id: u32,
+    pub name: String,
+    pub kind: String,
+    /// World position, including the position of the map
+    pub rect: Rect,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl MapObject {
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(|s| s.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<MapObject>,
+}
+
+/// A map made of tiles, usually made in Tiled. Only the tiles inside the view are drawn.
+#[derive(Debug, Clone)]
+pub struct Tilemap {
+    /// In tiles
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: f32,
+    pub tile_height: f32,
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<TileLayer>,
+    pub object_layers: Vec<ObjectLayer>,
+    /// World position of the top left corner
+    position: Vec2,
+    time: f32,
+    /// Ids of the objects that already came on screen
+    entered: BTreeSet<u32>,
+}
+
+impl Tilemap {
+    /// Load a map saved by Tiled as JSON (`.tmj` or `.json`)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("tmj" | "json") => tiled::load(path),
+            _ => bail!(
+                "{} is not a Tiled JSON map, save it as .tmj in Tiled",
+                path.display()
+            ),
+        }
+    }
+
+    pub fn with_position(mut self, position: Vec2) -> Self {
+        let delta = position - self.position;
+        self.position = position;
+        for object in self.objects_mut() {
+            object.rect = object.rect.offset(delta);
+        }
+        self
+    }
+
+    pub fn position(&self) -> Vec2 {
+        self.position
+    }
+
+    /// Size in pixels
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(
+            self.width as f32 * self.tile_width,
+            self.height as f32 * self.tile_height,
+        )
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &MapObject> {
+        self.object_layers.iter().flat_map(|l| l.objects.iter())
+    }
+
+    fn objects_mut(&mut self) -> impl Iterator<Item = &mut MapObject> {
+        self.object_layers
+            .iter_mut()
+            .flat_map(|l| l.objects.iter_mut())
+    }
+
+    /// All objects of one type, like "enemy", "boss" or "trigger"
+    pub fn objects_of<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a MapObject> {
+        self.objects().filter(move |o| o.kind == kind)
+    }
+
+    /// Objects that are in `view` for the first time, i.e. that just scrolled on screen. Each
+    /// object is only returned once, even if it scrolls off and back on again.
+    pub fn entered_view(&mut self, view: Rect) -> Vec<MapObject> {
+        let entered = self
+            .objects()
+            .filter(|o| overlaps(&o.rect, &view) && !self.entered.contains(&o.id))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.entered.extend(entered.iter().map(|o| o.id));
+        entered
+    }
+
+    fn tileset(&self, gid: u32) -> Option<&Tileset> {
+        self.tilesets.iter().rev().find(|t| t.contains(gid))
+    }
+
+    /// Draw the tiles that are inside `view`
+    pub fn draw_view(&self, view: Rect) {
+        for layer in self.layers.iter().filter(|l| l.visible) {
+            let origin = self.position + layer.offset;
+            let first_x = ((view.x - origin.x) / self.tile_width).floor().max(0.) as u32;
+            let first_y = ((view.y - origin.y) / self.tile_height).floor().max(0.) as u32;
+            let last_x = ((view.right() - origin.x) / self.tile_width).ceil().max(0.) as u32;
+            let last_y = ((view.bottom() - origin.y) / self.tile_height)
+                .ceil()
+                .max(0.) as u32;
+            let color = Color::new(1., 1., 1., layer.opacity);
+            for y in first_y..last_y.min(layer.height) {
+                for x in first_x..last_x.min(layer.width) {
+                    let raw = layer.tiles[(y * layer.width + x) as usize];
+                    let gid = raw & GID_MASK;
+                    let Some(tileset) = self.tileset(gid) else {
+                        continue;
+                    };
+                    let tile = tileset.animated(gid - tileset.first_gid, self.time);
+                    let px = origin.x + x as f32 * self.tile_width;
+                    // Tiles taller than the grid stick out at the top, like in Tiled
+                    let py = origin.y + (y + 1) as f32 * self.tile_height - tileset.tile_height;
+                    let (flip_x, flip_y, rotation) = flips(raw);
+                    draw_texture_ex(
+                        tileset.texture,
+                        px.round(),
+                        py.round(),
+                        color,
+                        DrawTextureParams {
+                            source: Some(tileset.source(tile)),
+                            flip_x,
+                            flip_y,
+                            rotation,
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Flips and rotation for `DrawTextureParams`. Tiled flips diagonally (swaps x and y) before
+/// flipping horizontally and vertically, that is the same as turning a quarter clockwise with
+/// the flips swapped.
+fn flips(raw: u32) -> (bool, bool, f32) {
+    let (x, y) = (raw & FLIP_X != 0, raw & FLIP_Y != 0);
+    if raw & FLIP_DIAGONAL != 0 {
+        (y, !x, std::f32::consts::FRAC_PI_2)
+    } else {
+        (x, y, 0.)
+    }
+}
+
+/// Like `Rect::overlaps`, but points (objects without a size) count too
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.x <= b.right() && a.right() >= b.x && a.y <= b.bottom() && a.bottom() >= b.y
+}
+
+impl Updateable for Tilemap {
+    fn update(&mut self, delta_time: f32) {
+        self.time += delta_time;
+    }
+}
+
+impl Drawable for Tilemap {
+    /// Draws the whole map, `draw_view` only draws what is on screen
+    fn draw(&self) {
+        let size = self.size();
+        self.draw_view(Rect::new(self.position.x, self.position.y, size.x, size.y));
+    }
+
+    fn draw_gizmos(&self) {
+        for object in self.objects() {
+            let color = match object.kind.as_str() {
+                "enemy" => GREEN,
+                "boss" => RED,
+                "trigger" => YELLOW,
+                _ => WHITE,
+            };
+            let r = object.rect;
+            if r.w == 0. && r.h == 0. {
+                draw_circle_lines(r.x, r.y, 2., 1., color);
+            } else {
+                draw_rectangle_lines(r.x, r.y, r.w, r.h, 1., color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_with_object(rect: Rect) -> Tilemap {
+        Tilemap {
+            width: 10,
+            height: 10,
+            tile_width: 16.,
+            tile_height: 16.,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            object_layers: vec![ObjectLayer {
+                name: "objects".to_string(),
+                objects: vec![MapObject {
+                    id: 1,
+                    name: String::new(),
+                    kind: "enemy".to_string(),
+                    rect,
+                    properties: BTreeMap::new(),
+                }],
+            }],
+            position: Vec2::ZERO,
+            time: 0.,
+            entered: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn objects_enter_the_view_once() {
+        let mut map = map_with_object(Rect::new(50., 50., 0., 0.));
+        let off_screen = Rect::new(100., 100., 20., 20.);
+        let on_screen = Rect::new(40., 40., 20., 20.);
+        assert!(map.entered_view(off_screen).is_empty());
+        assert_eq!(map.entered_view(on_screen).len(), 1);
+        assert!(map.entered_view(on_screen).is_empty());
+        assert!(map.entered_view(off_screen).is_empty());
+        assert!(map.entered_view(on_screen).is_empty());
+    }
+
+    #[test]
+    fn diagonal_flips_turn_a_quarter() {
+        assert_eq!(flips(1), (false, false, 0.));
+        assert_eq!(flips(FLIP_X | FLIP_Y | 1), (true, true, 0.));
+        let quarter = std::f32::consts::FRAC_PI_2;
+        assert_eq!(flips(FLIP_DIAGONAL | 1), (false, true, quarter));
+        // Tiled's "rotate 90° clockwise" is diagonal plus horizontal
+        assert_eq!(flips(FLIP_DIAGONAL | FLIP_X | 1), (false, false, quarter));
+        assert_eq!(flips(FLIP_DIAGONAL | FLIP_Y | 1), (true, true, quarter));
+    }
+}
@@ -0,0 +1,338 @@
+//! Import maps saved by Tiled in its JSON format (`.tmj`), with embedded or external (`.tsj`)
+//! tilesets. Tile layers have to use the default CSV layer format, not base64.
+//!
+//! Tile, object and group layers are understood, image layers are skipped.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use macroquad::prelude::{Rect, Vec2};
+use serde::Deserialize;
+
+use super::{MapObject, ObjectLayer, TileAnimationFrame, TileLayer, Tilemap, Tileset};
+use crate::drawable::load_png;
+
+#[derive(Debug, Clone, Deserialize)]
+struct TiledMap {
+    width: u32,
+    height: u32,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    layers: Vec<TiledLayer>,
+    #[serde(default)]
+    tilesets: Vec<TiledTilesetRef>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TiledLayer {
+    TileLayer {
+        name: String,
+        width: u32,
+        height: u32,
+        data: serde_json::Value,
+        #[serde(default = "default_true")]
+        visible: bool,
+        #[serde(default = "default_opacity")]
+        opacity: f32,
+        #[serde(default)]
+        offsetx: f32,
+        #[serde(default)]
+        offsety: f32,
+    },
+    ObjectGroup {
+        name: String,
+        #[serde(default)]
+        objects: Vec<TiledObject>,
+        #[serde(default)]
+        offsetx: f32,
+        #[serde(default)]
+        offsety: f32,
+    },
+    Group {
+        #[serde(default)]
+        layers: Vec<TiledLayer>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TiledProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TiledObject {
+    id: u32,
+    #[serde(default)]
+    name: String,
+    /// Tiled 1.9 renamed type to class
+    #[serde(default, alias = "class")]
+    r#type: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    /// Set on tile objects, those are placed by their bottom left corner
+    #[serde(default)]
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+impl TiledObject {
+    fn rect(&self, offset: Vec2) -> Rect {
+        let y = match self.gid {
+            Some(_) => self.y - self.height,
+            None => self.y,
+        };
+        Rect::new(self.x + offset.x, y + offset.y, self.width, self.height)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TiledFrame {
+    tileid: u32,
+    /// milliseconds
+    duration: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TiledTile {
+    id: u32,
+    #[serde(default)]
+    animation: Vec<TiledFrame>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TiledTileset {
+    image: String,
+    tilewidth: f32,
+    tileheight: f32,
+    columns: u32,
+    tilecount: u32,
+    #[serde(default)]
+    margin: f32,
+    #[serde(default)]
+    spacing: f32,
+    #[serde(default)]
+    tiles: Vec<TiledTile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum TiledTilesetRef {
+    External {
+        firstgid: u32,
+        source: String,
+    },
+    Embedded {
+        firstgid: u32,
+        #[serde(flatten)]
+        tileset: TiledTileset,
+    },
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    let data = fs::read(path).with_context(|| format!("Could not open {}", path.display()))?;
+    serde_json::from_slice::<T>(&data)
+        .with_context(|| format!("could not parse {}", path.display()))
+}
+
+pub(super) fn load(path: &Path) -> Result<Tilemap> {
+    let map = read_json::<TiledMap>(path)?;
+    if map.infinite {
+        bail!(
+            "{} is an infinite map, those are not supported",
+            path.display()
+        );
+    }
+    let tilesets = map
+        .tilesets
+        .iter()
+        .map(|t| load_tileset(path, t))
+        .collect::<Result<Vec<_>>>()?;
+    let mut tilemap = Tilemap {
+        width: map.width,
+        height: map.height,
+        tile_width: map.tilewidth,
+        tile_height: map.tileheight,
+        tilesets,
+        layers: Vec::new(),
+        object_layers: Vec::new(),
+        position: Vec2::ZERO,
+        time: 0.,
+        entered: Default::default(),
+    };
+    add_layers(&mut tilemap, map.layers)
+        .with_context(|| format!("could not parse {}", path.display()))?;
+    Ok(tilemap)
+}
+
+/// Image paths in tilesets are relative to the file they are in
+fn load_tileset(map_path: &Path, tileset: &TiledTilesetRef) -> Result<Tileset> {
+    let (first_gid, tileset, base) = match tileset {
+        TiledTilesetRef::Embedded { firstgid, tileset } => {
+            (*firstgid, tileset.clone(), map_path.to_path_buf())
+        }
+        TiledTilesetRef::External { firstgid, source } => {
+            let tileset_path = map_path.with_file_name(source);
+            (
+                *firstgid,
+                read_json::<TiledTileset>(&tileset_path)?,
+                tileset_path,
+            )
+        }
+    };
+    let texture = load_png(&base.with_file_name(&tileset.image))?;
+    let animations = tileset
+        .tiles
+        .iter()
+        .filter(|t| !t.animation.is_empty())
+        .map(|t| {
+            let frames = t
+                .animation
+                .iter()
+                .map(|f| TileAnimationFrame {
+                    tile: f.tileid,
+                    duration: f.duration / 1000.,
+                })
+                .collect();
+            (t.id, frames)
+        })
+        .collect();
+    Ok(Tileset {
+        texture,
+        first_gid,
+        tile_width: tileset.tilewidth,
+        tile_height: tileset.tileheight,
+        columns: tileset.columns,
+        tile_count: tileset.tilecount,
+        margin: tileset.margin,
+        spacing: tileset.spacing,
+        animations,
+    })
+}
+
+fn property_string(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+fn add_layers(tilemap: &mut Tilemap, layers: Vec<TiledLayer>) -> Result<()> {
+    for layer in layers {
+        match layer {
+            TiledLayer::TileLayer {
+                name,
+                width,
+                height,
+                data,
+                visible,
+                opacity,
+                offsetx,
+                offsety,
+            } => {
+                let Ok(tiles) = serde_json::from_value::<Vec<u32>>(data) else {
+                    bail!("layer {} is not saved as CSV", name);
+                };
+                if tiles.len() != (width * height) as usize {
+                    bail!(
+                        "layer {} has {} tiles, expected {}",
+                        name,
+                        tiles.len(),
+                        width * height
+                    );
+                }
+                tilemap.layers.push(TileLayer {
+                    name,
+                    width,
+                    height,
+                    tiles,
+                    visible,
+                    opacity,
+                    offset: Vec2::new(offsetx, offsety),
+                });
+            }
+            TiledLayer::ObjectGroup {
+                name,
+                objects,
+                offsetx,
+                offsety,
+            } => {
+                let objects = objects
+                    .into_iter()
+                    .map(|o| MapObject {
+                        rect: o.rect(Vec2::new(offsetx, offsety)),
+                        id: o.id,
+                        name: o.name,
+                        kind: o.r#type,
+                        properties: o
+                            .properties
+                            .into_iter()
+                            .map(|p| (p.name, property_string(p.value)))
+                            .collect::<BTreeMap<_, _>>(),
+                    })
+                    .collect();
+                tilemap.object_layers.push(ObjectLayer { name, objects });
+            }
+            TiledLayer::Group { layers } => add_layers(tilemap, layers)?,
+            TiledLayer::Other => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_objects_are_placed_by_their_bottom() {
+        let layers = serde_json::from_str::<Vec<TiledLayer>>(
+            r#"[{
+                "type": "objectgroup",
+                "name": "objects",
+                "offsetx": 5,
+                "objects": [
+                    {"id": 1, "x": 10, "y": 40, "width": 16, "height": 16},
+                    {"id": 2, "x": 10, "y": 40, "width": 16, "height": 16, "gid": 3}
+                ]
+            }]"#,
+        )
+        .unwrap();
+        let mut tilemap = Tilemap {
+            width: 1,
+            height: 1,
+            tile_width: 16.,
+            tile_height: 16.,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            object_layers: Vec::new(),
+            position: Vec2::ZERO,
+            time: 0.,
+            entered: Default::default(),
+        };
+        add_layers(&mut tilemap, layers).unwrap();
+        let rects = tilemap.objects().map(|o| o.rect).collect::<Vec<_>>();
+        assert_eq!(
+            rects,
+            vec![Rect::new(15., 40., 16., 16.), Rect::new(15., 24., 16., 16.)]
+        );
+    }
+}
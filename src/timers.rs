@@ -23,6 +23,11 @@ impl AliveTimer {
     pub fn new(ttl: f32) -> Self {
         Self { ttl }
     }
+
+    /// Time left, in seconds
+    pub fn ttl(&self) -> f32 {
+        self.ttl.max(0.)
+    }
 }
 
 impl AliveUpdatable for AliveTimer {}
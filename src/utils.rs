@@ -6,44 +6,55 @@ use egui_macroquad::egui::{
 };
 use macroquad::prelude::Color;
 use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
 
-#[derive(Default, Debug, Clone, Copy)]
-pub struct GameColor(Color);
-
-pub const WHITE: GameColor = GameColor(macroquad::color::WHITE);
-pub const BLACK: GameColor = GameColor(macroquad::color::BLACK);
-pub const LIGHTGRAY: GameColor = GameColor(macroquad::color::LIGHTGRAY);
-pub const GRAY: GameColor = GameColor(macroquad::color::GRAY);
-pub const DARKGRAY: GameColor = GameColor(macroquad::color::DARKGRAY);
-pub const BLUE: GameColor = GameColor(macroquad::color::BLUE);
-pub const RED: GameColor = GameColor(macroquad::color::RED);
-pub const GREEN: GameColor = GameColor(macroquad::color::GREEN);
-pub const PURPLE: GameColor = GameColor(macroquad::color::PURPLE);
-pub const GOLD: GameColor = GameColor(macroquad::color::GOLD);
-pub const LIME: GameColor = GameColor(macroquad::color::LIME);
-pub const PINK: GameColor = GameColor(macroquad::color::PINK);
-pub const BEIGE: GameColor = GameColor(macroquad::color::BEIGE);
-pub const BROWN: GameColor = GameColor(macroquad::color::BROWN);
-pub const MAROON: GameColor = GameColor(macroquad::color::MAROON);
-pub const ORANGE: GameColor = GameColor(macroquad::color::ORANGE);
-pub const YELLOW: GameColor = GameColor(macroquad::color::YELLOW);
-pub const VIOLET: GameColor = GameColor(macroquad::color::VIOLET);
-pub const MAGENTA: GameColor = GameColor(macroquad::color::MAGENTA);
-pub const SKYBLUE: GameColor = GameColor(macroquad::color::SKYBLUE);
-pub const DARKBLUE: GameColor = GameColor(macroquad::color::DARKBLUE);
-pub const DARKBROWN: GameColor = GameColor(macroquad::color::DARKBROWN);
-pub const DARKGREEN: GameColor = GameColor(macroquad::color::DARKGREEN);
-pub const DARKPURPLE: GameColor = GameColor(macroquad::color::DARKPURPLE);
-
-pub const PALETTE: [GameColor; 24] = [
-    BLACK, WHITE, LIGHTGRAY, GRAY, DARKGRAY, SKYBLUE, BLUE, DARKBLUE, PINK, RED, MAROON, MAGENTA,
-    LIME, GREEN, DARKGREEN, PURPLE, VIOLET, DARKPURPLE, BEIGE, BROWN, DARKBROWN, GOLD, YELLOW,
-    ORANGE,
-];
+/// A color, optionally tied to an index of the active `Palette`. Indexed colors are looked up
+/// (and remapped) when they are drawn, the `Color` is only a fallback for when the palette does
+/// not have that index.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct GameColor(Color, Option<u8>);
+
+pub const WHITE: GameColor = GameColor(macroquad::color::WHITE, None);
+pub const BLACK: GameColor = GameColor(macroquad::color::BLACK, None);
+pub const LIGHTGRAY: GameColor = GameColor(macroquad::color::LIGHTGRAY, None);
+pub const GRAY: GameColor = GameColor(macroquad::color::GRAY, None);
+pub const DARKGRAY: GameColor = GameColor(macroquad::color::DARKGRAY, None);
+pub const BLUE: GameColor = GameColor(macroquad::color::BLUE, None);
+pub const RED: GameColor = GameColor(macroquad::color::RED, None);
+pub const GREEN: GameColor = GameColor(macroquad::color::GREEN, None);
+pub const PURPLE: GameColor = GameColor(macroquad::color::PURPLE, None);
+pub const GOLD: GameColor = GameColor(macroquad::color::GOLD, None);
+pub const LIME: GameColor = GameColor(macroquad::color::LIME, None);
+pub const PINK: GameColor = GameColor(macroquad::color::PINK, None);
+pub const BEIGE: GameColor = GameColor(macroquad::color::BEIGE, None);
+pub const BROWN: GameColor = GameColor(macroquad::color::BROWN, None);
+pub const MAROON: GameColor = GameColor(macroquad::color::MAROON, None);
+pub const ORANGE: GameColor = GameColor(macroquad::color::ORANGE, None);
+pub const YELLOW: GameColor = GameColor(macroquad::color::YELLOW, None);
+pub const VIOLET: GameColor = GameColor(macroquad::color::VIOLET, None);
+pub const MAGENTA: GameColor = GameColor(macroquad::color::MAGENTA, None);
+pub const SKYBLUE: GameColor = GameColor(macroquad::color::SKYBLUE, None);
+pub const DARKBLUE: GameColor = GameColor(macroquad::color::DARKBLUE, None);
+pub const DARKBROWN: GameColor = GameColor(macroquad::color::DARKBROWN, None);
+pub const DARKGREEN: GameColor = GameColor(macroquad::color::DARKGREEN, None);
+pub const DARKPURPLE: GameColor = GameColor(macroquad::color::DARKPURPLE, None);
 
 impl GameColor {
     pub fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
-        Self(Color::new(r, g, b, a))
+        Self(Color::new(r, g, b, a), None)
+    }
+
+    pub fn indexed(index: u8, fallback: Color) -> Self {
+        Self(fallback, Some(index))
+    }
+
+    pub fn index(&self) -> Option<u8> {
+        self.1
+    }
+
+    /// The color to draw with right now
+    pub fn color(&self) -> Color {
+        self.1.and_then(crate::palette::resolve).unwrap_or(self.0)
     }
 
     pub fn brighten(self, amt: f32) -> Self {
@@ -78,41 +89,40 @@ impl GameColor {
 
 impl From<Color> for GameColor {
     fn from(value: Color) -> Self {
-        Self(value)
+        Self(value, None)
     }
 }
 
 impl From<GameColor> for Color {
     fn from(value: GameColor) -> Self {
-        value.0
+        value.color()
     }
 }
 
 impl From<egui::Rgba> for GameColor {
+    /// egui colors are premultiplied, ours are not
     fn from(value: egui::Rgba) -> Self {
-        let color = Color::new(value.r(), value.g(), value.b(), value.a());
-        Self(color)
+        let [r, g, b, a] = value.to_rgba_unmultiplied();
+        Self(Color::new(r, g, b, a), None)
     }
 }
 
 impl From<GameColor> for egui::Rgba {
     fn from(value: GameColor) -> Self {
-        let c = value.0;
+        let c = value.color();
         egui::Rgba::from_rgba_unmultiplied(c.r, c.g, c.b, c.a)
     }
 }
 
 impl From<HsvaGamma> for GameColor {
     fn from(value: HsvaGamma) -> Self {
-        let value: egui::Rgba = value.into();
-        let color = Color::new(value.r(), value.g(), value.b(), value.a());
-        Self(color)
+        egui::Rgba::from(value).into()
     }
 }
 
 impl From<GameColor> for HsvaGamma {
     fn from(value: GameColor) -> Self {
-        let c = value.0;
+        let c = value.color();
         let rgba = egui::Rgba::from_rgba_unmultiplied(c.r, c.g, c.b, c.a);
         rgba.into()
     }
@@ -120,15 +130,13 @@ impl From<GameColor> for HsvaGamma {
 
 impl From<Color32> for GameColor {
     fn from(value: Color32) -> Self {
-        let value: egui::Rgba = value.into();
-        let color = Color::new(value.r(), value.g(), value.b(), value.a());
-        Self(color)
+        egui::Rgba::from(value).into()
     }
 }
 
 impl From<GameColor> for Color32 {
     fn from(value: GameColor) -> Self {
-        let c = value.0;
+        let c = value.color();
         let rgba = egui::Rgba::from_rgba_unmultiplied(c.r, c.g, c.b, c.a);
         rgba.into()
     }
@@ -136,27 +144,221 @@ impl From<GameColor> for Color32 {
 
 impl From<Hsva> for GameColor {
     fn from(value: Hsva) -> Self {
-        let value: egui::Rgba = value.into();
-        let color = Color::new(value.r(), value.g(), value.b(), value.a());
-        Self(color)
+        egui::Rgba::from(value).into()
     }
 }
 
 impl From<GameColor> for Hsva {
     fn from(value: GameColor) -> Self {
-        let c = value.0;
-        let rgba = egui::Rgba::from_rgba_premultiplied(c.r, c.g, c.b, c.a);
+        let c = value.color();
+        let rgba = egui::Rgba::from_rgba_unmultiplied(c.r, c.g, c.b, c.a);
         rgba.into()
     }
 }
 
+/// How `GameColor`s are written when serialized. Indexed colors are always written as `pal(n)`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorFormat {
+    /// `rgba(1,0.8,0,1)`, lossless
+    #[default]
+    Rgba,
+    /// `#ffcc00` or, when not opaque, `#ffcc00aa`. Rounds to 8 bits per channel.
+    Hex,
+}
+
+thread_local! {
+    static COLOR_FORMAT: Cell<ColorFormat> = const { Cell::new(ColorFormat::Rgba) };
+}
+
+/// Serialize colors inside `f` in the given format, e.g. to save an asset with hex colors:
+/// `with_color_format(ColorFormat::Hex, || serde_yaml::to_string(&builder))`
+pub fn with_color_format<R>(format: ColorFormat, f: impl FnOnce() -> R) -> R {
+    let previous = COLOR_FORMAT.with(|c| c.replace(format));
+    let r = f();
+    COLOR_FORMAT.with(|c| c.set(previous));
+    r
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ColorParseError {
+    #[error("{0:?} is not a color, use rgba(...), hsv(...), #rrggbb, #rrggbbaa, pal(n) or a name")]
+    Unknown(String),
+    #[error("{0:?} should have {1} numbers")]
+    WrongArgs(String, usize),
+    #[error("{0:?} is not a hex color")]
+    BadHex(String),
+    #[error("palette index {0} is out of range")]
+    BadIndex(i64),
+}
+
+pub const NAMED_COLORS: [(&str, GameColor); 24] = [
+    ("black", BLACK),
+    ("white", WHITE),
+    ("lightgray", LIGHTGRAY),
+    ("gray", GRAY),
+    ("darkgray", DARKGRAY),
+    ("skyblue", SKYBLUE),
+    ("blue", BLUE),
+    ("darkblue", DARKBLUE),
+    ("pink", PINK),
+    ("red", RED),
+    ("maroon", MAROON),
+    ("magenta", MAGENTA),
+    ("lime", LIME),
+    ("green", GREEN),
+    ("darkgreen", DARKGREEN),
+    ("purple", PURPLE),
+    ("violet", VIOLET),
+    ("darkpurple", DARKPURPLE),
+    ("beige", BEIGE),
+    ("brown", BROWN),
+    ("darkbrown", DARKBROWN),
+    ("gold", GOLD),
+    ("yellow", YELLOW),
+    ("orange", ORANGE),
+];
+
+/// The numbers inside `name(...)`, if `s` looks like that
+fn parse_args<'a>(s: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let inner = s.strip_prefix(name)?.trim_start().strip_prefix('(')?;
+    let inner = inner.strip_suffix(')')?;
+    Some(inner.split(',').map(|v| v.trim()).collect())
+}
+
+fn parse_floats(s: &str, args: &[&str], counts: &[usize]) -> Result<Vec<f32>, ColorParseError> {
+    let err = || ColorParseError::WrongArgs(String::from(s), counts[0]);
+    if !counts.contains(&args.len()) {
+        return Err(err());
+    }
+    args.iter()
+        .map(|v| v.parse::<f32>().map_err(|_| err()))
+        .collect()
+}
+
+impl GameColor {
+    /// Look `index` up in the active palette. Indices the palette does not have are kept, they
+    /// are white until a palette that has them is active.
+    pub fn from_index(index: i64) -> Result<Self, ColorParseError> {
+        let index = u8::try_from(index).map_err(|_| ColorParseError::BadIndex(index))?;
+        let fallback = crate::palette::with_active(|p| p.get(index));
+        Ok(Self::indexed(index, fallback.unwrap_or(WHITE.0)))
+    }
+
+    /// `#rrggbb`, or `#rrggbbaa` when the color is not opaque
+    pub fn to_hex(&self) -> String {
+        let [r, g, b, a]: [u8; 4] = self.0.into();
+        if a == 255 {
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+
+    pub fn to_rgba_string(&self) -> String {
+        format!("rgba({},{},{},{})", self.0.r, self.0.g, self.0.b, self.0.a)
+    }
+}
+
+impl std::str::FromStr for GameColor {
+    type Err = ColorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let lower = s.to_ascii_lowercase();
+        if let Some(hex) = lower.strip_prefix('#') {
+            let bad = || ColorParseError::BadHex(String::from(s));
+            if !matches!(hex.len(), 6 | 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(bad());
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| bad());
+            let a = if hex.len() == 8 { channel(6)? } else { 255 };
+            return Ok(Color::from_rgba(channel(0)?, channel(2)?, channel(4)?, a).into());
+        }
+        if let Some(args) = parse_args(&lower, "rgba") {
+            let v = parse_floats(s, &args, &[4])?;
+            return Ok(Self::new(v[0], v[1], v[2], v[3]));
+        }
+        if let Some(args) = parse_args(&lower, "hsv") {
+            // hue in degrees, saturation and value from 0 to 1
+            let v = parse_floats(s, &args, &[3, 4])?;
+            let a = v.get(3).copied().unwrap_or(1.);
+            return Ok(HsvaGamma {
+                h: (v[0] / 360.).rem_euclid(1.),
+                s: v[1],
+                v: v[2],
+                a,
+            }
+            .into());
+        }
+        if let Some(args) = parse_args(&lower, "pal") {
+            let index = match args.as_slice() {
+                [index] => index
+                    .parse::<i64>()
+                    .map_err(|_| ColorParseError::WrongArgs(String::from(s), 1))?,
+                _ => return Err(ColorParseError::WrongArgs(String::from(s), 1)),
+            };
+            return Self::from_index(index);
+        }
+        if let Ok(index) = lower.parse::<i64>() {
+            return Self::from_index(index);
+        }
+        NAMED_COLORS
+            .iter()
+            .find(|(name, _)| *name == lower)
+            .map(|(_, color)| *color)
+            .ok_or_else(|| ColorParseError::Unknown(String::from(s)))
+    }
+}
+
+impl std::fmt::Display for GameColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(index) = self.1 {
+            return write!(f, "pal({})", index);
+        }
+        match COLOR_FORMAT.with(|c| c.get()) {
+            ColorFormat::Rgba => f.write_str(&self.to_rgba_string()),
+            ColorFormat::Hex => f.write_str(&self.to_hex()),
+        }
+    }
+}
+
 impl Serialize for GameColor {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let s = format!("rgba({},{},{},{})", self.0.r, self.0.g, self.0.b, self.0.a);
-        serializer.serialize_str(&s)
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+struct GameColorVisitor;
+
+impl<'de> serde::de::Visitor<'de> for GameColorVisitor {
+    type Value = GameColor;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a color string or a palette index")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        GameColor::from_index(v).map_err(E::custom)
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        GameColor::from_index(v.min(i64::MAX as u64) as i64).map_err(E::custom)
     }
 }
 
@@ -165,18 +367,131 @@ impl<'de> Deserialize<'de> for GameColor {
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-        if let Some(s) = s.strip_prefix("rgba(") {
-            if let Some(s) = s.strip_suffix(')') {
-                let v: Vec<_> = s
-                    .splitn(4, ',')
-                    .map_while(|v| v.parse::<f32>().ok())
-                    .collect();
-                if v.len() == 4 {
-                    return Ok(Self(Color::new(v[0], v[1], v[2], v[3])));
-                }
+        deserializer.deserialize_any(GameColorVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> GameColor {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    /// Saved in `format` and loaded again, as text and as YAML
+    fn round_trip(color: GameColor, format: ColorFormat) -> GameColor {
+        let text = with_color_format(format, || color.to_string());
+        let yaml = with_color_format(format, || serde_yaml::to_string(&color).unwrap());
+        let from_yaml = serde_yaml::from_str::<GameColor>(&yaml).unwrap();
+        assert_eq!(parse(&text), from_yaml, "{}", text);
+        from_yaml
+    }
+
+    const FORMATS: [ColorFormat; 2] = [ColorFormat::Rgba, ColorFormat::Hex];
+
+    #[test]
+    fn rgba_is_lossless() {
+        for s in [
+            "#ffcc00",
+            "#ffcc0080",
+            "red",
+            "hsv(200, 0.5, 0.7)",
+            "rgba(0.1,0.2,0.3,0.4)",
+        ] {
+            let color = parse(s);
+            assert_eq!(round_trip(color, ColorFormat::Rgba), color, "{}", s);
+        }
+    }
+
+    #[test]
+    fn hex_rounds_to_8_bits() {
+        for s in [
+            "#ffcc00",
+            "#ffcc0080",
+            "red",
+            "hsv(200, 0.5, 0.7)",
+            "rgba(0.1,0.2,0.3,0.4)",
+        ] {
+            let color = parse(s);
+            let loaded = round_trip(color, ColorFormat::Hex);
+            assert_eq!(loaded.to_hex(), color.to_hex(), "{}", s);
+            assert_eq!(round_trip(loaded, ColorFormat::Hex), loaded, "{}", s);
+        }
+        assert_eq!(parse("  #FFCC00 ").to_hex(), "#ffcc00");
+        assert_eq!(parse("#ffcc0080").to_hex(), "#ffcc0080");
+    }
+
+    #[test]
+    fn names_and_hsv() {
+        assert_eq!(parse("Red"), RED);
+        assert_eq!(parse("hsv(0, 0, 1)").to_hex(), "#ffffff");
+        assert_eq!(parse("hsv(360, 1, 1)").to_hex(), "#ff0000");
+        assert_eq!(parse("hsv(120, 1, 1, 0.5)").to_hex(), "#00ff007f");
+        for (name, color) in NAMED_COLORS {
+            for format in FORMATS {
+                assert_eq!(round_trip(parse(name), format).to_hex(), color.to_hex());
             }
         }
-        Err(serde::de::Error::custom("should be in rgba(...) format"))
+    }
+
+    #[test]
+    fn indexed_colors_stay_indexed() {
+        for s in ["pal(8)", "pal( 8 )", "8"] {
+            let color = parse(s);
+            assert_eq!(color.index(), Some(8));
+            assert_eq!(
+                color.color(),
+                crate::palette::Palette::pico8().get(8).unwrap()
+            );
+            for format in FORMATS {
+                assert_eq!(with_color_format(format, || color.to_string()), "pal(8)");
+                assert_eq!(round_trip(color, format), color, "{}", s);
+            }
+        }
+        assert_eq!(
+            serde_yaml::from_str::<GameColor>("8").unwrap(),
+            parse("pal(8)")
+        );
+    }
+
+    #[test]
+    fn bad_colors() {
+        let err = |s: &str| s.parse::<GameColor>().unwrap_err();
+        assert_eq!(
+            err("#ffcc0"),
+            ColorParseError::BadHex(String::from("#ffcc0"))
+        );
+        assert_eq!(
+            err("#ggcc00"),
+            ColorParseError::BadHex(String::from("#ggcc00"))
+        );
+        assert_eq!(
+            err("rgba(1,1,1)"),
+            ColorParseError::WrongArgs(String::from("rgba(1,1,1)"), 4)
+        );
+        assert_eq!(
+            err("pal(1,2)"),
+            ColorParseError::WrongArgs(String::from("pal(1,2)"), 1)
+        );
+        assert_eq!(err("teal"), ColorParseError::Unknown(String::from("teal")));
+    }
+
+    #[test]
+    fn indices_outside_the_palette_are_kept() {
+        // PICO-8 has 16 colors
+        let color = parse("pal(200)");
+        assert_eq!(color.index(), Some(200));
+        assert_eq!(color.color(), macroquad::color::WHITE);
+        assert_eq!(color.to_string(), "pal(200)");
+        assert_eq!(parse("200"), color);
+        assert_eq!(
+            "pal(256)".parse::<GameColor>(),
+            Err(ColorParseError::BadIndex(256))
+        );
+        assert_eq!(
+            "-1".parse::<GameColor>(),
+            Err(ColorParseError::BadIndex(-1))
+        );
     }
 }
@@ -0,0 +1,237 @@
+//! Watches asset files, so changes saved from other programs show up while the game runs
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use macroquad::prelude::warn;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Editors often save in several writes, a file has to be left alone this long before its change
+/// is reported
+const SETTLE_TIME: Duration = Duration::from_millis(150);
+
+pub struct AssetWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Changed paths are reported relative to this
+    root: PathBuf,
+    /// Changed, and when they last changed
+    pending: BTreeMap<PathBuf, Instant>,
+    /// Directories under the root that are not watched, like where captures are written
+    ignored: Vec<PathBuf>,
+    /// Every directory has its own watch, so ignored ones cost nothing
+    watched: BTreeSet<PathBuf>,
+}
+
+impl AssetWatcher {
+    /// Watch the files in `dir` and its sub directories, except for hidden ones (like `.git`) and
+    /// `ignored`, which are relative to `dir`. Changes are reported relative to `dir`, usually
+    /// the asset root.
+    pub fn new<P: Into<PathBuf>>(
+        dir: impl AsRef<Path>,
+        ignored: impl IntoIterator<Item = P>,
+    ) -> Result<Self> {
+        let (sender, events) = channel();
+        let watcher = notify::recommended_watcher(sender)?;
+        let dir = dir.as_ref();
+        let root = dir
+            .canonicalize()
+            .with_context(|| format!("Could not watch {}", dir.display()))?;
+        let mut obj = Self {
+            watcher,
+            events,
+            root: root.clone(),
+            pending: BTreeMap::new(),
+            ignored: ignored.into_iter().map(Into::into).collect(),
+            watched: BTreeSet::new(),
+        };
+        obj.watch(root)?;
+        Ok(obj)
+    }
+
+    /// Also watch the files in `dir` and its sub directories
+    pub fn watch(&mut self, dir: impl AsRef<Path>) -> Result<()> {
+        self.watch_tree(dir.as_ref(), None)
+    }
+
+    /// Hidden and ignored directories under the root are left out
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Ok(path) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        self.ignored.iter().any(|dir| path.starts_with(dir))
+            || path.components().any(
+                |c| matches!(c, Component::Normal(name) if name.to_string_lossy().starts_with('.')),
+            )
+    }
+
+    /// Watch `dir` and the directories in it one by one. Files found are reported as changed at
+    /// `found`, for directories that were just created and may already have files.
+    fn watch_tree(&mut self, dir: &Path, found: Option<Instant>) -> Result<()> {
+        if self.is_ignored(dir) || self.watched.contains(dir) {
+            return Ok(());
+        }
+        self.watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Could not watch {}", dir.display()))?;
+        self.watched.insert(dir.to_path_buf());
+        let entries =
+            fs::read_dir(dir).with_context(|| format!("Could not watch {}", dir.display()))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => self.watch_tree(&path, found)?,
+                Ok(t) if t.is_file() => {
+                    if let Some(at) = found {
+                        self.pending.insert(self.relative(path), at);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn relative(&self, path: PathBuf) -> PathBuf {
+        path.strip_prefix(&self.root)
+            .map(Path::to_path_buf)
+            .unwrap_or(path)
+    }
+
+    /// Files that changed since the last call and then settled, relative to the first watched
+    /// directory when they are inside it
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        let events = self.events.try_iter().flatten().collect::<Vec<_>>();
+        for event in events {
+            if let EventKind::Remove(_) = event.kind {
+                // Removed directories lose their watch, they get a new one if made again
+                for path in &event.paths {
+                    self.watched.retain(|dir| !dir.starts_with(path));
+                }
+                continue;
+            }
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+            for path in event.paths {
+                if self.is_ignored(&path) {
+                    continue;
+                }
+                if path.is_dir() {
+                    // New directories get their own watch
+                    if let Err(err) = self.watch_tree(&path, Some(now)) {
+                        warn!("Unable to watch new directory: {:#}", err);
+                    }
+                    continue;
+                }
+                let path = self.relative(path);
+                self.pending.insert(path, now);
+            }
+        }
+        let settled = self
+            .pending
+            .iter()
+            .filter(|(_, at)| now.duration_since(**at) >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in &settled {
+            self.pending.remove(path);
+        }
+        settled
+            .into_iter()
+            .filter(|p| self.root.join(p).is_file())
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for AssetWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AssetWatcher")
+            .field("root", &self.root)
+            .field("pending", &self.pending)
+            .field("ignored", &self.ignored)
+            .field("watched", &self.watched.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cowshmup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// Polls until something settles, or gives up
+    fn wait_for_changes(watcher: &mut AssetWatcher) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        let start = Instant::now();
+        while changed.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(SETTLE_TIME);
+            changed = watcher.changed();
+        }
+        changed
+    }
+
+    #[test]
+    fn reports_changes_in_sub_directories() {
+        let dir = temp_root("watch");
+        fs::create_dir_all(dir.join("sprites")).unwrap();
+        fs::create_dir_all(dir.join("captures")).unwrap();
+        let mut watcher = AssetWatcher::new(&dir, ["captures"]).unwrap();
+        fs::write(dir.join("sprites/ship.yaml"), "image: ship.png").unwrap();
+        fs::write(dir.join("captures/screenshot.png"), "").unwrap();
+        let changed = wait_for_changes(&mut watcher);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(changed, vec![PathBuf::from("sprites/ship.yaml")]);
+    }
+
+    #[test]
+    fn ignored_directories_are_never_watched() {
+        let dir = temp_root("watch-ignored");
+        for sub in [
+            "sprites/ships",
+            "captures/old",
+            "target/debug",
+            ".git/objects",
+        ] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let mut watcher = AssetWatcher::new(&dir, ["captures", "target"]).unwrap();
+        let watched = |w: &AssetWatcher| {
+            w.watched
+                .iter()
+                .map(|p| w.relative(p.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            watched(&watcher),
+            ["", "sprites", "sprites/ships"].map(PathBuf::from)
+        );
+
+        // Directories made later get the same treatment
+        fs::create_dir_all(dir.join("levels")).unwrap();
+        fs::write(dir.join("levels/one.tmj"), "{}").unwrap();
+        fs::create_dir_all(dir.join("target/release")).unwrap();
+        let changed = wait_for_changes(&mut watcher);
+        let watched = watched(&watcher);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(changed, vec![PathBuf::from("levels/one.tmj")]);
+        assert_eq!(
+            watched,
+            ["", "levels", "sprites", "sprites/ships"].map(PathBuf::from)
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{bullet::Bullet, migrate::Versioned, utils::GameColor, CenterPt, Velocity};
+
+/// One bullet of a volley
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shot {
+    /// From the center of the ship
+    pub offset: (f32, f32),
+    /// Degrees clockwise from straight up
+    pub angle: f32,
+    /// Pixels per second
+    pub speed: f32,
+    pub radius: f32,
+    pub color: GameColor,
+}
+
+impl Default for Shot {
+    fn default() -> Self {
+        Self {
+            offset: (0., -4.),
+            angle: 0.,
+            speed: 180.,
+            radius: 1.,
+            color: crate::utils::YELLOW,
+        }
+    }
+}
+
+impl Shot {
+    fn bullet(&self, center: CenterPt) -> Bullet {
+        let (x, y) = center.get();
+        let angle = self.angle.to_radians();
+        let velocity = Velocity::new(angle.sin() * self.speed, -angle.cos() * self.speed);
+        let center = CenterPt::new(x + self.offset.0, y + self.offset.1);
+        Bullet::new(center, velocity, self.radius, self.color)
+    }
+}
+
+/// The volley fired at one upgrade level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WeaponLevel {
+    pub shots: Vec<Shot>,
+    /// Seconds between volleys
+    pub cooldown: f32,
+}
+
+impl Default for WeaponLevel {
+    fn default() -> Self {
+        Self {
+            shots: vec![Shot::default()],
+            cooldown: 0.15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WeaponDef {
+    pub name: String,
+    /// Lowest level first
+    pub levels: Vec<WeaponLevel>,
+    /// Power pickups needed to go up a level
+    pub power_per_level: u32,
+}
+
+impl Default for WeaponDef {
+    /// Single shot, twin shot, then a spread
+    fn default() -> Self {
+        let shot = |x: f32, angle: f32| Shot {
+            offset: (x, -4.),
+            angle,
+            ..Default::default()
+        };
+        Self {
+            name: String::from("vulcan"),
+            levels: vec![
+                WeaponLevel::default(),
+                WeaponLevel {
+                    shots: vec![shot(-2., 0.), shot(2., 0.)],
+                    ..Default::default()
+                },
+                WeaponLevel {
+                    shots: vec![shot(-2., 0.), shot(2., 0.), shot(-3., -12.), shot(3., 12.)],
+                    ..Default::default()
+                },
+            ],
+            power_per_level: 5,
+        }
+    }
+}
+
+impl Versioned for WeaponDef {}
+
+/// A `WeaponDef` in use, powered up by pickups
+#[derive(Debug, Clone, Default)]
+pub struct Weapon {
+    pub def: WeaponDef,
+    power: u32,
+    cooldown: f32,
+}
+
+impl Weapon {
+    pub fn new(def: WeaponDef) -> Self {
+        Self {
+            def,
+            power: 0,
+            cooldown: 0.,
+        }
+    }
+
+    /// From 0, never past the last level of the def
+    pub fn level(&self) -> usize {
+        let level = (self.power / self.def.power_per_level.max(1)) as usize;
+        level.min(self.def.levels.len().saturating_sub(1))
+    }
+
+    pub fn power(&self) -> u32 {
+        self.power
+    }
+
+    /// Returns true when the weapon went up a level
+    pub fn add_power(&mut self, amount: u32) -> bool {
+        let level = self.level();
+        let max = self.def.levels.len().saturating_sub(1) as u32 * self.def.power_per_level.max(1);
+        self.power = (self.power + amount).min(max);
+        self.level() > level
+    }
+
+    /// Drop `levels` levels, like when the player dies
+    pub fn lose_levels(&mut self, levels: u32) {
+        let per_level = self.def.power_per_level.max(1);
+        let level = self.level() as u32;
+        self.power = level.saturating_sub(levels) * per_level;
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.cooldown = (self.cooldown - delta_time).max(0.);
+    }
+
+    /// The bullets of a volley, or nothing while cooling down
+    pub fn fire(&mut self, center: CenterPt) -> Vec<Bullet> {
+        let Some(level) = self.def.levels.get(self.level()) else {
+            return Vec::new();
+        };
+        if self.cooldown > 0. {
+            return Vec::new();
+        }
+        self.cooldown = level.cooldown;
+        level.shots.iter().map(|s| s.bullet(center)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weapon(levels: usize, power_per_level: u32) -> Weapon {
+        Weapon::new(WeaponDef {
+            levels: vec![WeaponLevel::default(); levels],
+            power_per_level,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn power_is_capped_at_the_last_level() {
+        let mut weapon = weapon(3, 5);
+        assert!(!weapon.add_power(4));
+        assert_eq!(weapon.level(), 0);
+        assert!(weapon.add_power(1));
+        assert_eq!(weapon.level(), 1);
+        assert!(weapon.add_power(100));
+        assert_eq!((weapon.level(), weapon.power()), (2, 10));
+        assert!(!weapon.add_power(1));
+    }
+
+    #[test]
+    fn losing_levels_keeps_whole_levels() {
+        let mut weapon = weapon(3, 5);
+        weapon.add_power(8);
+        weapon.lose_levels(1);
+        assert_eq!((weapon.level(), weapon.power()), (0, 0));
+        weapon.add_power(10);
+        weapon.lose_levels(1);
+        assert_eq!((weapon.level(), weapon.power()), (1, 5));
+        weapon.lose_levels(5);
+        assert_eq!((weapon.level(), weapon.power()), (0, 0));
+    }
+
+    #[test]
+    fn zero_power_per_level_counts_as_one() {
+        let mut weapon = weapon(3, 0);
+        assert!(weapon.add_power(1));
+        assert_eq!(weapon.level(), 1);
+        weapon.add_power(10);
+        assert_eq!((weapon.level(), weapon.power()), (2, 2));
+    }
+
+    #[test]
+    fn weapons_without_levels_do_not_fire() {
+        let mut weapon = weapon(0, 5);
+        assert!(!weapon.add_power(10));
+        assert_eq!((weapon.level(), weapon.power()), (0, 0));
+        assert!(weapon.fire(CenterPt::new(0., 0.)).is_empty());
+    }
+}
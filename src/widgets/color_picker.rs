@@ -1,6 +1,6 @@
 use egui_macroquad::egui::*;
 
-use crate::utils::GameColor;
+use crate::{palette, utils::GameColor};
 
 pub fn show_color_at(painter: &Painter, color: Color32, rect: Rect) {
     painter.rect_filled(rect, 0.0, color);
@@ -47,13 +47,9 @@ fn color_picker_palette(ui: &mut Ui, color: &mut GameColor, palette: &[GameColor
     close
 }
 
-/// Shows a button that, when clicked, allows the user to select a color from a limited,
-/// pre-defined, palette.
-pub fn color_edit_palette_button(
-    ui: &mut Ui,
-    color: &mut GameColor,
-    palette: &[GameColor],
-) -> Response {
+/// Shows a button that, when clicked, allows the user to select a color from the active palette.
+/// The selected color keeps its palette index.
+pub fn color_edit_palette_button(ui: &mut Ui, color: &mut GameColor) -> Response {
     let popup_id = ui.auto_id_with("popup");
     let open = ui.memory(|mem| mem.is_popup_open(popup_id));
     let btn_response = color_button(ui, (*color).into(), open);
@@ -70,8 +66,9 @@ pub fn color_edit_palette_button(
             .constrain(true)
             .show(ui.ctx(), |ui| {
                 ui.spacing_mut().slider_width = btn_response.rect.width() * 8.;
+                let palette = palette::with_active(|p| p.indexed_colors());
                 Frame::popup(ui.style()).show(ui, |ui| {
-                    close = color_picker_palette(ui, color, palette);
+                    close = color_picker_palette(ui, color, &palette);
                 });
             })
             .response;
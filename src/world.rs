@@ -1,54 +1,736 @@
-use std::cell::RefCell;
+use macroquad::prelude::{info, warn, Rect};
+use std::{cell::RefCell, collections::BTreeMap};
 pub const GAME_WIDTH: f32 = 128.0;
 pub const GAME_HEIGHT: f32 = 128.0;
+/// Pickup enemy bullets turn into when a bomb goes off, a default is used when the `PickupBank`
+/// does not have one
+pub const BULLET_SCORE: &str = "bullet_score";
 
 // use serde::{Deserialize, Serialize};
 
 use crate::{
-    drawable::{Drawable, Gizmo, Graphic},
+    alive::IsAlive,
+    audio::SoundEvent,
+    background::Background,
+    boss::{self, Boss, BossEvent},
+    buildable::Buildable,
+    bullet::Bullet,
+    drawable::{Drawable, Gizmo, Graphic, HasCenter, UpdateCenter, UpdateVelocity},
+    enemy::Enemy,
+    layer::{LayerId, LayerItem, Layers},
     particle::Particle,
+    particle::{Explosion, ExplosionBuilder},
+    pattern::{BulletPattern, PatternRunner},
+    pickup::{Pickup, PickupBank, PickupDef, PickupEffect},
+    player::{Life, Player, PlayerInput},
+    rank::{Rank, RankCurve},
+    retro_camera::CameraEffect,
+    script::{self, Command, Coroutine, ScriptBank, ScriptError, Snapshot},
+    scroll_camera::ScrollCamera,
+    tilemap::{MapObject, Tilemap},
     updateable::Updateable,
-    Rc,
+    utils, CenterPt, Rc, Velocity,
 };
 
 #[derive(Default /*, Serialize, Deserialize*/)]
 pub struct World {
-    graphics: Vec<Graphic>,
-    particles: Vec<Box<dyn Particle>>,
+    layers: Layers,
     gizmos: Vec<Rc<dyn Gizmo>>,
+    camera_effects: Vec<CameraEffect>,
+    sound_events: Vec<SoundEvent>,
+    camera: ScrollCamera,
+    /// Map objects that scrolled on screen, see `take_map_objects`
+    map_objects: Vec<MapObject>,
+    player: Option<Player>,
+    player_input: PlayerInput,
+    player_bullets: Vec<Bullet>,
+    pickups: Vec<Pickup>,
+    pickup_bank: PickupBank,
+    boss: Option<Boss>,
+    enemy_bullets: Vec<Bullet>,
+    patterns: Vec<PatternRunner>,
+    /// Loaded once by name, `None` when loading failed
+    pattern_cache: BTreeMap<String, Option<Rc<BulletPattern>>>,
+    /// Each pattern gets its own seed, so a replay plays out the same
+    pattern_seed: u64,
+    enemies: Vec<Enemy>,
+    /// Scripts that are not an enemy, like the stage timeline
+    stage_scripts: Vec<Coroutine>,
+    scripts: ScriptBank,
+    /// Until the editor or game picks them up, see `take_script_errors`
+    script_errors: Vec<ScriptError>,
+    /// Seconds not run as script ticks yet
+    script_time: f32,
+    script_seed: u64,
+    rank: Rank,
 }
 
 impl World {
+    /// Add to the `Enemies` layer, see `add_graphic_to`
     pub fn add_graphic(&mut self, d: Graphic) {
-        self.graphics.push(d)
+        self.add_graphic_to(LayerId::Enemies, 0, d)
     }
 
+    /// Add to the `Particles` layer, see `add_particle_to`
     pub fn add_particle(&mut self, d: Box<dyn Particle>) {
-        self.particles.push(d)
+        self.add_particle_to(LayerId::Particles, 0, d)
+    }
+
+    /// Higher `z` is drawn on top of lower `z` within the layer
+    pub fn add_graphic_to(&mut self, layer: LayerId, z: i32, d: Graphic) {
+        self.layers.add(layer, z, LayerItem::Graphic(d))
+    }
+
+    pub fn add_particle_to(&mut self, layer: LayerId, z: i32, d: Box<dyn Particle>) {
+        self.layers.add(layer, z, LayerItem::Particle(d))
+    }
+
+    /// Tilemaps scroll with the world, they usually go on the `Parallax` layer
+    pub fn add_tilemap(&mut self, layer: LayerId, z: i32, tilemap: Tilemap) {
+        // Objects already on screen show up in the next update
+        self.layers.add(layer, z, LayerItem::Tilemap(tilemap));
+    }
+
+    /// Objects from tilemaps (spawn points, triggers) that came on screen since the last call
+    pub fn take_map_objects(&mut self) -> Vec<MapObject> {
+        std::mem::take(&mut self.map_objects)
+    }
+
+    /// Replace whatever is on the `Background` layer
+    pub fn set_background(&mut self, background: Background) {
+        let layer = self.layers.get_mut(LayerId::Background);
+        layer.clear();
+        layer.add(0, LayerItem::Background(background));
+    }
+
+    pub fn layers(&self) -> &Layers {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut Layers {
+        &mut self.layers
     }
 
     pub fn add_gizmos(&mut self, d: Rc<dyn Gizmo>) {
         self.gizmos.push(d);
     }
+
+    /// Ask the camera to shake, flash or hit-stop. Effects are passed on to the camera once a
+    /// frame.
+    pub fn camera_effect(&mut self, effect: CameraEffect) {
+        self.camera_effects.push(effect);
+    }
+
+    pub fn take_camera_effects(&mut self) -> Vec<CameraEffect> {
+        std::mem::take(&mut self.camera_effects)
+    }
+
+    /// Ask for a sound from the `SoundBank`, played (or not, see `Audio::trigger`) once a frame
+    pub fn play_sound(&mut self, event: SoundEvent) {
+        self.sound_events.push(event);
+    }
+
+    pub fn take_sound_events(&mut self) -> Vec<SoundEvent> {
+        std::mem::take(&mut self.sound_events)
+    }
+
+    pub fn player(&self) -> Option<&Player> {
+        self.player.as_ref()
+    }
+
+    pub fn player_mut(&mut self) -> Option<&mut Player> {
+        self.player.as_mut()
+    }
+
+    pub fn set_player(&mut self, player: Player) {
+        self.player = Some(player);
+    }
+
+    /// Used by the next update
+    pub fn set_player_input(&mut self, input: PlayerInput) {
+        self.player_input = input;
+    }
+
+    pub fn set_pickup_bank(&mut self, bank: PickupBank) {
+        self.pickup_bank = bank;
+    }
+
+    /// Roll a drop table from the `PickupBank`, like when an enemy dies at `center`
+    pub fn drop_pickups(&mut self, table: &str, center: CenterPt) {
+        let pickups = self.pickup_bank.drop(table, center);
+        self.pickups.extend(pickups);
+    }
+
+    pub fn rank(&self) -> &Rank {
+        &self.rank
+    }
+
+    pub fn rank_mut(&mut self) -> &mut Rank {
+        &mut self.rank
+    }
+
+    /// Start over at the start rank of `curve`
+    pub fn set_rank_curve(&mut self, curve: RankCurve) {
+        self.rank = Rank::new(curve);
+    }
+
+    /// Replaces the current boss, if any
+    pub fn spawn_boss(&mut self, boss: Boss) {
+        self.boss = Some(boss);
+    }
+
+    /// Start `<name>.xml` or `<name>.yaml` at `center`
+    pub fn spawn_pattern(&mut self, name: &str, center: CenterPt) {
+        let pattern = self
+            .pattern_cache
+            .entry(String::from(name))
+            .or_insert_with(|| match BulletPattern::load_named(name) {
+                Ok(pattern) => Some(pattern.rc()),
+                Err(err) => {
+                    warn!("Unable to load bullet pattern: {:#?}", err);
+                    None
+                }
+            })
+            .clone();
+        if let Some(pattern) = pattern {
+            let runner = PatternRunner::new(pattern, center, self.pattern_seed);
+            self.pattern_seed += 1;
+            self.add_pattern(runner);
+        }
+    }
+
+    pub fn add_pattern(&mut self, runner: PatternRunner) {
+        self.patterns.push(runner);
+    }
+
+    fn start_script(&mut self, name: &str) -> Option<Coroutine> {
+        let started = match self.scripts.source(name) {
+            Ok(source) => Coroutine::start(name, source, self.script_seed),
+            Err(err) => Err(ScriptError {
+                script: String::from(name),
+                message: format!("{:#}", err),
+            }),
+        };
+        match started {
+            Ok(script) => {
+                self.script_seed += 1;
+                Some(script)
+            }
+            Err(err) => {
+                self.script_errors.push(err);
+                None
+            }
+        }
+    }
+
+    /// Scripts that are not replacing one, up to `script::MAX_SCRIPTS`
+    fn start_new_script(&mut self, name: &str) -> Option<Coroutine> {
+        if self.enemies.len() + self.stage_scripts.len() >= script::MAX_SCRIPTS {
+            self.script_errors.push(ScriptError {
+                script: String::from(name),
+                message: format!("not started, {} scripts are running", script::MAX_SCRIPTS),
+            });
+            return None;
+        }
+        self.start_script(name)
+    }
+
+    /// Load the pattern `name` again the next time it is spawned
+    pub fn forget_pattern(&mut self, name: &str) {
+        self.pattern_cache.remove(name);
+    }
+
+    /// An enemy running `<script>.rhai`, to set up further
+    pub fn spawn_enemy(&mut self, script: &str, center: CenterPt) -> Option<&mut Enemy> {
+        let script = self.start_new_script(script)?;
+        self.enemies.push(Enemy::new(script, center));
+        self.enemies.last_mut()
+    }
+
+    /// Run `<name>.rhai` without an enemy, for stage events
+    pub fn run_script(&mut self, name: &str) {
+        if let Some(script) = self.start_new_script(name) {
+            self.stage_scripts.push(script);
+        }
+    }
+
+    pub fn enemies(&self) -> &[Enemy] {
+        &self.enemies
+    }
+
+    pub fn take_script_errors(&mut self) -> Vec<ScriptError> {
+        std::mem::take(&mut self.script_errors)
+    }
+
+    /// Start scripts that changed on disk over, enemies stay where they are
+    pub fn reload_scripts(&mut self) {
+        let changed = self.scripts.reload_changed();
+        if changed.is_empty() {
+            return;
+        }
+        info!("Reloaded scripts {:?}", changed);
+        for i in 0..self.enemies.len() {
+            let name = String::from(self.enemies[i].script.name());
+            if changed.contains(&name) {
+                if let Some(script) = self.start_script(&name) {
+                    self.enemies[i].script = script;
+                }
+            }
+        }
+        for i in 0..self.stage_scripts.len() {
+            let name = String::from(self.stage_scripts[i].name());
+            if changed.contains(&name) {
+                if let Some(script) = self.start_script(&name) {
+                    self.stage_scripts[i] = script;
+                }
+            }
+        }
+    }
+
+    pub fn boss(&self) -> Option<&Boss> {
+        self.boss.as_ref()
+    }
+
+    /// Which part of the world is on screen
+    pub fn camera(&self) -> &ScrollCamera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut ScrollCamera {
+        &mut self.camera
+    }
+
+    /// The view the `RetroCamera` renders, snapped to whole pixels like it does
+    fn view(&self) -> Rect {
+        let view = self.camera.view();
+        Rect::new(view.x.round(), view.y.round(), view.w, view.h)
+    }
+
+    fn update_player(&mut self, delta_time: f32) {
+        let view = self.view();
+        if let Some(player) = &mut self.player {
+            if matches!(player.life(), Life::Alive) {
+                self.rank
+                    .update(delta_time, player.score, player.weapon.level());
+            }
+            let fired = player.update_input(self.player_input, delta_time, view);
+            self.player_bullets.extend(fired);
+            if self.player_input.bomb && player.use_bomb() {
+                self.bomb();
+            }
+        }
+        self.player_bullets.update(delta_time);
+        // Bullets that left the screen are gone for good
+        let margin = Rect::new(view.x - 8., view.y - 8., view.w + 16., view.h + 16.);
+        self.player_bullets
+            .retain(|b| b.is_alive() && margin.contains(b.center().get().into()));
+    }
+
+    /// Enemy bullets and the boss itself kill the player, bullets that only come close are
+    /// grazed
+    fn update_player_hits(&mut self) {
+        let Some(player) = &mut self.player else {
+            return;
+        };
+        if !player.is_vulnerable() {
+            return;
+        }
+        let center = player.center();
+        let bullets = self
+            .enemy_bullets
+            .iter_mut()
+            .chain(self.patterns.iter_mut().flat_map(|p| p.bullets_mut()));
+        for from in player.graze_bullets(bullets) {
+            let sparks = Explosion::sparks(center, from, crate::utils::WHITE);
+            self.layers
+                .add(LayerId::Particles, 0, LayerItem::Particle(Box::new(sparks)));
+        }
+        let hit_bullet = self
+            .enemy_bullets
+            .iter()
+            .chain(self.patterns.iter().flat_map(|p| p.bullets()))
+            .any(|b| b.is_alive() && b.overlaps(center, player.radius));
+        let hit_boss = self
+            .boss
+            .as_mut()
+            .is_some_and(|b| b.touches(center, player.radius));
+        let hit_enemy = self
+            .enemies
+            .iter()
+            .any(|e| !e.is_dead() && e.touches(center, player.radius));
+        if !(hit_bullet || hit_boss || hit_enemy) || !player.kill() {
+            return;
+        }
+        self.rank.on_death();
+        if let Some(explosion) = ExplosionBuilder::burst(12.).build(center) {
+            self.layers.add(
+                LayerId::Particles,
+                0,
+                LayerItem::Particle(Box::new(explosion)),
+            );
+        }
+        self.camera_effects.push(CameraEffect::HitStop(0.15));
+        self.camera_effects.push(CameraEffect::Shake(0.6));
+        self.sound_events
+            .push(SoundEvent::at("player_death", center));
+        // A fresh start for the next ship
+        self.enemy_bullets.clear();
+        self.patterns.iter_mut().for_each(|p| p.clear_bullets());
+    }
+
+    /// Clear the screen of enemy bullets, each one turns into points flying to the player
+    fn bomb(&mut self) {
+        let def = self
+            .pickup_bank
+            .pickups
+            .get(BULLET_SCORE)
+            .cloned()
+            .unwrap_or_else(|| PickupDef {
+                effect: PickupEffect::Score(10),
+                color: crate::utils::GOLD,
+                radius: 1.,
+                magnet_radius: 1000.,
+                magnet_speed: 150.,
+                fall_speed: 0.,
+                sound: None,
+                ..Default::default()
+            });
+        let mut bullets = std::mem::take(&mut self.enemy_bullets);
+        for pattern in &mut self.patterns {
+            bullets.extend(pattern.take_bullets());
+        }
+        let pickups = bullets
+            .into_iter()
+            .filter(|b| b.is_alive())
+            .map(|b| Pickup::new(BULLET_SCORE, def.clone(), b.center()));
+        self.pickups.extend(pickups);
+        self.camera_effects.push(CameraEffect::Flash {
+            color: crate::utils::WHITE,
+            duration: 0.3,
+        });
+        self.camera_effects.push(CameraEffect::Shake(0.5));
+        let center = self.camera.center();
+        self.sound_events.push(SoundEvent::at("bomb", center));
+    }
+
+    fn update_boss(&mut self, delta_time: f32) {
+        let Some(boss) = &mut self.boss else {
+            return;
+        };
+        for bullet in &mut self.player_bullets {
+            if bullet.is_alive() && boss.hit(bullet.center(), bullet.radius, 1.) {
+                bullet.kill();
+            }
+        }
+        let target = self.player.as_ref().map(|p| p.center());
+        let mut pattern_starts = Vec::new();
+        let mut drops_at = Vec::new();
+        for event in boss.update(delta_time, target, &self.rank) {
+            match event {
+                BossEvent::Fire(bullet) => self.enemy_bullets.push(bullet),
+                BossEvent::Pattern { name, center } => pattern_starts.push((name, center)),
+                BossEvent::Explode {
+                    center,
+                    explosion,
+                    shake,
+                } => {
+                    if let Some(explosion) = explosion.build(center) {
+                        self.layers.add(
+                            LayerId::Particles,
+                            0,
+                            LayerItem::Particle(Box::new(explosion)),
+                        );
+                    }
+                    self.camera_effects.push(CameraEffect::Shake(shake));
+                    self.sound_events.push(SoundEvent::at("explosion", center));
+                }
+                BossEvent::PhaseChanged(_) => {
+                    self.camera_effects.push(CameraEffect::Flash {
+                        color: crate::utils::WHITE,
+                        duration: 0.1,
+                    });
+                }
+                BossEvent::PartDestroyed {
+                    center,
+                    score,
+                    drops,
+                    ..
+                }
+                | BossEvent::Defeated {
+                    center,
+                    score,
+                    drops,
+                } => {
+                    if let Some(player) = &mut self.player {
+                        player.add_score(score);
+                    }
+                    if let Some(table) = drops {
+                        drops_at.push((table, center));
+                    }
+                }
+            }
+        }
+        if boss.is_dying() {
+            // Everything the boss fired goes with it
+            self.enemy_bullets.clear();
+            self.patterns.clear();
+            pattern_starts.clear();
+        }
+        if boss.is_gone() {
+            self.boss = None;
+        }
+        for (name, center) in pattern_starts {
+            self.spawn_pattern(&name, center);
+        }
+        for (table, center) in drops_at {
+            self.drop_pickups(&table, center);
+        }
+    }
+
+    fn update_enemy_bullets(&mut self, delta_time: f32) {
+        let view = self.view();
+        self.enemy_bullets.update(delta_time);
+        let margin = Rect::new(view.x - 8., view.y - 8., view.w + 16., view.h + 16.);
+        self.enemy_bullets
+            .retain(|b| b.is_alive() && margin.contains(b.center().get().into()));
+    }
+
+    /// Scripts run on fixed ticks, like bullet patterns
+    fn update_scripts(&mut self, delta_time: f32) {
+        let tick = 1. / script::TICKS_PER_SECOND;
+        self.script_time += delta_time;
+        for _ in 0..8 {
+            if self.script_time < tick {
+                return;
+            }
+            self.script_time -= tick;
+            self.tick_scripts();
+        }
+        self.script_time = self.script_time.min(tick);
+    }
+
+    fn tick_scripts(&mut self) {
+        let player = self
+            .player
+            .as_ref()
+            .filter(|p| matches!(p.life(), Life::Alive))
+            .map(|p| p.center());
+        let rank = self.rank.fraction();
+        let mut results = Vec::new();
+        for (i, enemy) in self.enemies.iter_mut().enumerate() {
+            let snapshot = enemy.snapshot(player, rank);
+            results.push((Some(i), enemy.script.tick(snapshot)));
+        }
+        let center = self.camera.center();
+        for script in &mut self.stage_scripts {
+            let snapshot = Snapshot {
+                center,
+                player,
+                rank,
+                ..Default::default()
+            };
+            results.push((None, script.tick(snapshot)));
+        }
+        for (enemy, result) in results {
+            match result {
+                Ok(commands) => commands
+                    .into_iter()
+                    .for_each(|c| self.run_command(enemy, c)),
+                Err(err) => self.script_errors.push(err),
+            }
+        }
+        self.stage_scripts.retain(|s| !s.is_finished());
+    }
+
+    /// Carry out what a script asked for, `enemy` is the index of the enemy running it
+    fn run_command(&mut self, enemy: Option<usize>, command: Command) {
+        let center = match enemy {
+            Some(i) => self.enemies[i].center(),
+            None => self.camera.center(),
+        };
+        match command {
+            Command::Spawn { script, center } => {
+                self.spawn_enemy(&script, center);
+            }
+            Command::Pattern(name) => self.spawn_pattern(&name, center),
+            Command::Sound(name) => self.sound_events.push(SoundEvent::at(&name, center)),
+            Command::Shake(amount) => self.camera_effects.push(CameraEffect::Shake(amount)),
+            Command::Fire { angle, speed } => {
+                let angle = angle.to_radians();
+                let velocity = Velocity::new(angle.sin(), -angle.cos()) * speed;
+                self.enemy_bullets
+                    .push(Bullet::new(center, velocity, 1.5, utils::PINK));
+            }
+            command => {
+                let Some(enemy) = enemy.and_then(|i| self.enemies.get_mut(i)) else {
+                    return;
+                };
+                match command {
+                    Command::MoveTo(center) => enemy.update_center(center),
+                    Command::MoveBy(dx, dy) => {
+                        let (x, y) = center.get();
+                        enemy.update_center(CenterPt::new(x + dx, y + dy));
+                    }
+                    Command::SetVelocity(velocity) => enemy.update_velocity(velocity),
+                    Command::SetHp(hp) => enemy.hp = hp,
+                    Command::SetScore(score) => enemy.score = score,
+                    Command::SetDrops(table) => enemy.drops = Some(table),
+                    Command::Vanish => enemy.vanish(),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn update_enemies(&mut self, delta_time: f32) {
+        self.enemies.update(delta_time);
+        for enemy in &mut self.enemies {
+            for bullet in &mut self.player_bullets {
+                if bullet.is_alive() && enemy.hit(bullet.center(), bullet.radius, 1.) {
+                    bullet.kill();
+                }
+            }
+        }
+        let view = self.view();
+        // Enemies can come in from well off screen
+        let margin = Rect::new(view.x - view.w, view.y - view.h, view.w * 3., view.h * 3.);
+        let mut killed = Vec::new();
+        self.enemies.retain(|e| {
+            if e.is_dead() {
+                killed.push((e.center(), e.score, e.drops.clone()));
+                return false;
+            }
+            !e.is_vanished() && margin.contains(e.center().get().into())
+        });
+        for (center, score, drops) in killed {
+            if let Some(explosion) = ExplosionBuilder::burst(6.).build(center) {
+                self.layers.add(
+                    LayerId::Particles,
+                    0,
+                    LayerItem::Particle(Box::new(explosion)),
+                );
+            }
+            self.sound_events.push(SoundEvent::at("explosion", center));
+            if let Some(player) = &mut self.player {
+                player.add_score(score);
+            }
+            if let Some(table) = drops {
+                self.drop_pickups(&table, center);
+            }
+        }
+    }
+
+    fn update_patterns(&mut self, delta_time: f32) {
+        let view = self.view();
+        let margin = Rect::new(view.x - 8., view.y - 8., view.w + 16., view.h + 16.);
+        let target = self.player.as_ref().map(|p| p.center());
+        let rank = self.rank.fraction();
+        for pattern in &mut self.patterns {
+            pattern.aim_at(target);
+            pattern.set_rank(rank);
+            pattern.update(delta_time);
+            pattern.retain_in(margin);
+        }
+        self.patterns.retain(|p| !p.is_finished());
+    }
+
+    fn update_pickups(&mut self, delta_time: f32) {
+        self.pickups.update(delta_time);
+        // A wrecked ship picks nothing up, pickups keep falling past it
+        let player = self
+            .player
+            .as_mut()
+            .filter(|p| matches!(p.life(), Life::Alive | Life::FlyingIn(_)));
+        if let Some(player) = player {
+            for pickup in &mut self.pickups {
+                if !pickup.attract(player.center(), player.radius, delta_time) {
+                    continue;
+                }
+                match pickup.def.effect {
+                    PickupEffect::Power(amount) => {
+                        player.weapon.add_power(amount);
+                    }
+                    PickupEffect::Score(points) => player.add_score(points),
+                    PickupEffect::Bomb => player.bombs += 1,
+                    PickupEffect::Life => player.lives += 1,
+                }
+                if let Some(sound) = &pickup.def.sound {
+                    self.sound_events
+                        .push(SoundEvent::at(sound, pickup.center()));
+                }
+            }
+        }
+        self.pickups.retain(|p| p.is_alive());
+    }
+
+    /// What the world draws on `id` besides the layer items
+    fn draw_entities(&self, id: LayerId, gizmos: bool) {
+        let draw = |d: &dyn Drawable| {
+            if gizmos {
+                d.draw_gizmos()
+            } else {
+                d.draw()
+            }
+        };
+        match id {
+            LayerId::Player => {
+                self.pickups.iter().for_each(|p| draw(p));
+                if let Some(player) = &self.player {
+                    draw(player);
+                }
+            }
+            LayerId::Enemies => {
+                self.enemies.iter().for_each(|e| draw(e));
+                if let Some(boss) = &self.boss {
+                    draw(boss);
+                }
+            }
+            LayerId::Bullets => {
+                self.player_bullets.iter().for_each(|b| draw(b));
+                self.enemy_bullets.iter().for_each(|b| draw(b));
+                self.patterns.iter().for_each(|p| draw(p));
+            }
+            LayerId::Hud if !gizmos => {
+                if let Some(boss) = self.boss.as_ref().filter(|b| !b.is_dying()) {
+                    boss::draw_health_bar(boss, self.view().w);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 impl Drawable for World {
     fn draw(&self) {
-        self.graphics.iter().for_each(|g| g.draw());
-        self.particles.iter().for_each(|p| p.draw());
+        self.layers
+            .draw_with(self.view(), |id| self.draw_entities(id, false));
     }
 
     fn draw_gizmos(&self) {
-        self.graphics.iter().for_each(|g| g.draw_gizmos());
-        self.particles.iter().for_each(|p| p.draw_gizmos());
+        self.layers
+            .draw_gizmos_with(self.view(), |id| self.draw_entities(id, true));
         self.gizmos.iter().for_each(|p| p.draw_gizmos());
     }
 }
 
 impl Updateable for World {
     fn update(&mut self, delta_time: f32) {
-        self.particles.iter_mut().for_each(|p| p.update(delta_time))
-        // TODO: Remove dead particles...
+        self.camera.update(delta_time);
+        let view = self.view();
+        let entered = self
+            .layers
+            .tilemaps_mut()
+            .flat_map(|t| t.entered_view(view));
+        self.map_objects.extend(entered);
+        self.layers.update(delta_time);
+        self.update_player(delta_time);
+        self.update_boss(delta_time);
+        self.update_scripts(delta_time);
+        self.update_enemies(delta_time);
+        self.update_enemy_bullets(delta_time);
+        self.update_patterns(delta_time);
+        self.update_player_hits();
+        self.update_pickups(delta_time);
         // TODO: Remove dead gizmos...
         // TODO: gizmos might need updating too...
         // self.gizmos.iter_mut()